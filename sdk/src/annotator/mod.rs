@@ -1,2 +1,4 @@
 mod stronghold_sign_provider;
+mod vault_sign_provider;
 pub use stronghold_sign_provider::*;
+pub use vault_sign_provider::*;
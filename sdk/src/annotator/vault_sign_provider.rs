@@ -0,0 +1,342 @@
+use std::sync::Arc;
+
+use alvarium_annotator::SignProvider;
+use alvarium_sdk_rust::errors::Error;
+use base64::Engine;
+use iota_sdk::crypto::signatures::ed25519::{PublicKey, Signature};
+use tokio::sync::{RwLock, RwLockReadGuard};
+use vaultrs::{
+    api::transit::{KeyType, requests::CreateKeyRequest, responses::ReadKeyData},
+    client::VaultClient as Client,
+};
+
+use super::stronghold_sign_provider::get_signature;
+use crate::{
+    errors::{IdentityError, IdentityResult},
+    identity_demia::{
+        core::BaseEncoding,
+        verification::{MethodData, MethodType, VerificationMethod},
+    },
+    identity_did::{CoreDID, DID},
+    models::VaultClient,
+};
+
+/// Management of named Ed25519 signing keys that never leave the backend holding them
+#[async_trait::async_trait]
+pub trait KeyManager: Send + Sync {
+    /// Creates the key, does nothing if it already exists
+    async fn create_key(&self, name: &str) -> IdentityResult<()>;
+    /// Adds a new key version, older versions stay available for verification
+    async fn rotate_key(&self, name: &str) -> IdentityResult<()>;
+    /// Public keys of every version, oldest first
+    async fn public_keys(&self, name: &str) -> IdentityResult<Vec<PublicKey>>;
+    /// Signs with the latest key version
+    async fn sign(&self, name: &str, content: &[u8]) -> IdentityResult<Signature>;
+
+    async fn public_key(&self, name: &str) -> IdentityResult<PublicKey> {
+        self.public_keys(name)
+            .await?
+            .pop()
+            .ok_or_else(|| IdentityError::VaultError(format!("Key {} has no versions", name)))
+    }
+
+    /// Verifies against every key version, so signatures made before a rotation stay valid
+    async fn verify(&self, name: &str, content: &[u8], signature: &Signature) -> IdentityResult<bool> {
+        Ok(self
+            .public_keys(name)
+            .await?
+            .iter()
+            .any(|key| key.verify(signature, content)))
+    }
+}
+
+/// Ed25519 keys in a HashiCorp Vault transit engine, using the auth of an existing [`VaultClient`]
+#[derive(Debug, Clone)]
+pub struct VaultTransit {
+    vault: Arc<RwLock<VaultClient>>,
+    mount: String,
+}
+
+impl VaultTransit {
+    pub async fn new(vault: Arc<RwLock<VaultClient>>) -> Self {
+        let mount = vault.read().await.vault_config().transit_mount.clone();
+        Self { vault, mount }
+    }
+
+    /// Shares the vault client between requests. It is only locked exclusively when the lease expired and it has to
+    /// log in again
+    async fn client(&self) -> IdentityResult<RwLockReadGuard<'_, VaultClient>> {
+        {
+            let vault = self.vault.read().await;
+            if vault.client().is_some() {
+                return Ok(vault);
+            }
+        }
+        self.vault.write().await.authenticated().await?;
+        Ok(self.vault.read().await)
+    }
+}
+
+fn authenticated(vault: &VaultClient) -> IdentityResult<&Client> {
+    vault
+        .client()
+        .ok_or_else(|| IdentityError::VaultError("Vault lease expired".to_string()))
+}
+
+#[async_trait::async_trait]
+impl KeyManager for VaultTransit {
+    async fn create_key(&self, name: &str) -> IdentityResult<()> {
+        let vault = self.client().await?;
+        vaultrs::transit::key::create(
+            authenticated(&vault)?,
+            &self.mount,
+            name,
+            Some(CreateKeyRequest::builder().key_type(KeyType::Ed25519)),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn rotate_key(&self, name: &str) -> IdentityResult<()> {
+        let vault = self.client().await?;
+        vaultrs::transit::key::rotate(authenticated(&vault)?, &self.mount, name).await?;
+        Ok(())
+    }
+
+    async fn public_keys(&self, name: &str) -> IdentityResult<Vec<PublicKey>> {
+        let key = {
+            let vault = self.client().await?;
+            vaultrs::transit::key::read(authenticated(&vault)?, &self.mount, name).await?
+        };
+
+        let mut entries = match key.keys {
+            ReadKeyData::Asymmetric(entries) => entries.into_iter().collect::<Vec<_>>(),
+            ReadKeyData::Symmetric(_) => {
+                return Err(IdentityError::VaultError(format!("Key {} is not an ed25519 key", name)));
+            }
+        };
+        entries.sort_by_key(|(version, _)| version.parse::<u64>().unwrap_or_default());
+
+        entries
+            .into_iter()
+            .map(|(_, entry)| {
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(entry.public_key)
+                    .map_err(|e| IdentityError::VaultError(e.to_string()))?;
+                let bytes = <[u8; PublicKey::LENGTH]>::try_from(bytes.as_slice())
+                    .map_err(|_| IdentityError::VaultError(format!("Invalid public key length {}", bytes.len())))?;
+                PublicKey::try_from_bytes(bytes).map_err(|e| IdentityError::VaultError(e.to_string()))
+            })
+            .collect()
+    }
+
+    async fn sign(&self, name: &str, content: &[u8]) -> IdentityResult<Signature> {
+        let input = base64::engine::general_purpose::STANDARD.encode(content);
+        let response = {
+            let vault = self.client().await?;
+            vaultrs::transit::data::sign(authenticated(&vault)?, &self.mount, name, &input, None).await?
+        };
+
+        // Signatures come back as vault:v<version>:<base64>
+        let encoded = response
+            .signature
+            .rsplit(':')
+            .next()
+            .ok_or_else(|| IdentityError::VaultError("Malformed transit signature".to_string()))?;
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| IdentityError::VaultError(e.to_string()))?;
+        let bytes = <[u8; Signature::LENGTH]>::try_from(bytes.as_slice())
+            .map_err(|_| IdentityError::VaultError(format!("Invalid signature length {}", bytes.len())))?;
+        Ok(Signature::from_bytes(bytes))
+    }
+}
+
+/// Signs annotations with a Vault transit key instead of a key held in Stronghold.
+/// Signatures are hex encoded like those of the [`super::StrongholdProvider`].
+pub struct VaultTransitProvider<K: KeyManager = VaultTransit> {
+    keys: K,
+    key_name: String,
+}
+
+impl<K: KeyManager> VaultTransitProvider<K> {
+    pub fn new(keys: K, key_name: &str) -> Self {
+        Self {
+            keys,
+            key_name: key_name.to_string(),
+        }
+    }
+
+    pub fn key_name(&self) -> &str {
+        &self.key_name
+    }
+
+    /// Verification method holding the latest key version, to be inserted into the DID document of `did` so that
+    /// operations signed with [`VaultTransitProvider::sign_did_operation`] verify against it
+    pub async fn verification_method(&self, did: &CoreDID, fragment: &str) -> IdentityResult<VerificationMethod> {
+        let public_key = self.keys.public_key(&self.key_name).await?;
+        Ok(VerificationMethod::builder(Default::default())
+            .id(did.to_url().join(format!("#{}", fragment))?)
+            .controller(did.clone())
+            .type_(MethodType::ED25519_VERIFICATION_KEY_2018)
+            .data(MethodData::PublicKeyMultibase(BaseEncoding::encode_multibase(
+                &public_key,
+                None,
+            )))
+            .build()?)
+    }
+
+    /// Signs a DID operation, such as a document update or a credential, as `method`. Fails when the method doesn't
+    /// hold the latest key version, since the signature would not verify against the published document
+    pub async fn sign_did_operation(&self, method: &VerificationMethod, content: &[u8]) -> IdentityResult<Signature> {
+        let public_key = self.keys.public_key(&self.key_name).await?;
+        if method_key(method)? != public_key.to_bytes() {
+            return Err(IdentityError::VaultError(format!(
+                "Method {} does not hold the latest version of key {}",
+                method.id(),
+                self.key_name
+            )));
+        }
+        self.keys.sign(&self.key_name, content).await
+    }
+}
+
+/// Verifies a DID operation against the key published in `method`
+pub fn verify_did_operation(
+    method: &VerificationMethod,
+    content: &[u8],
+    signature: &Signature,
+) -> IdentityResult<bool> {
+    let bytes = <[u8; PublicKey::LENGTH]>::try_from(method_key(method)?.as_slice())
+        .map_err(|_| IdentityError::VaultError(format!("Method {} is not an ed25519 key", method.id())))?;
+    let public_key = PublicKey::try_from_bytes(bytes).map_err(|e| IdentityError::VaultError(e.to_string()))?;
+    Ok(public_key.verify(signature, content))
+}
+
+fn method_key(method: &VerificationMethod) -> IdentityResult<Vec<u8>> {
+    method
+        .data()
+        .try_decode()
+        .map_err(|e| IdentityError::VaultError(e.to_string()))
+}
+
+#[async_trait::async_trait]
+impl<K: KeyManager> SignProvider for VaultTransitProvider<K> {
+    type Error = Error;
+
+    async fn sign(&self, content: &[u8]) -> Result<String, Self::Error> {
+        let signature = self
+            .keys
+            .sign(&self.key_name, content)
+            .await
+            .map_err(|e| Self::Error::External(Box::new(e)))?;
+        Ok(hex::encode(signature.to_bytes()))
+    }
+
+    async fn verify(&self, content: &[u8], signed: &[u8]) -> Result<bool, Self::Error> {
+        let sig = get_signature(signed)?;
+        self.keys
+            .verify(&self.key_name, content, &sig)
+            .await
+            .map_err(|e| Self::Error::External(Box::new(e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use iota_sdk::crypto::signatures::ed25519::SecretKey;
+
+    use super::*;
+
+    /// Keeps key versions in memory, like the transit engine does on the server
+    #[derive(Default)]
+    struct MockKeyManager {
+        keys: Mutex<HashMap<String, Vec<SecretKey>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl KeyManager for MockKeyManager {
+        async fn create_key(&self, name: &str) -> IdentityResult<()> {
+            let mut keys = self.keys.lock().unwrap();
+            if !keys.contains_key(name) {
+                keys.insert(name.to_string(), vec![SecretKey::generate().unwrap()]);
+            }
+            Ok(())
+        }
+
+        async fn rotate_key(&self, name: &str) -> IdentityResult<()> {
+            let mut keys = self.keys.lock().unwrap();
+            let versions = keys
+                .get_mut(name)
+                .ok_or_else(|| IdentityError::VaultError(format!("No key {}", name)))?;
+            versions.push(SecretKey::generate().unwrap());
+            Ok(())
+        }
+
+        async fn public_keys(&self, name: &str) -> IdentityResult<Vec<PublicKey>> {
+            let keys = self.keys.lock().unwrap();
+            let versions = keys
+                .get(name)
+                .ok_or_else(|| IdentityError::VaultError(format!("No key {}", name)))?;
+            Ok(versions.iter().map(SecretKey::public_key).collect())
+        }
+
+        async fn sign(&self, name: &str, content: &[u8]) -> IdentityResult<Signature> {
+            let keys = self.keys.lock().unwrap();
+            let latest = keys
+                .get(name)
+                .and_then(|versions| versions.last())
+                .ok_or_else(|| IdentityError::VaultError(format!("No key {}", name)))?;
+            Ok(latest.sign(content))
+        }
+    }
+
+    async fn provider() -> VaultTransitProvider<MockKeyManager> {
+        let keys = MockKeyManager::default();
+        keys.create_key("annotator").await.unwrap();
+        VaultTransitProvider::new(keys, "annotator")
+    }
+
+    #[tokio::test]
+    async fn test_sign_and_verify() {
+        let provider = provider().await;
+        let signature = provider.sign(b"annotation").await.unwrap();
+        let signed = hex::decode(&signature).unwrap();
+
+        assert!(provider.verify(b"annotation", &signed).await.unwrap());
+        assert!(!provider.verify(b"other annotation", &signed).await.unwrap());
+        assert!(provider.verify(b"annotation", &signed[1..]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_signatures_survive_rotation() {
+        let provider = provider().await;
+        let before = hex::decode(provider.sign(b"annotation").await.unwrap()).unwrap();
+        provider.keys.rotate_key("annotator").await.unwrap();
+        let after = hex::decode(provider.sign(b"annotation").await.unwrap()).unwrap();
+
+        assert_ne!(before, after);
+        assert!(provider.verify(b"annotation", &before).await.unwrap());
+        assert!(provider.verify(b"annotation", &after).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_did_operation() {
+        let provider = provider().await;
+        let did = CoreDID::parse("did:example:transit").unwrap();
+        let method = provider.verification_method(&did, "transit-key").await.unwrap();
+        assert_eq!(method.id().fragment(), Some("transit-key"));
+
+        let signature = provider.sign_did_operation(&method, b"document update").await.unwrap();
+        assert!(verify_did_operation(&method, b"document update", &signature).unwrap());
+        assert!(!verify_did_operation(&method, b"other update", &signature).unwrap());
+
+        // The published method holds the old key until the document is updated
+        provider.keys.rotate_key("annotator").await.unwrap();
+        assert!(provider.sign_did_operation(&method, b"document update").await.is_err());
+        let method = provider.verification_method(&did, "transit-key").await.unwrap();
+        assert!(provider.sign_did_operation(&method, b"document update").await.is_ok());
+    }
+}
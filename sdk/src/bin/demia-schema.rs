@@ -0,0 +1,75 @@
+//! Writes the schema bundle and OpenAPI document of the SDK models, or checks two bundles for breaking changes.
+//!
+//! ```text
+//! demia-schema export [dir]
+//! demia-schema check <old bundle> <new bundle>
+//! ```
+
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use demia_sdk::schema::{self, SchemaBundle};
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["export"] => export(PathBuf::from(".")),
+        ["export", dir] => export(PathBuf::from(dir)),
+        ["check", old, new] => check(old, new),
+        _ => Err("Usage: demia-schema export [dir] | demia-schema check <old bundle> <new bundle>".to_string()),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn export(dir: PathBuf) -> Result<ExitCode, String> {
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let bundle = schema::bundle();
+    let bundle_path = dir.join(bundle.file_name());
+    write_json(&bundle_path, &bundle)?;
+
+    let openapi_path = dir.join(format!("demia-openapi-{}.json", bundle.version));
+    write_json(&openapi_path, &schema::openapi())?;
+
+    println!("Wrote {} and {}", bundle_path.display(), openapi_path.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn check(old: &str, new: &str) -> Result<ExitCode, String> {
+    let report = schema::check_compatibility(&read_bundle(old)?, &read_bundle(new)?);
+    for change in &report.changes {
+        println!("{:?}\t{}\t{}", change.kind, change.path, change.description);
+    }
+
+    match report.is_compatible() {
+        true => {
+            println!("{} is compatible with {}", report.to, report.from);
+            Ok(ExitCode::SUCCESS)
+        }
+        false => {
+            println!(
+                "{} has {} breaking changes from {}",
+                report.to,
+                report.breaking().count(),
+                report.from
+            );
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+fn read_bundle(path: &str) -> Result<SchemaBundle, String> {
+    let json = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_slice(&json).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn write_json(path: &PathBuf, value: &impl serde::Serialize) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))
+}
@@ -1,14 +1,19 @@
 use std::fmt::Debug;
 
-use base64::Engine;
-use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use jsonwebtoken::{Algorithm, Validation};
 use serde_json::{Value, json};
+use url::Url;
 
 use crate::{
-    clients::SecretManager,
+    clients::{
+        SecretManager,
+        jwks::{JwksCache, decode_with_cache},
+        read_token_response,
+    },
     configuration::ApplicationConfiguration,
     errors::{SecretError, SecretResult},
     models::{TokenResponse, TokenType, TokenWrap},
+    utils::{ID_TOKEN_TYPE, TOKEN_EXCHANGE_GRANT},
 };
 
 #[derive(Default, Clone)]
@@ -16,6 +21,9 @@ pub struct Auth0Client {
     client: reqwest::Client,
     url: String,
     session_refresh: Option<String>,
+    jwks: JwksCache,
+    management_audience: Option<String>,
+    api_audience: Option<String>,
 }
 
 impl Auth0Client {
@@ -24,40 +32,47 @@ impl Auth0Client {
             client: reqwest::Client::new(),
             url: config.secrets_api.clone(),
             session_refresh: None,
+            jwks: JwksCache::default(),
+            management_audience: config.auth0_management_audience.clone(),
+            api_audience: config.auth0_api_audience.clone(),
         }
     }
 
     async fn get_token_data(&self, token: &TokenResponse) -> SecretResult<jsonwebtoken::TokenData<Value>> {
-        let jwks_url = format!("{}/.well-known/jwks.json", self.url);
-        let jwks_json: Value = reqwest::get(jwks_url)
-            .await
-            .map_err(|_| SecretError::Jwt("couldn't query jwks".to_string()))?
-            .json()
-            .await
-            .map_err(|_| SecretError::Jwt("couldn't convert jwk response to json".to_string()))?;
-        let jwk = jwks_json["keys"][0]["x5c"][0]
-            .as_str()
-            .ok_or_else(|| SecretError::Jwt("Failed to extract jwk public key".to_string()))?;
-
-        // println!("Jwk: {}", jwk);
-        // The public key is Base64-encoded in the JWKS, so decode it
-        let engine = base64::engine::general_purpose::STANDARD_NO_PAD;
-        let public_key_der = engine
-            .decode(jwk)
-            .map_err(|_| SecretError::Jwt("coudln't decode jwk base64".to_string()))?;
-
-        let public_key_pem = format!(
-            "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----",
-            engine.encode(public_key_der)
-        );
+        self.decode(&token.id_token, TokenType::AUTH0.client_id()).await
+    }
 
-        let decoding_key = DecodingKey::from_rsa_pem(public_key_pem.as_bytes())
-            .map_err(|_| SecretError::Jwt("Failed to turn key into decodingkey".to_string()))?;
+    async fn decode(&self, token: &str, audience: &str) -> SecretResult<jsonwebtoken::TokenData<Value>> {
+        let jwks_url = format!("{}/.well-known/jwks.json", self.url);
         let mut validator = Validation::new(Algorithm::RS256);
-        validator.set_audience(&["KJO1MMQW7ae5aQykrpbNKZnyUJb7dsyZ"]);
+        validator.set_audience(&[audience]);
+
+        decode_with_cache(&self.jwks, &self.client, &jwks_url, token, &validator).await
+    }
+
+    /// Audience of the Auth0 Management API of this tenant, `https://<tenant>.auth0.com/api/v2/` unless configured
+    /// otherwise
+    pub fn management_api(&self) -> String {
+        if let Some(audience) = &self.management_audience {
+            return audience.clone();
+        }
+        match Url::parse(&self.url).ok().as_ref().and_then(Url::host_str) {
+            Some(host) => format!("https://{}/api/v2/", host),
+            None => format!("{}/api/v2/", self.url.trim_end_matches('/')),
+        }
+    }
 
-        jsonwebtoken::decode::<Value>(&token.id_token, &decoding_key, &validator)
-            .map_err(|e| SecretError::Jwt(e.to_string()))
+    /// API audience requested in the client credentials grant
+    fn api_audience(&self, token_type: &TokenType) -> SecretResult<String> {
+        match token_type {
+            TokenType::Auth0Admin => Ok(self.management_api()),
+            _ => self.api_audience.clone().ok_or_else(|| {
+                SecretError::Configuration(format!(
+                    "No auth0_api_audience configured for client credentials of {}",
+                    token_type
+                ))
+            }),
+        }
     }
 
     async fn token_from_response(
@@ -112,14 +127,15 @@ impl SecretManager for Auth0Client {
 
     async fn get_token_with_secret(&mut self, token_type: &TokenType, client_secret: &str) -> SecretResult<TokenWrap> {
         let client_id = token_type.client_id();
-        log::debug!("Refreshing token: {}", client_id);
+        log::debug!("Requesting client credentials token: {}", client_id);
 
         let url = format!("{}/oauth/token", self.url);
+        let audience = self.api_audience(token_type)?;
         let params = json!({
-            "grant_type": "client_secret",
+            "grant_type": "client_credentials",
             "client_id": client_id,
             "client_secret": client_secret,
-            "scope": "openid profile email offline_access"
+            "audience": audience,
         });
 
         let response = self
@@ -129,9 +145,17 @@ impl SecretManager for Auth0Client {
             .send()
             .await
             .map_err(|_| SecretError::Jwt("Failed to receive reponse from Auth0 client".to_string()))?;
+        if !response.status().is_success() {
+            return Err(SecretError::Jwt(response.text().await?));
+        }
 
-        log::debug!("Response: {:?}", response);
-        self.token_from_response(token_type.clone(), response).await
+        // Client credentials only issue an access token for the API, no id or refresh token
+        let token: TokenResponse = response
+            .json()
+            .await
+            .map_err(|_| SecretError::Jwt("Should be a token response".to_string()))?;
+        let token_data = self.decode(&token.access_token, &audience).await?;
+        Ok(TokenWrap::new(token_type.clone(), token_data, token.access_token))
     }
 
     async fn refresh_token(&mut self) -> SecretResult<TokenWrap> {
@@ -143,7 +167,7 @@ impl SecretManager for Auth0Client {
         });
 
         let response = self.client.post(url).form(&params).send().await?;
-        let token: TokenResponse = response.json().await?;
+        let token = read_token_response(response).await?;
         self.session_refresh.replace(token.refresh_token.clone());
         let token_data = self.get_token_data(&token).await?;
 
@@ -164,4 +188,109 @@ impl SecretManager for Auth0Client {
 
         Ok(TokenWrap::new(token_type.clone(), token_data, token.to_string()))
     }
+
+    async fn exchange_token(&mut self, subject: &TokenWrap, token_type: &TokenType) -> SecretResult<TokenWrap> {
+        let audience = token_type.client_id();
+        log::debug!("Exchanging token for: {}", audience);
+
+        let url = format!("{}/oauth/token", self.url);
+        let params = json!({
+            "grant_type": TOKEN_EXCHANGE_GRANT,
+            "client_id": TokenType::AUTH0.client_id(),
+            "subject_token": subject.raw(),
+            "subject_token_type": ID_TOKEN_TYPE,
+            "audience": audience,
+            "scope": "openid profile email"
+        });
+
+        let response = self
+            .client
+            .post(url)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|_| SecretError::Jwt("Failed to receive reponse from Auth0 client".to_string()))?;
+        if !response.status().is_success() {
+            return Err(SecretError::TokenExchange(response.text().await?));
+        }
+
+        // The exchanged token belongs to the same session, so the session refresh token is kept
+        let token: TokenResponse = response
+            .json()
+            .await
+            .map_err(|_| SecretError::Jwt("Should be a token response".to_string()))?;
+        let token_data = self.decode(&token.id_token, audience).await?;
+        Ok(TokenWrap::new(token_type.clone(), token_data, token.id_token))
+    }
+
+    fn session_refresh(&self) -> Option<String> {
+        self.session_refresh.clone()
+    }
+
+    fn set_session_refresh(&mut self, refresh_token: String) {
+        self.session_refresh.replace(refresh_token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(secrets_api: &str) -> Auth0Client {
+        Auth0Client::new(&ApplicationConfiguration {
+            secrets_api: secrets_api.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_management_audience() {
+        assert_eq!(
+            client("https://demia.eu.auth0.com").management_api(),
+            "https://demia.eu.auth0.com/api/v2/"
+        );
+        assert_eq!(
+            client("https://demia.eu.auth0.com/").management_api(),
+            "https://demia.eu.auth0.com/api/v2/"
+        );
+
+        // A custom domain can't be mapped to the tenant
+        let custom = Auth0Client::new(&ApplicationConfiguration {
+            secrets_api: "https://login.demia.net".to_string(),
+            auth0_management_audience: Some("https://demia.eu.auth0.com/api/v2/".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(custom.management_api(), "https://demia.eu.auth0.com/api/v2/");
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn test_client_credentials_audience() {
+        use crate::test_support::{MockOidcConfig, MockOidcIssuer};
+
+        let issuer = MockOidcIssuer::start(MockOidcConfig::default()).await.unwrap();
+        let mut auth0 = Auth0Client::new(&ApplicationConfiguration {
+            auth0_management_audience: Some("https://demia.eu.auth0.com/api/v2/".to_string()),
+            auth0_api_audience: Some("https://api.demia.test".to_string()),
+            ..issuer.application_config()
+        });
+
+        let admin = auth0
+            .get_token_with_secret(&TokenType::Auth0Admin, "secret")
+            .await
+            .unwrap();
+        assert_eq!(
+            admin.token_data().claims["aud"],
+            json!(["https://demia.eu.auth0.com/api/v2/"])
+        );
+        let api = auth0.get_token_with_secret(&TokenType::AUTH0, "secret").await.unwrap();
+        assert_eq!(api.token_data().claims["aud"], json!(["https://api.demia.test"]));
+
+        // The client id is not an API identifier
+        let mut unconfigured = issuer.auth0();
+        assert!(matches!(
+            unconfigured.get_token_with_secret(&TokenType::AUTH0, "secret").await,
+            Err(SecretError::Configuration(_))
+        ));
+    }
 }
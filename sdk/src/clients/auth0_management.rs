@@ -0,0 +1,277 @@
+use std::fmt::Debug;
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value, json};
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::{
+    clients::{Auth0Client, HttpClient, SecretManager, TokenManager},
+    configuration::ApplicationConfiguration,
+    errors::{ApiError, ApiResult},
+    models::{AUTH0_DID_METADATA, Auth0Role, Auth0User, NewAuth0User, TokenType, UserIdentity},
+    utils::API_TIMEOUT,
+};
+
+/// Client for the Auth0 Management API of the tenant in `ApplicationConfiguration::secrets_api`.
+///
+/// Authenticates as the [`TokenType::Auth0Admin`] application through the client credentials grant. User ids are
+/// the full Auth0 ids including the connection prefix, e.g. `auth0|...`, not `TokenWrap::get_sub`.
+pub struct Auth0Management {
+    api_url: Url,
+    http_client: HttpClient,
+    token_manager: Mutex<TokenManager>,
+    client_secret: String,
+}
+
+impl Debug for Auth0Management {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Auth0Management")
+            .field("api_url", &self.api_url.as_str())
+            .finish()
+    }
+}
+
+impl Auth0Management {
+    pub fn new(config: &ApplicationConfiguration, client_secret: &str) -> ApiResult<Self> {
+        let auth0 = Auth0Client::new(config);
+        Ok(Self {
+            api_url: Url::parse(&auth0.management_api())?,
+            http_client: HttpClient::new("demia".to_string()),
+            token_manager: Mutex::new(TokenManager::new(Box::new(auth0))),
+            client_secret: client_secret.to_string(),
+        })
+    }
+
+    async fn bearer(&self) -> ApiResult<String> {
+        let token = self
+            .token_manager
+            .lock()
+            .await
+            .get_token_with_secret(&TokenType::Auth0Admin, &self.client_secret)
+            .await?;
+        Ok(token.raw().to_string())
+    }
+
+    fn url(&self, segments: &[&str]) -> ApiResult<Url> {
+        let mut url = self.api_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| ApiError::NotFound(self.api_url.to_string()))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: Url) -> ApiResult<T> {
+        let bearer = self.bearer().await?;
+        self.http_client.get(url, &bearer, API_TIMEOUT).await?.into_json().await
+    }
+
+    pub async fn get_user(&self, user_id: &str) -> ApiResult<Auth0User> {
+        self.get(self.url(&["users", user_id])?).await
+    }
+
+    pub async fn find_users_by_email(&self, email: &str) -> ApiResult<Vec<Auth0User>> {
+        let mut url = self.url(&["users-by-email"])?;
+        url.query_pairs_mut().append_pair("email", email);
+        self.get(url).await
+    }
+
+    pub async fn create_user(&self, user: &NewAuth0User) -> ApiResult<Auth0User> {
+        let bearer = self.bearer().await?;
+        self.http_client
+            .post_json(self.url(&["users"])?, &bearer, API_TIMEOUT, serde_json::to_value(user)?)
+            .await?
+            .into_json()
+            .await
+    }
+
+    /// Patches the user, top level fields are replaced
+    pub async fn update_user(&self, user_id: &str, patch: Value) -> ApiResult<Auth0User> {
+        let bearer = self.bearer().await?;
+        self.http_client
+            .patch_json(self.url(&["users", user_id])?, &bearer, API_TIMEOUT, patch)
+            .await?
+            .into_json()
+            .await
+    }
+
+    pub async fn delete_user(&self, user_id: &str) -> ApiResult<()> {
+        let bearer = self.bearer().await?;
+        self.http_client
+            .delete(self.url(&["users", user_id])?, &bearer, API_TIMEOUT, None)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn app_metadata(&self, user_id: &str) -> ApiResult<Map<String, Value>> {
+        Ok(self.get_user(user_id).await?.app_metadata)
+    }
+
+    /// Merges the fields into the user's app_metadata. Fields set to null are removed
+    pub async fn update_app_metadata(&self, user_id: &str, metadata: Map<String, Value>) -> ApiResult<Auth0User> {
+        self.update_user(user_id, json!({ "app_metadata": metadata })).await
+    }
+
+    pub async fn set_did(&self, user_id: &str, did: &str) -> ApiResult<Auth0User> {
+        let metadata = Map::from_iter([(AUTH0_DID_METADATA.to_string(), json!(did))]);
+        self.update_app_metadata(user_id, metadata).await
+    }
+
+    /// Records the DID of a freshly created identity on the user
+    pub async fn set_identity_did(&self, user_id: &str, identity: &UserIdentity) -> ApiResult<Auth0User> {
+        self.set_did(user_id, &identity.doc_id().to_string()).await
+    }
+
+    pub async fn did(&self, user_id: &str) -> ApiResult<Option<String>> {
+        Ok(self.get_user(user_id).await?.did().map(str::to_string))
+    }
+
+    /// Blocked users can no longer log in, existing tokens stay valid until they expire
+    pub async fn block_user(&self, user_id: &str) -> ApiResult<Auth0User> {
+        self.update_user(user_id, json!({ "blocked": true })).await
+    }
+
+    pub async fn unblock_user(&self, user_id: &str) -> ApiResult<Auth0User> {
+        self.update_user(user_id, json!({ "blocked": false })).await
+    }
+
+    pub async fn roles(&self) -> ApiResult<Vec<Auth0Role>> {
+        self.get(self.url(&["roles"])?).await
+    }
+
+    pub async fn role_by_name(&self, name: &str) -> ApiResult<Option<Auth0Role>> {
+        let mut url = self.url(&["roles"])?;
+        url.query_pairs_mut().append_pair("name_filter", name);
+        let roles: Vec<Auth0Role> = self.get(url).await?;
+        Ok(roles.into_iter().find(|role| role.name == name))
+    }
+
+    pub async fn user_roles(&self, user_id: &str) -> ApiResult<Vec<Auth0Role>> {
+        self.get(self.url(&["users", user_id, "roles"])?).await
+    }
+
+    pub async fn assign_roles(&self, user_id: &str, role_ids: &[&str]) -> ApiResult<()> {
+        let bearer = self.bearer().await?;
+        self.http_client
+            .post_json(
+                self.url(&["users", user_id, "roles"])?,
+                &bearer,
+                API_TIMEOUT,
+                json!({ "roles": role_ids }),
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_roles(&self, user_id: &str, role_ids: &[&str]) -> ApiResult<()> {
+        let bearer = self.bearer().await?;
+        self.http_client
+            .delete(
+                self.url(&["users", user_id, "roles"])?,
+                &bearer,
+                API_TIMEOUT,
+                Some(json!({ "roles": role_ids })),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests {
+    use std::sync::Arc;
+
+    use base64::Engine;
+
+    use super::*;
+    use crate::{
+        clients::{HttpRequest, HttpResponse, MockTransport},
+        test_support::{MockOidcConfig, MockOidcIssuer},
+    };
+
+    const MANAGEMENT_API: &str = "https://demia.eu.auth0.com/api/v2/";
+    const USER_PATH: &str = "/api/v2/users/auth0%7Calice";
+
+    /// Answers with the user, its fields patched by the request body
+    fn user(request: &HttpRequest) -> HttpResponse {
+        let mut user = json!({ "user_id": "auth0|alice", "app_metadata": { "plan": "pro" } });
+        if let Some(body) = &request.body {
+            let patch: Value = serde_json::from_slice(body).unwrap();
+            for (field, value) in patch.as_object().unwrap() {
+                user[field] = value.clone();
+            }
+        }
+        HttpResponse::json(200, &user)
+    }
+
+    async fn management(issuer: &MockOidcIssuer, transport: Arc<MockTransport>) -> Auth0Management {
+        let config = ApplicationConfiguration {
+            auth0_management_audience: Some(MANAGEMENT_API.to_string()),
+            ..issuer.application_config()
+        };
+        let mut management = Auth0Management::new(&config, "secret").unwrap();
+        management.http_client.set_transport(transport);
+        management
+    }
+
+    fn body(request: &HttpRequest) -> Value {
+        serde_json::from_slice(request.body.as_ref().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_user_endpoints() {
+        let issuer = MockOidcIssuer::start(MockOidcConfig::default()).await.unwrap();
+        let transport = Arc::new(
+            MockTransport::new()
+                .on("GET", USER_PATH, user)
+                .on("PATCH", USER_PATH, user),
+        );
+        let management = management(&issuer, transport.clone()).await;
+
+        let alice = management.get_user("auth0|alice").await.unwrap();
+        assert_eq!(alice.user_id, "auth0|alice");
+        assert_eq!(alice.did(), None);
+
+        let alice = management.set_did("auth0|alice", "did:iota:alice").await.unwrap();
+        assert_eq!(alice.did(), Some("did:iota:alice"));
+        assert!(management.block_user("auth0|alice").await.unwrap().blocked);
+        assert!(!management.unblock_user("auth0|alice").await.unwrap().blocked);
+
+        let requests = transport.requests();
+        let calls: Vec<_> = requests
+            .iter()
+            .map(|request| (request.method.as_str(), request.url.path()))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("GET", USER_PATH),
+                ("PATCH", USER_PATH),
+                ("PATCH", USER_PATH),
+                ("PATCH", USER_PATH)
+            ]
+        );
+        assert!(requests[0].body.is_none());
+        assert_eq!(
+            body(&requests[1]),
+            json!({ "app_metadata": { AUTH0_DID_METADATA: "did:iota:alice" } })
+        );
+        assert_eq!(body(&requests[2]), json!({ "blocked": true }));
+        assert_eq!(body(&requests[3]), json!({ "blocked": false }));
+
+        // Every call carries the management token, fetched once through the client credentials grant
+        let bearer = requests[0].headers["authorization"].strip_prefix("Bearer ").unwrap();
+        assert!(
+            requests
+                .iter()
+                .all(|request| request.headers["authorization"] == requests[0].headers["authorization"])
+        );
+        let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(bearer.split('.').nth(1).unwrap())
+            .unwrap();
+        let claims: Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(claims["aud"], json!([MANAGEMENT_API]));
+        assert_eq!(issuer.grant_count("client_credentials"), 1);
+    }
+}
@@ -129,7 +129,7 @@ impl Storage for AwsClient {
             .s3_client
             .get_object()
             .bucket(info.bucket.to_string())
-            .key(info.url);
+            .key(&info.url);
 
         if let Some(time) = last_modified {
             let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(time.timestamp() as u64);
@@ -147,13 +147,11 @@ impl Storage for AwsClient {
 
                 Ok(data)
             }
-            Err(e) => {
-                if e.raw_response().map(|r| r.status()) == Some(StatusCode::NOT_MODIFIED.into()) {
-                    Err(StorageError::NotModified)
-                } else {
-                    Err(e.into())
-                }
-            }
+            Err(e) => match e.raw_response().map(|r| r.status().as_u16()) {
+                Some(status) if status == StatusCode::NOT_MODIFIED.as_u16() => Err(StorageError::NotModified),
+                Some(status) if status == StatusCode::NOT_FOUND.as_u16() => Err(StorageError::NotFound(info.url)),
+                _ => Err(e.into()),
+            },
         }
     }
 
@@ -2,10 +2,10 @@ use std::{collections::HashMap, fmt::Debug};
 
 use chrono::{DateTime, Utc};
 use log::{debug, info, warn};
-use rusoto_core::{Region, credential::StaticProvider};
+use rusoto_core::{Region, RusotoError, credential::StaticProvider};
 use rusoto_s3::{
-    CopyObjectRequest, DeleteObjectRequest, GetObjectRequest, HeadObjectRequest, ListObjectsV2Request, Object,
-    PutObjectRequest, S3, S3Client,
+    CopyObjectRequest, DeleteObjectRequest, GetObjectError, GetObjectRequest, HeadObjectRequest, ListObjectsV2Request,
+    Object, PutObjectRequest, S3, S3Client,
 };
 use rusoto_sts::{AssumeRoleWithWebIdentityRequest, Credentials, Sts, StsClient};
 use tokio::io::AsyncReadExt;
@@ -140,10 +140,9 @@ impl Storage for AwsRusotoClient {
 
                 Ok(data)
             }
-            Err(e) => {
-                // TODO: Check if unmodified was returned
-                Err(e.into())
-            }
+            // TODO: Check if unmodified was returned
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => Err(StorageError::NotFound(info.url)),
+            Err(e) => Err(e.into()),
         }
     }
 
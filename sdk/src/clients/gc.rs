@@ -75,13 +75,18 @@ impl Storage for GoogleCloud {
             .download_object(
                 &GetObjectRequest {
                     bucket: data.bucket.to_string(),
-                    object: data.url,
+                    object: data.url.clone(),
                     ..Default::default()
                 },
                 &Range::default(),
             )
             .await
-            .map_err(Into::into)
+            .map_err(|e| match &e {
+                google_cloud_storage::http::Error::Response(response) if response.code == 404 => {
+                    StorageError::NotFound(data.url.clone())
+                }
+                _ => e.into(),
+            })
     }
 
     async fn update_credentials(&mut self, _token: TokenWrap) -> StorageResult<()> {
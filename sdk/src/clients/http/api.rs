@@ -1,14 +1,16 @@
-use std::{fmt::Debug, time::Duration};
+use std::{sync::Arc, time::Duration};
 
 use iota_sdk::types::block::address::Address;
-use serde::{Serialize, de::DeserializeOwned};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 use url::Url;
 
-use super::{GuardianApiClient, retriever::RetrieverApi};
+use super::{GuardianApiClient, HttpTransport, RetryPolicy, retriever::RetrieverApi};
 use crate::{
     clients::{HttpClient, query_tuples_to_query_string},
     configuration::ApplicationConfiguration,
     errors::{ApiError, ApiResult},
+    models::{Equipment, NewSite, Notification, Permission, Sensor, Sensors, Site, TokenWrap},
     utils::{
         API_TIMEOUT,
         constants::{GUARDIAN_API, LOCAL_API, RETRIEVER_API},
@@ -75,6 +77,20 @@ impl ApiClient {
         API_TIMEOUT
     }
 
+    /// Applies the retry policy to the cloud api, retriever and guardian clients
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.http_client.set_retry_policy(policy.clone());
+        self.retriever.set_retry_policy(policy.clone());
+        self.guardian.set_retry_policy(policy);
+    }
+
+    /// Sends the requests of the cloud api, retriever and guardian clients through the transport
+    pub fn set_transport(&mut self, transport: Arc<dyn HttpTransport>) {
+        self.http_client.set_transport(transport.clone());
+        self.retriever.set_transport(transport.clone());
+        self.guardian.set_transport(transport);
+    }
+
     pub fn retriever(&self) -> &RetrieverApi {
         &self.retriever
     }
@@ -83,6 +99,17 @@ impl ApiClient {
         &self.guardian
     }
 
+    /// Appends the path segments to the cloud api url, keeping any path it is served under
+    fn endpoint(&self, segments: &[&str], query: Option<&str>) -> ApiResult<Url> {
+        let mut url = self.cloud_api_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| ApiError::NotFound(self.cloud_api_url.to_string()))?
+            .pop_if_empty()
+            .extend(segments);
+        url.set_query(query);
+        Ok(url)
+    }
+
     pub async fn request_balance(
         &self,
         bearer: &str,
@@ -91,70 +118,280 @@ impl ApiClient {
         node: &str,
     ) -> ApiResult<String> {
         let addr = address.as_ed25519().to_string();
-        let path = "/v1/balance";
         let query = query_tuples_to_query_string([
             Some(("address", addr)),
             Some(("faucetUrl", faucet.to_string())),
             Some(("nodeUrl", node.to_string())),
         ]);
+        let url = self.endpoint(&["v1", "balance"], query.as_deref())?;
 
-        let mut url = self.cloud_api_url.clone();
+        let res = self.http_client.get_bytes(url, bearer, Self::get_timeout()).await?;
+        res.into_text().await
+    }
 
-        // Account for existing path in uri
-        let mut full_path = url.path().to_string();
-        if let Some(stripped) = full_path.strip_suffix("/") {
-            full_path = stripped.to_string();
-        }
-        full_path.push_str(path);
+    pub async fn sites(&self, bearer: &str) -> ApiResult<Vec<Site>> {
+        self.get_request(bearer, &["v1", "sites"], None).await
+    }
+
+    pub async fn site(&self, bearer: &str, site_id: &str) -> ApiResult<Site> {
+        self.get_request(bearer, &["v1", "sites", site_id], None).await
+    }
 
-        url.set_path(&full_path);
-        url.set_query(query.as_deref());
+    /// Creates the site with its sensors, returning it as stored
+    pub async fn create_site(&self, bearer: &str, site: &NewSite) -> ApiResult<Site> {
+        self.post_request(bearer, &["v1", "sites"], None, serde_json::to_value(site)?)
+            .await
+    }
 
-        // TODO: Add bearer
+    /// Site, sensor and equipment changes need a token granting [`Permission::SiteAdmin`] over the site, they are not
+    /// sent otherwise
+    pub async fn update_site(&self, token: &TokenWrap, site: &Site) -> ApiResult<Site> {
+        token.require(&Permission::SiteAdmin(site.project_id.clone()))?;
+        let json = serde_json::to_value(site)?;
+        self.put_request(token.raw(), &["v1", "sites", &site.project_id], json)
+            .await
+    }
 
-        let res = self.http_client.get_bytes(url, bearer, Self::get_timeout()).await?;
-        print!("code: {}", &res.status());
-        res.into_text().await
+    pub async fn delete_site(&self, token: &TokenWrap, site_id: &str) -> ApiResult<()> {
+        token.require(&Permission::SiteAdmin(site_id.to_string()))?;
+        self.delete_request(token.raw(), &["v1", "sites", site_id]).await
+    }
+
+    pub async fn sensors(&self, bearer: &str, site_id: &str) -> ApiResult<Sensors> {
+        self.get_request(bearer, &["v1", "sites", site_id, "sensors"], None)
+            .await
+    }
+
+    pub async fn sensor(&self, bearer: &str, site_id: &str, sensor_id: &str) -> ApiResult<Sensor> {
+        self.get_request(bearer, &["v1", "sites", site_id, "sensors", sensor_id], None)
+            .await
+    }
+
+    pub async fn create_sensor(&self, token: &TokenWrap, site_id: &str, sensor: &Sensor) -> ApiResult<Sensor> {
+        token.require(&Permission::SiteAdmin(site_id.to_string()))?;
+        let json = serde_json::to_value(sensor)?;
+        self.post_request(token.raw(), &["v1", "sites", site_id, "sensors"], None, json)
+            .await
+    }
+
+    pub async fn update_sensor(&self, token: &TokenWrap, site_id: &str, sensor: &Sensor) -> ApiResult<Sensor> {
+        token.require(&Permission::SiteAdmin(site_id.to_string()))?;
+        let json = serde_json::to_value(sensor)?;
+        self.put_request(token.raw(), &["v1", "sites", site_id, "sensors", &sensor.id], json)
+            .await
+    }
+
+    pub async fn delete_sensor(&self, token: &TokenWrap, site_id: &str, sensor_id: &str) -> ApiResult<()> {
+        token.require(&Permission::SiteAdmin(site_id.to_string()))?;
+        self.delete_request(token.raw(), &["v1", "sites", site_id, "sensors", sensor_id])
+            .await
+    }
+
+    pub async fn equipment(&self, bearer: &str, site_id: &str) -> ApiResult<Vec<Equipment>> {
+        self.get_request(bearer, &["v1", "sites", site_id, "equipment"], None)
+            .await
+    }
+
+    pub async fn equipment_by_id(&self, bearer: &str, site_id: &str, equipment_id: &str) -> ApiResult<Equipment> {
+        self.get_request(bearer, &["v1", "sites", site_id, "equipment", equipment_id], None)
+            .await
+    }
+
+    pub async fn create_equipment(
+        &self,
+        token: &TokenWrap,
+        site_id: &str,
+        equipment: &Equipment,
+    ) -> ApiResult<Equipment> {
+        token.require(&Permission::SiteAdmin(site_id.to_string()))?;
+        let json = serde_json::to_value(equipment)?;
+        self.post_request(token.raw(), &["v1", "sites", site_id, "equipment"], None, json)
+            .await
+    }
+
+    pub async fn update_equipment(
+        &self,
+        token: &TokenWrap,
+        site_id: &str,
+        equipment: &Equipment,
+    ) -> ApiResult<Equipment> {
+        token.require(&Permission::SiteAdmin(site_id.to_string()))?;
+        let json = serde_json::to_value(equipment)?;
+        self.put_request(token.raw(), &["v1", "sites", site_id, "equipment", &equipment.id], json)
+            .await
+    }
+
+    pub async fn delete_equipment(&self, token: &TokenWrap, site_id: &str, equipment_id: &str) -> ApiResult<()> {
+        token.require(&Permission::SiteAdmin(site_id.to_string()))?;
+        self.delete_request(token.raw(), &["v1", "sites", site_id, "equipment", equipment_id])
+            .await
+    }
+
+    /// Notifications of the user, optionally only those of one site
+    pub async fn notifications(&self, bearer: &str, site_id: Option<&str>) -> ApiResult<Vec<Notification>> {
+        let query = query_tuples_to_query_string([site_id.map(|site| ("site", site.to_string()))]);
+        self.get_request(bearer, &["v1", "notifications"], query.as_deref())
+            .await
     }
 
-    #[allow(dead_code)]
-    pub(crate) async fn post_request<T: DeserializeOwned + Debug + Serialize>(
+    pub async fn create_notification(&self, bearer: &str, notification: &Notification) -> ApiResult<Notification> {
+        let json = serde_json::to_value(notification)?;
+        self.post_request(bearer, &["v1", "notifications"], None, json).await
+    }
+
+    pub(crate) async fn post_request<T: DeserializeOwned>(
         &self,
         bearer: &str,
-        path: &str,
+        segments: &[&str],
         query: Option<&str>,
-        json: serde_json::Value,
+        json: Value,
     ) -> ApiResult<T> {
-        let mut url = self.cloud_api_url.clone();
-        url.set_path(path);
-        url.set_query(query);
-
-        // TODO set username & password?
-
-        let res = self.http_client.post_json(url, bearer, Self::get_timeout(), json).await;
-        match res {
-            Ok(r) => r.into_json().await,
-            Err(e) => Err(e),
-        }
+        let url = self.endpoint(segments, query)?;
+        let res = self
+            .http_client
+            .post_json(url, bearer, Self::get_timeout(), json)
+            .await?;
+        res.into_json().await
     }
 
-    #[allow(dead_code)]
-    pub(crate) async fn get_request<T: DeserializeOwned + Debug + Serialize>(
+    pub(crate) async fn get_request<T: DeserializeOwned>(
         &self,
         bearer: &str,
-        path: &str,
+        segments: &[&str],
         query: Option<&str>,
     ) -> ApiResult<T> {
-        let mut url = self.cloud_api_url.clone();
-        url.set_path(path);
-        url.set_query(query);
+        let url = self.endpoint(segments, query)?;
+        let res = self.http_client.get(url, bearer, Self::get_timeout()).await?;
+        res.into_json().await
+    }
 
-        // TODO: Add bearer
+    pub(crate) async fn put_request<T: DeserializeOwned>(
+        &self,
+        bearer: &str,
+        segments: &[&str],
+        json: Value,
+    ) -> ApiResult<T> {
+        let url = self.endpoint(segments, None)?;
+        let res = self
+            .http_client
+            .put_json(url, bearer, Self::get_timeout(), json)
+            .await?;
+        res.into_json().await
+    }
 
-        let res = self.http_client.get_bytes(url, bearer, Self::get_timeout()).await;
-        match res {
-            Ok(r) => r.into_json().await,
-            Err(e) => Err(e),
+    pub(crate) async fn delete_request(&self, bearer: &str, segments: &[&str]) -> ApiResult<()> {
+        let url = self.endpoint(segments, None)?;
+        self.http_client.delete(url, bearer, Self::get_timeout(), None).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use iota_sdk::types::block::address::Ed25519Address;
+
+    use super::*;
+    use crate::{
+        clients::{FixtureTransport, HttpResponse, MockTransport},
+        models::TokenType,
+        utils::API_CLIENT_ID,
+    };
+
+    fn token(roles: &[&str]) -> TokenWrap {
+        let claims = json!({ "sub": "user", "resource_access": { API_CLIENT_ID: { "roles": roles } } });
+        let data = jsonwebtoken::TokenData {
+            header: jsonwebtoken::Header::default(),
+            claims,
+        };
+        TokenWrap::new(TokenType::VAULT, data, "token".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_site_endpoints_keep_base_path() {
+        let site = serde_json::to_value(Site::default()).unwrap();
+        let transport = Arc::new(
+            MockTransport::new()
+                .on_json("GET", "/api/v1/sites/site%201", 200, site.clone())
+                .on_json("POST", "/api/v1/sites", 200, site)
+                .on_json("DELETE", "/api/v1/sites/site%201/sensors/sensor", 200, json!({})),
+        );
+        let mut client = ApiClient::new("http://cloud.test/api/", None, None).unwrap();
+        client.set_transport(transport.clone());
+
+        client.site("token", "site 1").await.unwrap();
+        client.create_site("token", &NewSite::default()).await.unwrap();
+        client
+            .delete_sensor(&token(&["site-admin:site 1"]), "site 1", "sensor")
+            .await
+            .unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].url.path(), "/api/v1/sites/site%201");
+        assert_eq!(requests[1].url.path(), "/api/v1/sites");
+        assert!(
+            requests
+                .iter()
+                .all(|request| request.headers["authorization"] == "Bearer token")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_site_changes_require_site_admin() {
+        let transport = Arc::new(MockTransport::new().on_json("DELETE", "/api/v1/sites/alpha", 200, json!({})));
+        let mut client = ApiClient::new("http://cloud.test/api", None, None).unwrap();
+        client.set_transport(transport.clone());
+
+        let unauthorized: [&[&str]; 3] = [&[], &["site:alpha"], &["site-admin:beta"]];
+        for roles in unauthorized {
+            assert!(matches!(
+                client.delete_site(&token(roles), "alpha").await,
+                Err(ApiError::NotAuthorized(_))
+            ));
         }
+        assert!(transport.requests().is_empty());
+
+        client
+            .delete_site(&token(&["site-admin:alpha"]), "alpha")
+            .await
+            .unwrap();
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_balance_replays() {
+        let dir = std::env::temp_dir().join(format!("demia-balance-{}", uuid::Uuid::new_v4()));
+        let address = Address::Ed25519(Ed25519Address::new([7; 32]));
+        let mock = Arc::new(MockTransport::new().on("GET", "/api/v1/balance", |_| {
+            HttpResponse::new(200, b"1000000".to_vec())
+        }));
+
+        let mut recorder = ApiClient::new("http://cloud.test/api", None, None).unwrap();
+        recorder.set_transport(Arc::new(FixtureTransport::record(&dir, mock.clone())));
+        let recorded = recorder
+            .request_balance("token", &address, "https://faucet.test/api", "https://node.test")
+            .await
+            .unwrap();
+        assert_eq!(recorded, "1000000");
+
+        let request = &mock.requests()[0];
+        let query: std::collections::HashMap<_, _> = request.url.query_pairs().into_owned().collect();
+        assert_eq!(query["address"], address.as_ed25519().to_string());
+        assert_eq!(query["faucetUrl"], "https://faucet.test/api");
+        assert_eq!(query["nodeUrl"], "https://node.test");
+        assert_eq!(request.headers["accept"], "application/vnd.demia.serializer-v2");
+
+        // The replay runs without the mock or any network, against whatever host is configured
+        let mut replay = ApiClient::new("http://127.0.0.1:9/api", None, None).unwrap();
+        replay.set_transport(Arc::new(FixtureTransport::replay(&dir)));
+        let replayed = replay
+            .request_balance("other token", &address, "https://faucet.test/api", "https://node.test")
+            .await
+            .unwrap();
+        assert_eq!(replayed, recorded);
+        assert_eq!(mock.requests().len(), 1);
+        std::fs::remove_dir_all(dir).ok();
     }
 }
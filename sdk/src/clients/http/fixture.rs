@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use iota_sdk::crypto::hashes::{Digest, blake2b::Blake2b256};
+use serde::{Deserialize, Serialize};
+
+use super::transport::{HttpRequest, HttpResponse, HttpTransport, TransportError};
+
+/// A recorded exchange. The authorization header is never stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub method: String,
+    /// Path and query, so fixtures replay against any host
+    pub path: String,
+    pub request_body: Option<String>,
+    pub response: HttpResponse,
+}
+
+#[derive(Debug)]
+enum FixtureMode {
+    Record(Arc<dyn HttpTransport>),
+    Replay,
+}
+
+/// Records responses of another transport to disk, or replays them without any network access.
+///
+/// Each distinct request (method, path, query and body) gets its own json file in the fixture directory holding the
+/// responses in the order they were recorded. Replays return them in the same order, repeating the last one.
+#[derive(Debug)]
+pub struct FixtureTransport {
+    dir: PathBuf,
+    mode: FixtureMode,
+    replayed: Mutex<HashMap<String, usize>>,
+}
+
+impl FixtureTransport {
+    pub fn record(dir: impl Into<PathBuf>, inner: Arc<dyn HttpTransport>) -> Self {
+        Self {
+            dir: dir.into(),
+            mode: FixtureMode::Record(inner),
+            replayed: Default::default(),
+        }
+    }
+
+    pub fn replay(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            mode: FixtureMode::Replay,
+            replayed: Default::default(),
+        }
+    }
+
+    fn key(request: &HttpRequest) -> String {
+        let path = path_and_query(request);
+        let mut hasher = Blake2b256::new();
+        hasher.update(request.method.as_bytes());
+        hasher.update(path.as_bytes());
+        if let Some(body) = &request.body {
+            hasher.update(body);
+        }
+        let hash = hex::encode(&hasher.finalize()[..8]);
+
+        let readable: String = path
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}{}_{}", request.method.to_lowercase(), readable, hash)
+    }
+
+    fn file(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    async fn load(&self, key: &str) -> Result<Vec<Fixture>, TransportError> {
+        match tokio::fs::read(self.file(key)).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| TransportError::Other(e.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(TransportError::Other(e.to_string())),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpTransport for FixtureTransport {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, TransportError> {
+        let key = Self::key(&request);
+        match &self.mode {
+            FixtureMode::Record(inner) => {
+                let response = inner.send(request.clone()).await?;
+                let mut fixtures = self.load(&key).await?;
+                fixtures.push(Fixture {
+                    method: request.method.clone(),
+                    path: path_and_query(&request),
+                    request_body: request
+                        .body
+                        .as_ref()
+                        .map(|body| String::from_utf8_lossy(body).to_string()),
+                    response: response.clone(),
+                });
+
+                let json = serde_json::to_vec_pretty(&fixtures).map_err(|e| TransportError::Other(e.to_string()))?;
+                tokio::fs::create_dir_all(&self.dir)
+                    .await
+                    .map_err(|e| TransportError::Other(e.to_string()))?;
+                tokio::fs::write(self.file(&key), json)
+                    .await
+                    .map_err(|e| TransportError::Other(e.to_string()))?;
+                Ok(response)
+            }
+            FixtureMode::Replay => {
+                let fixtures = self.load(&key).await?;
+                let index = {
+                    let mut replayed = self.replayed.lock().unwrap();
+                    let count = replayed.entry(key.clone()).or_default();
+                    *count += 1;
+                    (*count - 1).min(fixtures.len().saturating_sub(1))
+                };
+                fixtures
+                    .get(index)
+                    .map(|fixture| fixture.response.clone())
+                    .ok_or_else(|| {
+                        TransportError::Other(format!(
+                            "No fixture for {} {} in {}",
+                            request.method,
+                            path_and_query(&request),
+                            self.file(&key).display()
+                        ))
+                    })
+            }
+        }
+    }
+}
+
+type Responder = Box<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+
+/// In-process transport answering from registered handlers and keeping every request for assertions.
+/// Requests without a handler get a 404.
+#[derive(Default)]
+pub struct MockTransport {
+    handlers: Mutex<Vec<(String, String, Responder)>>,
+    requests: Mutex<Vec<HttpRequest>>,
+}
+
+impl std::fmt::Debug for MockTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockTransport")
+            .field("requests", &self.requests.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers requests whose path ends with `path_suffix`. Later handlers take precedence
+    pub fn on(
+        self,
+        method: &str,
+        path_suffix: &str,
+        responder: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    ) -> Self {
+        self.handlers
+            .lock()
+            .unwrap()
+            .push((method.to_uppercase(), path_suffix.to_string(), Box::new(responder)));
+        self
+    }
+
+    /// Always answers with the same json
+    pub fn on_json(self, method: &str, path_suffix: &str, status: u16, json: serde_json::Value) -> Self {
+        self.on(method, path_suffix, move |_| HttpResponse::json(status, &json))
+    }
+
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpTransport for MockTransport {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, TransportError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let handlers = self.handlers.lock().unwrap();
+        let response = handlers
+            .iter()
+            .rev()
+            .find(|(method, suffix, _)| *method == request.method && request.url.path().ends_with(suffix.as_str()))
+            .map(|(_, _, responder)| responder(&request))
+            .unwrap_or_else(|| HttpResponse::new(404, Vec::new()));
+        Ok(response)
+    }
+}
+
+fn path_and_query(request: &HttpRequest) -> String {
+    match request.url.query() {
+        Some(query) => format!("{}?{}", request.url.path(), query),
+        None => request.url.path().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use url::Url;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("demia-fixtures-{}", uuid::Uuid::new_v4()));
+        let mock = Arc::new(
+            MockTransport::new()
+                .on_json("GET", "/v1/balance", 200, json!({ "balance": 1 }))
+                .on_json("GET", "/v1/balance", 200, json!({ "balance": 2 })),
+        );
+        let request = HttpRequest::new(
+            "GET",
+            Url::parse("http://localhost:1111/v1/balance?address=abc").unwrap(),
+            Duration::from_secs(1),
+        )
+        .header("authorization", "Bearer secret");
+
+        let recorder = FixtureTransport::record(&dir, mock.clone());
+        let recorded = recorder.send(request.clone()).await.unwrap();
+        assert_eq!(mock.requests().len(), 1);
+
+        // Replays ignore the host and never store the token
+        let replay = FixtureTransport::replay(&dir);
+        let mut other_host = request.clone();
+        other_host.url = Url::parse("http://127.0.0.1:9/v1/balance?address=abc").unwrap();
+        let replayed = replay.send(other_host).await.unwrap();
+        assert_eq!(replayed.body, recorded.body);
+        assert_eq!(replayed.body, json!({ "balance": 2 }).to_string().into_bytes());
+
+        let stored = std::fs::read_to_string(replay.file(&FixtureTransport::key(&request))).unwrap();
+        assert!(!stored.contains("secret"));
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
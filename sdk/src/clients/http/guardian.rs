@@ -1,17 +1,28 @@
-use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::{Value, json};
 use url::Url;
 
+use super::{
+    GuardianCredentials, GuardianSession, GuardianVerifier, GuardianWorkflow, HttpClient, HttpTransport, RetryPolicy,
+};
 use crate::{
     errors::{ApiError as Error, ApiResult as Result},
-    models::{GuardianAccessTokenResponse, GuardianLoginResponse, GuardianProfileResponse, GuardianReport},
-    utils::GUARDIAN_API,
+    models::{
+        CredentialVerification, GuardianAccessTokenResponse, GuardianBlock, GuardianBlockContent, GuardianBlockData,
+        GuardianBlockId, GuardianErrorResponse, GuardianLoginResponse, GuardianPolicy, GuardianProfileResponse,
+        GuardianReport, GuardianRole, GuardianTrustChain,
+    },
+    telemetry::Call,
+    utils::{API_TIMEOUT, GUARDIAN_API},
 };
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+/// Submits reports to a Guardian policy as one role of the session
+#[derive(Default, Debug, Clone)]
 pub struct GuardianClient {
-    pub client: GuardianApiClient,
-    pub access_token: Option<String>,
+    pub session: GuardianSession,
+    pub role: GuardianRole,
     pub policy_id: String,
     pub send_block: String,
     pub trust_chain_block: String,
@@ -21,7 +32,7 @@ pub struct GuardianClient {
 impl GuardianClient {
     pub fn new(client: GuardianApiClient, policy_id: String, send_block: String, ref_block: String) -> Self {
         GuardianClient {
-            client,
+            session: GuardianSession::new(client),
             policy_id,
             send_block,
             ref_block,
@@ -29,99 +40,159 @@ impl GuardianClient {
         }
     }
 
-    //
-    pub async fn connect(&mut self) -> Result<()> {
-        self.log_in("Installer", "test").await?;
-        self.get_policy().await?;
-        Ok(())
-    }
-
-    pub fn access_token(&self) -> Result<&str> {
-        self.access_token
-            .as_deref()
-            .ok_or_else(|| Error::Guardian("No Access token".to_string()))
-    }
-
-    pub async fn send_report(&mut self, report: GuardianReport, username: &str, password: &str) -> Result<()> {
-        self.log_in(username, password).await?;
-        self.get_policy().await?;
-
-        let ref_blocks = self.get_ref_block().await?;
-        let ref_blocks = ref_blocks
-            .get("data")
-            .ok_or_else(|| Error::Guardian("Json get data".to_string()))?
-            .as_array()
-            .ok_or_else(|| Error::Guardian("Json get data as array".to_string()))?;
-        let ref_block = ref_blocks
-            .iter()
-            .find(|data| {
-                data.get("type")
-                    .and_then(|v| v.as_str())
-                    .map(|str| str == "project_sr")
-                    .unwrap_or(false)
-            })
-            .ok_or_else(|| Error::Guardian("Json get type".to_string()))?;
-
-        let report_block = self.get_report_block_id().await?;
-        let data = json!({
-            "document": report,
-            "ref": ref_block,
-        });
-        log::debug!("data: {:#}", data);
-        let res = self
-            .client
-            .post_block(self.access_token()?, &self.policy_id, &report_block, &data)
-            .await?;
-        log::debug!("Response: {:#?}", res);
-        Ok(())
+    /// Adds the account of the role and acts as it
+    pub fn with_account(mut self, role: GuardianRole, credentials: GuardianCredentials) -> Self {
+        self.session.add_account(role.clone(), credentials);
+        self.role = role;
+        self
+    }
+
+    /// Tag of the policy's report block, which serves trust chains
+    pub fn with_trust_chain_block(mut self, tag: &str) -> Self {
+        self.trust_chain_block = tag.to_string();
+        self
+    }
+
+    pub fn with_role(mut self, role: GuardianRole) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn client(&self) -> &GuardianApiClient {
+        self.session.client()
     }
 
-    async fn log_in(&mut self, username: &str, password: &str) -> Result<()> {
-        let res = self.client.login(username, password).await?;
-        let access_token = self.client.access_token(&res.refresh_token).await?;
-        log::debug!("Login response: {:?}", access_token);
+    /// Logs in and checks that the policy is reachable
+    pub async fn connect(&self) -> Result<GuardianPolicy> {
+        self.get_policy().await
+    }
+
+    pub async fn access_token(&self) -> Result<String> {
+        self.session.access_token(&self.role).await
+    }
 
-        self.access_token = Some(access_token.access_token);
-        log::debug!("Access token: {:?}", self.access_token);
-        Ok(())
+    /// Runs the [`GuardianWorkflow::monitoring_report`] flow with the report
+    pub async fn send_report(&self, report: GuardianReport) -> Result<Value> {
+        let workflow = GuardianWorkflow::monitoring_report(
+            self.session.clone(),
+            self.role.clone(),
+            &self.policy_id,
+            &self.send_block,
+        );
+        let inputs = HashMap::from([("input".to_string(), serde_json::to_value(report)?)]);
+        let context = workflow.run(inputs).await?;
+        log::debug!("Response: {:#?}", context.get("response"));
+        Ok(context.get("response")?.clone())
     }
 
-    pub async fn get_policy(&self) -> Result<Value> {
-        self.client.policy(self.access_token()?, &self.policy_id).await
+    pub async fn get_policy(&self) -> Result<GuardianPolicy> {
+        let client = self.client();
+        self.session
+            .call(&self.role, |token| async move {
+                client.policy(&token, &self.policy_id).await
+            })
+            .await
     }
 
     pub async fn get_report_block_id(&self) -> Result<String> {
+        let client = self.client();
         let res = self
-            .client
-            .ref_block(self.access_token()?, &self.policy_id, &self.send_block)
+            .session
+            .call(&self.role, |token| async move {
+                client.ref_block(&token, &self.policy_id, &self.send_block).await
+            })
             .await?;
-        get_str(res, "id")
+        Ok(res.id)
     }
 
-    pub async fn get_ref_block(&self) -> Result<Value> {
+    pub async fn get_ref_block(&self) -> Result<GuardianBlockData> {
         let block_id = self.get_report_block_id().await?;
         self.get_block(&block_id).await
     }
 
-    pub async fn get_block(&self, block_id: &str) -> Result<Value> {
-        self.client
-            .get_block(self.access_token()?, &self.policy_id, block_id)
+    /// Trust chain of the VP or VC with the hash, served by the report block tagged `trust_chain_block`
+    pub async fn trust_chain(&self, hash: &str) -> Result<GuardianTrustChain> {
+        if self.trust_chain_block.is_empty() {
+            return Err(Error::Configuration(
+                "No trust chain block, see GuardianClient::with_trust_chain_block".to_string(),
+            ));
+        }
+        let client = self.client();
+        let block = self
+            .session
+            .call(&self.role, |token| async move {
+                client.ref_block(&token, &self.policy_id, &self.trust_chain_block).await
+            })
+            .await?;
+        let block_id = block.id.as_str();
+        self.session
+            .call(&self.role, |token| async move {
+                client.trust_chain(&token, &self.policy_id, block_id, hash).await
+            })
+            .await
+    }
+
+    /// Retrieves the trust chain and verifies every proof in it. Issuers the verifier has no DID document for are
+    /// resolved through their Guardian profiles. Guardian's proofs only verify with a URDNA2015 canonicalizer, see
+    /// [`GuardianVerifier`]
+    pub async fn verify_trust_chain(
+        &self,
+        hash: &str,
+        verifier: &mut GuardianVerifier,
+    ) -> Result<(GuardianTrustChain, Vec<CredentialVerification>)> {
+        let chain = self.trust_chain(hash).await?;
+        let client = self.client();
+        for item in chain.items() {
+            let (Some(issuer), Some(username)) = (&item.issuer, &item.username) else {
+                continue;
+            };
+            if verifier.has_document(issuer) {
+                continue;
+            }
+            let profile = self
+                .session
+                .call(
+                    &self.role,
+                    |token| async move { client.profile(username, &token).await },
+                )
+                .await;
+            match profile.and_then(|profile| verifier.add_guardian_document(&profile.did_document)) {
+                Ok(()) => {}
+                Err(e) => log::warn!("Could not resolve the DID document of {}: {}", issuer, e),
+            }
+        }
+
+        let verifications = verifier.verify_trust_chain(&chain);
+        Ok((chain, verifications))
+    }
+
+    pub async fn get_block(&self, block_id: &str) -> Result<GuardianBlockData> {
+        let client = self.client();
+        self.session
+            .call(&self.role, |token| async move {
+                client.get_block(&token, &self.policy_id, block_id).await
+            })
             .await
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GuardianApiClient {
-    #[serde(skip_serializing, skip_deserializing)]
-    pub(crate) client: reqwest::Client,
+    #[serde(skip_serializing, skip_deserializing, default = "http_client")]
+    pub(crate) http_client: HttpClient,
     pub(crate) url: Url,
 }
 
+/// Calls are recorded by [`GuardianApiClient`] itself, named after the resource
+fn http_client() -> HttpClient {
+    HttpClient::new("demia".to_string()).unobserved()
+}
+
 impl Default for GuardianApiClient {
     fn default() -> Self {
         Self {
             url: Url::parse(GUARDIAN_API).unwrap(),
-            client: reqwest::Client::new(),
+            http_client: http_client(),
         }
     }
 }
@@ -132,134 +203,321 @@ impl GuardianApiClient {
         T::Error: std::fmt::Display,
     {
         Ok(Self {
-            client: reqwest::Client::new(),
+            http_client: http_client(),
             url: url.try_into().map_err(|e| Error::NotFound(e.to_string()))?,
         })
     }
 
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.http_client.set_retry_policy(policy);
+    }
+
+    pub fn set_transport(&mut self, transport: Arc<dyn HttpTransport>) {
+        self.http_client.set_transport(transport);
+    }
+
+    pub(crate) fn get_timeout() -> Duration {
+        API_TIMEOUT
+    }
+
+    /// Appends the path segments to the api url, keeping any path it is served under
+    fn endpoint(&self, segments: &[&str], query: Option<&str>) -> Result<Url> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::NotFound(self.url.to_string()))?
+            .pop_if_empty()
+            .extend(segments);
+        url.set_query(query);
+        Ok(url)
+    }
+
+    /// Turns Guardian error bodies into [`Error::GuardianResponse`]
+    fn map_error(error: Error) -> Error {
+        match error {
+            Error::ResponseError { code, text, url } => {
+                let message = serde_json::from_str::<GuardianErrorResponse>(&text)
+                    .map(|response| response.message())
+                    .unwrap_or(text);
+                Error::GuardianResponse { code, message, url }
+            }
+            error => error,
+        }
+    }
+
+    /// The call for metrics, named after the resource so ids stay out of the attributes
+    fn call(method: &str, segments: &[&str]) -> Call {
+        Call::new("guardian", method).with("resource", segments.first().copied().unwrap_or_default())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, access_token: &str, segments: &[&str], query: Option<&str>) -> Result<T> {
+        let request = async {
+            self.http_client
+                .get(self.endpoint(segments, query)?, access_token, Self::get_timeout())
+                .await
+                .map_err(Self::map_error)?
+                .into_json()
+                .await
+        };
+        Self::call("GET", segments).observe(request).await
+    }
+
+    async fn post<T: DeserializeOwned>(&self, access_token: &str, segments: &[&str], json: Value) -> Result<T> {
+        let request = async {
+            self.http_client
+                .post_json(self.endpoint(segments, None)?, access_token, Self::get_timeout(), json)
+                .await
+                .map_err(Self::map_error)?
+                .into_json()
+                .await
+        };
+        Self::call("POST", segments).observe(request).await
+    }
+
+    /// Untyped GET of any path below the api url, for endpoints or fields the typed models do not cover. The path
+    /// may end in a query
+    pub async fn get_raw(&self, access_token: &str, path: &str) -> Result<Value> {
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (path, None),
+        };
+        self.get(access_token, &raw_segments(path), query).await
+    }
+
+    /// Untyped POST of any path below the api url
+    pub async fn post_raw(&self, access_token: &str, path: &str, json: Value) -> Result<Value> {
+        self.post(access_token, &raw_segments(path), json).await
+    }
+
     pub async fn login(&self, username: &str, password: &str) -> Result<GuardianLoginResponse> {
-        let login_url = format!("{}{}", self.url, "/accounts/login");
-        Ok(self
-            .client
-            .post(&login_url)
-            .json(&json!({"username": username, "password": password}))
-            .send()
-            .await?
-            .json::<GuardianLoginResponse>()
-            .await?)
+        self.post(
+            "",
+            &["accounts", "login"],
+            json!({"username": username, "password": password}),
+        )
+        .await
     }
 
     pub async fn access_token(&self, refresh_token: &str) -> Result<GuardianAccessTokenResponse> {
-        let token_url = format!("{}{}", self.url, "/accounts/access-token");
-
-        Ok(self
-            .client
-            .post(token_url)
-            .json(&json!({"refreshToken": refresh_token}))
-            .send()
-            .await?
-            .json::<GuardianAccessTokenResponse>()
-            .await?)
+        self.post(
+            "",
+            &["accounts", "access-token"],
+            json!({"refreshToken": refresh_token}),
+        )
+        .await
     }
 
     pub async fn profile(&self, username: &str, access_token: &str) -> Result<GuardianProfileResponse> {
-        let profile_url = format!("{}/profiles/{}", self.url, username);
-
-        Ok(self
-            .client
-            .get(profile_url)
-            .bearer_auth(access_token)
-            .send()
-            .await?
-            .json::<GuardianProfileResponse>()
-            .await?)
-    }
-
-    pub async fn policies(&self, access_token: &str) -> Result<Value> {
-        let policies_url = format!("{}{}", self.url, "/policies");
-
-        Ok(self
-            .client
-            .get(&policies_url)
-            .bearer_auth(access_token)
-            .send()
-            .await?
-            .json::<Value>()
-            .await?)
-    }
-
-    pub async fn policy(&self, access_token: &str, policy_id: &str) -> Result<Value> {
-        let policy_url = format!("{}/policies/{}", self.url, policy_id);
-
-        Ok(self
-            .client
-            .get(&policy_url)
-            .bearer_auth(access_token)
-            .send()
-            .await?
-            .json::<Value>()
-            .await?)
-    }
-    pub async fn ref_block(&self, access_token: &str, policy_id: &str, ref_block: &str) -> Result<Value> {
-        let tag_url = format!("{}/policies/{}/tag/{}", self.url, policy_id, ref_block);
-
-        Ok(self
-            .client
-            .get(&tag_url)
-            .bearer_auth(access_token)
-            .send()
-            .await?
-            .json::<Value>()
-            .await?)
-    }
-
-    pub async fn blocks(&self, access_token: &str, policy_id: &str) -> Result<Value> {
-        let policy_url = format!("{}/policies/{}/blocks", self.url, policy_id);
-
-        Ok(self
-            .client
-            .get(&policy_url)
-            .bearer_auth(access_token)
-            .send()
-            .await?
-            .json::<Value>()
-            .await?)
-    }
-
-    pub async fn get_block(&self, access_token: &str, policy_id: &str, block_id: &str) -> Result<Value> {
-        let get_block_url = format!("{}/policies/{}/blocks/{}", self.url, policy_id, block_id);
-
-        Ok(self
-            .client
-            .get(&get_block_url)
-            .bearer_auth(access_token)
-            .send()
-            .await?
-            .json::<Value>()
-            .await?)
-    }
-
-    pub async fn post_block(&self, access_token: &str, policy_id: &str, block_id: &str, data: &Value) -> Result<Value> {
-        let post_block_url = format!("{}/policies/{}/blocks/{}", self.url, policy_id, block_id);
-
-        Ok(self
-            .client
-            .post(&post_block_url)
-            .bearer_auth(access_token)
-            .json(&data)
-            .send()
-            .await?
-            .json::<Value>()
-            .await?)
+        self.get(access_token, &["profiles", username], None).await
+    }
+
+    pub async fn policies(&self, access_token: &str) -> Result<Vec<GuardianPolicy>> {
+        self.get(access_token, &["policies"], None).await
+    }
+
+    pub async fn policy(&self, access_token: &str, policy_id: &str) -> Result<GuardianPolicy> {
+        self.get(access_token, &["policies", policy_id], None).await
+    }
+
+    /// Resolves the id of the block with the tag
+    pub async fn ref_block(&self, access_token: &str, policy_id: &str, ref_block: &str) -> Result<GuardianBlockId> {
+        self.get(access_token, &["policies", policy_id, "tag", ref_block], None)
+            .await
+    }
+
+    /// The block tree of the policy, as visible to the logged in user
+    pub async fn blocks(&self, access_token: &str, policy_id: &str) -> Result<GuardianBlock> {
+        self.get(access_token, &["policies", policy_id, "blocks"], None).await
+    }
+
+    pub async fn get_block(&self, access_token: &str, policy_id: &str, block_id: &str) -> Result<GuardianBlockData> {
+        self.get(access_token, &["policies", policy_id, "blocks", block_id], None)
+            .await
+    }
+
+    /// Filters the report block to the VP or VC with the hash and reads its trust chain
+    pub async fn trust_chain(
+        &self,
+        access_token: &str,
+        policy_id: &str,
+        block_id: &str,
+        hash: &str,
+    ) -> Result<GuardianTrustChain> {
+        self.post_block(access_token, policy_id, block_id, &json!({ "filterValue": hash }))
+            .await?;
+        match self.get_block(access_token, policy_id, block_id).await?.data {
+            GuardianBlockContent::Other(Value::Null) => Err(Error::Guardian(format!("No trust chain for {}", hash))),
+            GuardianBlockContent::Other(data) => Ok(serde_json::from_value(data)?),
+            GuardianBlockContent::Documents(_) => {
+                Err(Error::Guardian(format!("Block {} is not a report block", block_id)))
+            }
+        }
+    }
+
+    /// Posts to the block. The response depends on the block type, so it is left untyped
+    pub async fn post_block<T: Serialize>(
+        &self,
+        access_token: &str,
+        policy_id: &str,
+        block_id: &str,
+        data: &T,
+    ) -> Result<Value> {
+        self.post(
+            access_token,
+            &["policies", policy_id, "blocks", block_id],
+            serde_json::to_value(data)?,
+        )
+        .await
     }
 }
 
-// Extracts string from serde Value, thros Guardian error if it fails
-pub(crate) fn get_str(value: Value, name: &str) -> Result<String> {
-    let val = value
-        .get(name)
-        .ok_or_else(|| Error::Guardian(format!("Missing {}", name).to_string()))?;
-    val.as_str()
-        .ok_or_else(|| Error::Guardian(format!("{} not a string", name).to_string()))
-        .map(|s| s.to_owned())
+fn raw_segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|segment| !segment.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::clients::MockTransport;
+    #[cfg(feature = "test-support")]
+    use crate::test_support::{MockGuardian, MockGuardianConfig, MockGuardianPolicy, MockGuardianUser};
+
+    #[cfg(feature = "test-support")]
+    async fn monitoring_guardian() -> (MockGuardian, GuardianClient) {
+        let guardian = MockGuardian::start(MockGuardianConfig {
+            users: vec![MockGuardianUser::new("Installer", "test", "USER")],
+            policies: vec![MockGuardianPolicy::monitoring_report()],
+        })
+        .await
+        .unwrap();
+        let policy = guardian.policy("monitoring_report").unwrap();
+        let client = GuardianClient::new(
+            guardian.api_client(),
+            policy.id,
+            "send".to_string(),
+            "reports".to_string(),
+        )
+        .with_account(GuardianRole::Installer, GuardianCredentials::new("Installer", "test"));
+        (guardian, client)
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn test_send_report() {
+        let (guardian, client) = monitoring_guardian().await;
+        let report = |id: &str| GuardianReport {
+            report_id: id.to_string(),
+            ..Default::default()
+        };
+        client.send_report(report("report-1")).await.unwrap();
+        let access_token = client.access_token().await.unwrap();
+        client.send_report(report("report-2")).await.unwrap();
+        assert_eq!(client.access_token().await.unwrap(), access_token);
+
+        let posts = guardian.posts();
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[1].username, "Installer");
+        assert_eq!(posts[1].body["ref"]["id"], "project");
+        assert_eq!(posts[1].body["document"]["field0"], "report-2");
+
+        // Stored in the reports block, as Guardian issues the VC
+        let reports = &guardian.policy("monitoring_report").unwrap().root.children[1];
+        let data = client.get_block(&reports.id).await.unwrap();
+        assert_eq!(data.documents().len(), 2);
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn test_verify_trust_chain() {
+        use crate::clients::JcsCanonicalizer;
+
+        let (guardian, client) = monitoring_guardian().await;
+        assert!(matches!(
+            client.trust_chain("vp-hash").await,
+            Err(Error::Configuration(_))
+        ));
+        let client = client.with_trust_chain_block("trust_chain");
+
+        let installer = guardian.user("Installer").unwrap();
+        let context = json!(["https://www.w3.org/2018/credentials/v1"]);
+        let report = installer.sign(json!({
+            "@context": context,
+            "id": "urn:uuid:report",
+            "type": ["VerifiableCredential"],
+            "issuer": installer.did,
+            "issuanceDate": "2024-01-01T00:00:00.000Z",
+            "credentialSubject": [{ "field0": "report-1" }],
+        }));
+        let mint = installer.sign(json!({
+            "@context": context,
+            "id": "urn:uuid:mint",
+            "type": ["VerifiablePresentation"],
+            "verifiableCredential": [report],
+        }));
+        let item = |tag: &str, document: &Value| json!({ "tag": tag, "issuer": installer.did, "username": installer.username, "document": document });
+        let policy = guardian.policy("monitoring_report").unwrap();
+        assert!(guardian.add_trust_chain(
+            &policy.id,
+            "trust_chain",
+            "vp-hash",
+            json!({ "vpDocument": item("mint", &mint), "documents": [item("reports", &report)] }),
+        ));
+
+        // The issuer's DID document is resolved through the Guardian profile
+        let mut verifier = GuardianVerifier::new(Arc::new(JcsCanonicalizer));
+        let (chain, verifications) = client.verify_trust_chain("vp-hash", &mut verifier).await.unwrap();
+        assert!(verifier.has_document(&installer.did));
+        assert_eq!(chain.documents.len(), 1);
+        // The VP, the VC inside it and the report
+        assert_eq!(verifications.len(), 3);
+        assert!(verifications.iter().all(|v| v.verified), "{:?}", verifications);
+
+        let mut tampered = report.clone();
+        tampered["credentialSubject"][0]["field0"] = json!("report-2");
+        guardian.add_trust_chain(
+            &policy.id,
+            "trust_chain",
+            "tampered",
+            json!({ "documents": [item("reports", &tampered)] }),
+        );
+        let (_, verifications) = client.verify_trust_chain("tampered", &mut verifier).await.unwrap();
+        assert!(!verifications[0].verified);
+
+        assert!(client.trust_chain("unknown").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_guardian_error() {
+        let transport = Arc::new(MockTransport::new().on_json(
+            "GET",
+            "/policies/policy",
+            401,
+            json!({ "statusCode": 401, "message": "Unauthorized" }),
+        ));
+        let mut api = GuardianApiClient::new("http://guardian.test/api/v1").unwrap();
+        api.set_transport(transport);
+
+        match api.policy("expired", "policy").await {
+            Err(Error::GuardianResponse { code, message, .. }) => {
+                assert_eq!(code, 401);
+                assert_eq!(message, "Unauthorized");
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn test_refresh_on_unauthorized() {
+        let (guardian, client) = monitoring_guardian().await;
+        let policy = client.connect().await.unwrap();
+        assert_eq!(policy.name, "monitoring_report");
+        let access_token = client.access_token().await.unwrap();
+
+        // The rejected token is renewed with the refresh token, without logging in again
+        guardian.expire_access_tokens();
+        assert_eq!(client.connect().await.unwrap().id, policy.id);
+        assert_ne!(client.access_token().await.unwrap(), access_token);
+    }
 }
@@ -0,0 +1,536 @@
+use std::{fmt::Debug, path::PathBuf, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use iota_sdk::crypto::hashes::{Digest, blake2b::Blake2b256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use super::{GuardianClient, RetryPolicy};
+use crate::{
+    errors::{ApiError as Error, ApiResult as Result},
+    models::GuardianReport,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxStatus {
+    /// Waiting for its first or next attempt
+    Pending,
+    /// Being posted to Guardian, or the post failed without showing whether Guardian accepted it, e.g. a timeout, a
+    /// lost response or a crash. Only posted again once the reconcile block shows it wasn't accepted
+    InFlight,
+    /// Posted to Guardian, not yet seen in the reconcile block
+    Submitted,
+    /// Found in the reconcile block
+    Confirmed,
+    /// Gave up after the maximum attempts, see [`GuardianOutbox::requeue`]
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub idempotency_key: String,
+    pub report: GuardianReport,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+}
+
+impl OutboxEntry {
+    pub fn new(report: GuardianReport) -> Self {
+        let now = Utc::now();
+        Self {
+            idempotency_key: idempotency_key(&report.report_id),
+            report,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+            next_attempt_at: now,
+        }
+    }
+
+    fn is_due(&self, now: DateTime<Utc>, reconciles: bool) -> bool {
+        match self.status {
+            OutboxStatus::Pending => self.next_attempt_at <= now,
+            // Only retried once the reconcile block has shown it wasn't accepted
+            OutboxStatus::InFlight => reconciles,
+            _ => false,
+        }
+    }
+}
+
+/// Key of a report in the outbox, stable across restarts so a report is only queued once
+pub fn idempotency_key(report_id: &str) -> String {
+    hex::encode(Blake2b256::digest(report_id.as_bytes()))
+}
+
+/// Whether the error proves the report never reached Guardian, so it can be posted again without reconciling.
+/// Timeouts, 5xx responses and anything else that may have followed an accepted post don't
+fn never_reached(error: &Error) -> bool {
+    match error {
+        Error::Unreachable(_) | Error::CircuitOpen(_) | Error::BadRequest | Error::NotFound(_) => true,
+        Error::ResponseError { code, .. } | Error::GuardianResponse { code, .. } => (400..500).contains(code),
+        _ => false,
+    }
+}
+
+/// Persistent storage of the outbox
+#[async_trait::async_trait]
+pub trait OutboxStore: Debug + Send + Sync {
+    async fn put(&self, entry: &OutboxEntry) -> Result<()>;
+    async fn get(&self, key: &str) -> Result<Option<OutboxEntry>>;
+    async fn list(&self) -> Result<Vec<OutboxEntry>>;
+    async fn remove(&self, key: &str) -> Result<()>;
+}
+
+/// One json file per entry. Files are replaced atomically, so a crash never leaves a half written entry
+#[derive(Debug, Clone)]
+pub struct FileOutboxStore {
+    dir: PathBuf,
+}
+
+impl FileOutboxStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn file(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+fn io_error(error: std::io::Error) -> Error {
+    Error::Guardian(format!("Outbox: {}", error))
+}
+
+#[async_trait::async_trait]
+impl OutboxStore for FileOutboxStore {
+    async fn put(&self, entry: &OutboxEntry) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await.map_err(io_error)?;
+        let file = self.file(&entry.idempotency_key);
+        let tmp = file.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(entry)?)
+            .await
+            .map_err(io_error)?;
+        tokio::fs::rename(tmp, file).await.map_err(io_error)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<OutboxEntry>> {
+        match tokio::fs::read(self.file(key)).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<OutboxEntry>> {
+        let mut entries = Vec::new();
+        let mut dir = match tokio::fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(io_error(e)),
+        };
+        while let Some(file) = dir.next_entry().await.map_err(io_error)? {
+            if file.path().extension().is_some_and(|extension| extension == "json") {
+                let bytes = tokio::fs::read(file.path()).await.map_err(io_error)?;
+                entries.push(serde_json::from_slice(&bytes)?);
+            }
+        }
+        entries.sort_by_key(|entry: &OutboxEntry| entry.created_at);
+        Ok(entries)
+    }
+
+    async fn remove(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.file(key)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(io_error(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Queues reports on disk and submits them to Guardian until they are accepted.
+///
+/// Failed submissions are retried with the backoff of the retry policy, `max_retries` bounds the attempts. Before
+/// each attempt, and for submitted reports, the documents of the reconcile block are searched for the report id, so a
+/// report that reached Guardian before a crash or timeout is not submitted twice. Entries are stored as
+/// [`OutboxStatus::InFlight`] before they are posted, an entry still in flight when processing starts is resubmitted
+/// only if the reconcile block doesn't hold it. Without a reconcile block reports stay [`OutboxStatus::Submitted`],
+/// and entries left in flight stay so until they are [requeued](GuardianOutbox::requeue).
+#[derive(Debug)]
+pub struct GuardianOutbox {
+    client: GuardianClient,
+    store: Arc<dyn OutboxStore>,
+    policy: RetryPolicy,
+    reconcile_block: Option<String>,
+    processing: Mutex<()>,
+}
+
+impl GuardianOutbox {
+    pub fn new(client: GuardianClient, store: Arc<dyn OutboxStore>) -> Self {
+        Self {
+            client,
+            store,
+            policy: RetryPolicy {
+                max_retries: 10,
+                initial_backoff: Duration::from_secs(5),
+                max_backoff: Duration::from_secs(60 * 60),
+                ..Default::default()
+            },
+            reconcile_block: None,
+            processing: Mutex::new(()),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Tag of a block serving the submitted reports, e.g. the monitoring reports grid
+    pub fn with_reconcile_block(mut self, tag: &str) -> Self {
+        self.reconcile_block = Some(tag.to_string());
+        self
+    }
+
+    /// Stores the report for submission and returns its idempotency key. Queuing a report id again returns the
+    /// existing entry's key and leaves it untouched
+    pub async fn enqueue(&self, report: GuardianReport) -> Result<String> {
+        let key = idempotency_key(&report.report_id);
+        if self.store.get(&key).await?.is_none() {
+            self.store.put(&OutboxEntry::new(report)).await?;
+        }
+        Ok(key)
+    }
+
+    pub async fn status(&self, key: &str) -> Result<Option<OutboxEntry>> {
+        self.store.get(key).await
+    }
+
+    pub async fn status_of_report(&self, report_id: &str) -> Result<Option<OutboxEntry>> {
+        self.store.get(&idempotency_key(report_id)).await
+    }
+
+    pub async fn entries(&self) -> Result<Vec<OutboxEntry>> {
+        self.store.list().await
+    }
+
+    pub async fn entries_with_status(&self, status: OutboxStatus) -> Result<Vec<OutboxEntry>> {
+        let mut entries = self.store.list().await?;
+        entries.retain(|entry| entry.status == status);
+        Ok(entries)
+    }
+
+    /// Puts a failed report back in the queue with fresh attempts
+    pub async fn requeue(&self, key: &str) -> Result<()> {
+        let mut entry = self
+            .store
+            .get(key)
+            .await?
+            .ok_or_else(|| Error::Guardian(format!("No outbox entry {}", key)))?;
+        entry.status = OutboxStatus::Pending;
+        entry.attempts = 0;
+        entry.next_attempt_at = Utc::now();
+        entry.updated_at = Utc::now();
+        self.store.put(&entry).await
+    }
+
+    /// Drops confirmed entries, returns how many were removed
+    pub async fn prune_confirmed(&self) -> Result<usize> {
+        let confirmed = self.entries_with_status(OutboxStatus::Confirmed).await?;
+        for entry in &confirmed {
+            self.store.remove(&entry.idempotency_key).await?;
+        }
+        Ok(confirmed.len())
+    }
+
+    /// Attempts every due report once, returns how many were submitted or found already accepted
+    pub async fn process(&self) -> Result<usize> {
+        let _processing = self.processing.lock().await;
+        let now = Utc::now();
+        let reconciles = self.reconcile_block.is_some();
+        let mut due = Vec::new();
+        for entry in self.store.list().await? {
+            if entry.is_due(now, reconciles) {
+                due.push(entry);
+            } else if entry.status == OutboxStatus::InFlight {
+                log::warn!(
+                    "Report {} may have reached Guardian, requeue it once checked",
+                    entry.report.report_id
+                );
+            }
+        }
+
+        let accepted = self.accepted_report_ids().await?;
+        let mut done = 0;
+        for mut entry in due {
+            if accepted.iter().any(|id| id == &entry.report.report_id) {
+                log::info!("Report {} was already accepted by Guardian", entry.report.report_id);
+                entry.status = OutboxStatus::Confirmed;
+                entry.updated_at = Utc::now();
+                self.store.put(&entry).await?;
+                done += 1;
+                continue;
+            }
+
+            entry.attempts += 1;
+            entry.status = OutboxStatus::InFlight;
+            entry.updated_at = Utc::now();
+            self.store.put(&entry).await?;
+            entry.updated_at = Utc::now();
+            match self.client.send_report(entry.report.clone()).await {
+                Ok(_) => {
+                    entry.status = OutboxStatus::Submitted;
+                    entry.last_error = None;
+                    done += 1;
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Submitting report {} failed, attempt {}: {}",
+                        entry.report.report_id,
+                        entry.attempts,
+                        e
+                    );
+                    entry.last_error = Some(e.to_string());
+                    if !never_reached(&e) {
+                        // Guardian may have accepted it, posting again could submit it twice
+                        entry.status = OutboxStatus::InFlight;
+                    } else if entry.attempts > self.policy.max_retries {
+                        entry.status = OutboxStatus::Failed;
+                    } else {
+                        entry.status = OutboxStatus::Pending;
+                        let backoff = self.policy.backoff(entry.attempts - 1);
+                        entry.next_attempt_at = Utc::now()
+                            + chrono::Duration::from_std(backoff).unwrap_or_else(|_| chrono::Duration::zero());
+                    }
+                }
+            }
+            self.store.put(&entry).await?;
+        }
+        Ok(done)
+    }
+
+    /// Confirms submitted and in flight reports that show up in the reconcile block, returns how many were confirmed
+    pub async fn reconcile(&self) -> Result<usize> {
+        let _processing = self.processing.lock().await;
+        let accepted = self.accepted_report_ids().await?;
+        let mut confirmed = 0;
+        let mut entries = self.store.list().await?;
+        entries.retain(|entry| matches!(entry.status, OutboxStatus::Submitted | OutboxStatus::InFlight));
+        for mut entry in entries {
+            if accepted.iter().any(|id| id == &entry.report.report_id) {
+                entry.status = OutboxStatus::Confirmed;
+                entry.updated_at = Utc::now();
+                self.store.put(&entry).await?;
+                confirmed += 1;
+            }
+        }
+        Ok(confirmed)
+    }
+
+    /// Report ids of the documents in the reconcile block
+    async fn accepted_report_ids(&self) -> Result<Vec<String>> {
+        let Some(tag) = &self.reconcile_block else {
+            return Ok(Vec::new());
+        };
+        let client = self.client.client();
+        let (policy_id, role) = (self.client.policy_id.as_str(), &self.client.role);
+        let block = self
+            .client
+            .session
+            .call(
+                role,
+                |token| async move { client.ref_block(&token, policy_id, tag).await },
+            )
+            .await?;
+        let data = self.client.get_block(&block.id).await?;
+
+        Ok(data
+            .documents()
+            .iter()
+            .flat_map(|document| match document.document.pointer("/credentialSubject") {
+                Some(Value::Array(subjects)) => subjects.clone(),
+                Some(subject) => vec![subject.clone()],
+                None => Vec::new(),
+            })
+            .filter_map(|subject| subject.get("field0").and_then(Value::as_str).map(str::to_string))
+            .collect())
+    }
+
+    /// Processes and reconciles the outbox every `interval` until the handle is aborted
+    pub fn spawn(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.process().await {
+                    log::warn!("Guardian outbox processing failed: {}", e);
+                }
+                if let Err(e) = self.reconcile().await {
+                    log::warn!("Guardian outbox reconciliation failed: {}", e);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        clients::GuardianCredentials,
+        models::GuardianRole,
+        test_support::{MockGuardian, MockGuardianConfig, MockGuardianPolicy, MockGuardianUser},
+    };
+
+    async fn outbox() -> (MockGuardian, GuardianOutbox, PathBuf) {
+        let guardian = MockGuardian::start(MockGuardianConfig {
+            users: vec![MockGuardianUser::new("Installer", "test", "USER")],
+            policies: vec![MockGuardianPolicy::monitoring_report()],
+        })
+        .await
+        .unwrap();
+        let policy = guardian.policy("monitoring_report").unwrap();
+        let client = GuardianClient::new(
+            guardian.api_client(),
+            policy.id,
+            "send".to_string(),
+            "reports".to_string(),
+        )
+        .with_account(GuardianRole::Installer, GuardianCredentials::new("Installer", "test"));
+
+        let dir = std::env::temp_dir().join(format!("demia-outbox-{}", uuid::Uuid::new_v4()));
+        let outbox = GuardianOutbox::new(client, Arc::new(FileOutboxStore::new(&dir)));
+        (guardian, outbox, dir)
+    }
+
+    fn report(id: &str) -> GuardianReport {
+        GuardianReport {
+            report_id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outbox_reconciles_before_resubmitting() {
+        let (guardian, outbox, dir) = outbox().await;
+        let outbox = outbox.with_reconcile_block("reports");
+        let policy = guardian.policy("monitoring_report").unwrap();
+        guardian.add_document(
+            &policy.id,
+            "reports",
+            json!({ "id": "vc", "document": { "credentialSubject": [{ "field0": "report-1" }] } }),
+        );
+
+        let key = outbox.enqueue(report("report-1")).await.unwrap();
+        assert_eq!(outbox.enqueue(report("report-1")).await.unwrap(), key);
+        assert_eq!(outbox.entries().await.unwrap().len(), 1);
+
+        assert_eq!(outbox.process().await.unwrap(), 1);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::Confirmed);
+        assert_eq!(entry.attempts, 0);
+        assert!(guardian.posts().is_empty());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_outbox_submits_and_confirms() {
+        let (guardian, outbox, dir) = outbox().await;
+        let outbox = outbox.with_reconcile_block("reports");
+        outbox.enqueue(report("report-1")).await.unwrap();
+
+        assert_eq!(outbox.process().await.unwrap(), 1);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::Submitted);
+        assert_eq!(guardian.posts().len(), 1);
+
+        assert_eq!(outbox.reconcile().await.unwrap(), 1);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::Confirmed);
+        assert_eq!(outbox.prune_confirmed().await.unwrap(), 1);
+        assert!(outbox.entries().await.unwrap().is_empty());
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_entry_needs_reconciliation() {
+        let (guardian, outbox, dir) = outbox().await;
+
+        // Left in flight by a crash while posting
+        let mut entry = OutboxEntry::new(report("report-1"));
+        entry.status = OutboxStatus::InFlight;
+        entry.attempts = 1;
+        FileOutboxStore::new(&dir).put(&entry).await.unwrap();
+
+        assert_eq!(outbox.process().await.unwrap(), 0);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::InFlight);
+        assert_eq!(entry.attempts, 1);
+        assert!(guardian.posts().is_empty());
+
+        // Not in the reconcile block, so it is submitted again
+        let outbox = outbox.with_reconcile_block("reports");
+        assert_eq!(outbox.process().await.unwrap(), 1);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::Submitted);
+        assert_eq!(entry.attempts, 2);
+        assert_eq!(guardian.posts().len(), 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_lost_response_stays_in_flight() {
+        let (guardian, outbox, dir) = outbox().await;
+        outbox.enqueue(report("report-1")).await.unwrap();
+
+        // Guardian stores the report, but the client only sees a bad gateway
+        guardian.fail_next_post_after_accepting(StatusCode::BAD_GATEWAY);
+        assert_eq!(outbox.process().await.unwrap(), 0);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::InFlight);
+        assert!(entry.last_error.is_some());
+        assert_eq!(guardian.posts().len(), 1);
+
+        // Without a reconcile block it is never posted again
+        assert_eq!(outbox.process().await.unwrap(), 0);
+        assert_eq!(guardian.posts().len(), 1);
+
+        let outbox = outbox.with_reconcile_block("reports");
+        assert_eq!(outbox.process().await.unwrap(), 1);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::Confirmed);
+        assert_eq!(guardian.posts().len(), 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_rejected_post_is_retried() {
+        let (guardian, outbox, dir) = outbox().await;
+        let outbox = outbox.with_retry_policy(RetryPolicy {
+            initial_backoff: Duration::ZERO,
+            ..Default::default()
+        });
+        outbox.enqueue(report("report-1")).await.unwrap();
+
+        guardian.reject_next_post(StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(outbox.process().await.unwrap(), 0);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::Pending);
+        assert!(guardian.posts().is_empty());
+
+        assert_eq!(outbox.process().await.unwrap(), 1);
+        let entry = outbox.status_of_report("report-1").await.unwrap().unwrap();
+        assert_eq!(entry.status, OutboxStatus::Submitted);
+        assert_eq!(entry.attempts, 2);
+        assert_eq!(guardian.posts().len(), 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,180 @@
+use std::{collections::HashMap, fmt::Debug, future::Future, sync::Arc};
+
+use tokio::sync::Mutex;
+
+use super::GuardianApiClient;
+use crate::{
+    errors::{ApiError as Error, ApiResult as Result},
+    models::{GuardianRole, UserIdentity},
+    telemetry,
+    utils::{STRONGHOLD_KEY_HEDERA_PASSWORD, STRONGHOLD_KEY_HEDERA_USERNAME},
+};
+
+#[derive(Clone)]
+pub struct GuardianCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Debug for GuardianCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuardianCredentials")
+            .field("username", &self.username)
+            .finish()
+    }
+}
+
+impl GuardianCredentials {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Reads the Hedera account stored in the user's Stronghold
+    pub async fn from_stronghold(identity: &UserIdentity) -> Result<Self> {
+        let read = |key: &'static str| async move {
+            identity
+                .get_stronghold_string(key)
+                .await
+                .map_err(|e| Error::Guardian(e.to_string()))?
+                .ok_or_else(|| Error::Guardian(format!("Missing {} in stronghold", key)))
+        };
+        Ok(Self {
+            username: read(STRONGHOLD_KEY_HEDERA_USERNAME).await?,
+            password: read(STRONGHOLD_KEY_HEDERA_PASSWORD).await?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct GuardianTokens {
+    refresh_token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Logged in Guardian accounts, one per role.
+///
+/// Access tokens are cached and renewed from the refresh token once Guardian rejects them, logging in again only when
+/// the refresh token is no longer accepted. Each role is renewed under its own lock, so one role logging in doesn't
+/// hold up requests of the others. Clones share the tokens.
+#[derive(Clone, Default)]
+pub struct GuardianSession {
+    client: GuardianApiClient,
+    accounts: HashMap<GuardianRole, GuardianCredentials>,
+    tokens: Arc<Mutex<HashMap<GuardianRole, Arc<Mutex<GuardianTokens>>>>>,
+}
+
+impl Debug for GuardianSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuardianSession")
+            .field("url", &self.client.url.as_str())
+            .field("roles", &self.accounts.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl GuardianSession {
+    pub fn new(client: GuardianApiClient) -> Self {
+        Self {
+            client,
+            accounts: HashMap::new(),
+            tokens: Default::default(),
+        }
+    }
+
+    pub fn with_account(mut self, role: GuardianRole, credentials: GuardianCredentials) -> Self {
+        self.add_account(role, credentials);
+        self
+    }
+
+    /// Sets the account of the role. Tokens of a replaced account stay cached until [`Self::logout`]
+    pub fn add_account(&mut self, role: GuardianRole, credentials: GuardianCredentials) {
+        self.accounts.insert(role, credentials);
+    }
+
+    pub fn client(&self) -> &GuardianApiClient {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut GuardianApiClient {
+        &mut self.client
+    }
+
+    pub fn roles(&self) -> impl Iterator<Item = &GuardianRole> {
+        self.accounts.keys()
+    }
+
+    /// The cached access token of the role, renewing or logging in as needed
+    pub async fn access_token(&self, role: &GuardianRole) -> Result<String> {
+        let tokens = self.tokens(role).await;
+        // Concurrent callers of the role wait here for a single renewal
+        let mut tokens = tokens.lock().await;
+        match &tokens.access_token {
+            Some(access_token) => Ok(access_token.clone()),
+            None => self.authenticate(role, &mut tokens).await,
+        }
+    }
+
+    async fn tokens(&self, role: &GuardianRole) -> Arc<Mutex<GuardianTokens>> {
+        self.tokens.lock().await.entry(role.clone()).or_default().clone()
+    }
+
+    async fn authenticate(&self, role: &GuardianRole, tokens: &mut GuardianTokens) -> Result<String> {
+        if let Some(refresh_token) = &tokens.refresh_token {
+            let refreshed = self.client.access_token(refresh_token).await;
+            telemetry::record_token_refresh("guardian", refreshed.is_ok());
+            match refreshed {
+                Ok(response) => {
+                    tokens.access_token = Some(response.access_token.clone());
+                    return Ok(response.access_token);
+                }
+                Err(e) => log::debug!("Refreshing the {} access token failed, logging in: {}", role, e),
+            }
+        }
+
+        let credentials = self
+            .accounts
+            .get(role)
+            .ok_or_else(|| Error::Guardian(format!("No account for role {}", role)))?;
+        let login = self.client.login(&credentials.username, &credentials.password).await?;
+        tokens.refresh_token = Some(login.refresh_token.clone());
+        let response = self.client.access_token(&login.refresh_token).await?;
+        tokens.access_token = Some(response.access_token.clone());
+        Ok(response.access_token)
+    }
+
+    /// Drops the access token if it is still the cached one, so the next call renews it
+    pub async fn expire(&self, role: &GuardianRole, access_token: &str) {
+        let tokens = self.tokens.lock().await.get(role).cloned();
+        if let Some(tokens) = tokens {
+            let mut tokens = tokens.lock().await;
+            if tokens.access_token.as_deref() == Some(access_token) {
+                tokens.access_token = None;
+            }
+        }
+    }
+
+    /// Forgets both tokens of the role
+    pub async fn logout(&self, role: &GuardianRole) {
+        self.tokens.lock().await.remove(role);
+    }
+
+    /// Runs the request with the access token of the role. A 401 renews the token and runs it once more
+    pub async fn call<T, F, Fut>(&self, role: &GuardianRole, request: F) -> Result<T>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let access_token = self.access_token(role).await?;
+        match request(access_token.clone()).await {
+            Err(Error::GuardianResponse { code: 401, .. }) => {
+                log::debug!("Guardian rejected the {} access token, renewing", role);
+                self.expire(role, &access_token).await;
+                request(self.access_token(role).await?).await
+            }
+            result => result,
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use iota_sdk::crypto::{
+    hashes::{Digest, sha::Sha256},
+    signatures::ed25519::{PublicKey, Signature},
+};
+use serde_json::Value;
+
+use crate::{
+    errors::{ApiError as Error, ApiResult as Result},
+    identity_demia::{document::CoreDocument, verification::VerificationMethod},
+    models::{CredentialVerification, GuardianDidDocument, GuardianTrustChain, Proof},
+};
+
+/// Turns a document into the bytes that were hashed for its proof. Guardian's own proofs need an implementation
+/// backed by a JSON-LD processor producing URDNA2015, which the SDK doesn't ship
+pub trait ProofCanonicalizer: Send + Sync {
+    fn canonicalize(&self, value: &Value) -> Result<Vec<u8>>;
+}
+
+/// RFC 8785 canonical json, for proofs signed over JCS.
+///
+/// Guardian signs its Ed25519Signature2018 proofs over URDNA2015 canonicalized JSON-LD, which this does not produce.
+/// Verifying those needs a canonicalizer backed by a JSON-LD processor that can load the policy's schema contexts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JcsCanonicalizer;
+
+impl ProofCanonicalizer for JcsCanonicalizer {
+    fn canonicalize(&self, value: &Value) -> Result<Vec<u8>> {
+        let mut canonical = String::new();
+        write_canonical(value, &mut canonical);
+        Ok(canonical.into_bytes())
+    }
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (index, (key, value)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        Value::Array(values) => {
+            out.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out);
+            }
+            out.push(']');
+        }
+        // Integral floats are written without a fraction, as in ECMAScript
+        Value::Number(number) => match number.as_f64() {
+            Some(float) if number.is_f64() && float.fract() == 0.0 && float.abs() < 1e21 => {
+                out.push_str(&format!("{}", float as i64))
+            }
+            _ => out.push_str(&number.to_string()),
+        },
+        value => out.push_str(&value.to_string()),
+    }
+}
+
+/// Verifies Ed25519Signature2018 proofs with detached JWS against the DID documents of the issuers.
+///
+/// There is no default canonicalizer: it has to match the one the proofs were signed over. Credentials issued by
+/// Guardian are signed over URDNA2015, so verifying a chain from Guardian needs a caller supplied
+/// [`ProofCanonicalizer`]. With [`JcsCanonicalizer`] only proofs signed over JCS verify.
+#[derive(Clone)]
+pub struct GuardianVerifier {
+    documents: HashMap<String, CoreDocument>,
+    canonicalizer: Arc<dyn ProofCanonicalizer>,
+}
+
+impl Debug for GuardianVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuardianVerifier")
+            .field("documents", &self.documents.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl GuardianVerifier {
+    pub fn new(canonicalizer: Arc<dyn ProofCanonicalizer>) -> Self {
+        Self {
+            documents: HashMap::new(),
+            canonicalizer,
+        }
+    }
+
+    pub fn add_document(&mut self, document: CoreDocument) {
+        self.documents.insert(document.id().to_string(), document);
+    }
+
+    pub fn add_guardian_document(&mut self, document: &GuardianDidDocument) -> Result<()> {
+        self.add_document(document.document()?);
+        Ok(())
+    }
+
+    pub fn has_document(&self, did: &str) -> bool {
+        self.documents.contains_key(did)
+    }
+
+    /// Checks the proof of a VC or VP against the DID document of its verification method, which has to belong to the
+    /// issuer of a VC or the holder of a VP
+    pub fn verify_credential(&self, credential: &Value) -> Result<()> {
+        let proof_value = credential
+            .get("proof")
+            .ok_or_else(|| Error::InvalidProof("Credential has no proof".to_string()))?;
+        let proof: Proof = serde_json::from_value(proof_value.clone())?;
+        if proof.r#type != "Ed25519Signature2018" {
+            return Err(Error::InvalidProof(format!("Unsupported proof type {}", proof.r#type)));
+        }
+
+        let did = proof.verification_method.split('#').next().unwrap_or_default();
+        let signers = signers(credential);
+        if !signers.iter().any(|signer| signer == did) {
+            return Err(Error::InvalidProof(format!(
+                "Proof by {} but the credential is from {}",
+                did,
+                match signers.is_empty() {
+                    true => "an unknown issuer".to_string(),
+                    false => signers.join(", "),
+                }
+            )));
+        }
+
+        let document = self
+            .documents
+            .get(did)
+            .ok_or_else(|| Error::InvalidProof(format!("No DID document for {}", did)))?;
+        let method = document
+            .resolve_method(proof.verification_method.as_str(), None)
+            .ok_or_else(|| Error::InvalidProof(format!("No method {}", proof.verification_method)))?;
+        let public_key = public_key(method)?;
+
+        let (header, signature) = proof
+            .jws
+            .split_once("..")
+            .ok_or_else(|| Error::InvalidProof("JWS is not detached".to_string()))?;
+        let header_json: Value = serde_json::from_slice(&decode(header)?)?;
+        if header_json.get("alg").and_then(Value::as_str) != Some("EdDSA") {
+            return Err(Error::InvalidProof(format!("Unsupported JWS header {}", header_json)));
+        }
+        let encoded_payload = header_json.get("b64").and_then(Value::as_bool).unwrap_or(true);
+
+        // Proof options carry the context of the document, without the signature itself
+        let mut options = proof_value.clone();
+        if let Some(options) = options.as_object_mut() {
+            for key in ["jws", "proofValue", "signatureValue"] {
+                options.remove(key);
+            }
+            if let Some(context) = credential.get("@context") {
+                options.insert("@context".to_string(), context.clone());
+            }
+        }
+        let mut unsigned = credential.clone();
+        if let Some(unsigned) = unsigned.as_object_mut() {
+            unsigned.remove("proof");
+        }
+
+        let mut data = Sha256::digest(self.canonicalizer.canonicalize(&options)?).to_vec();
+        data.extend(Sha256::digest(self.canonicalizer.canonicalize(&unsigned)?));
+
+        let mut signing_input = format!("{}.", header).into_bytes();
+        match encoded_payload {
+            true => signing_input.extend(URL_SAFE_NO_PAD.encode(&data).into_bytes()),
+            false => signing_input.extend(data),
+        }
+
+        let signature = decode(signature)?;
+        let signature = <[u8; Signature::LENGTH]>::try_from(signature.as_slice())
+            .map_err(|_| Error::InvalidProof(format!("Invalid signature length {}", signature.len())))?;
+        match public_key.verify(&Signature::from_bytes(signature), &signing_input) {
+            true => Ok(()),
+            false => Err(Error::InvalidProof(format!(
+                "Signature of {} does not match",
+                credential.get("id").and_then(Value::as_str).unwrap_or("credential")
+            ))),
+        }
+    }
+
+    /// Verifies every VP and VC of the chain. The chain holds when all of them are verified
+    pub fn verify_trust_chain(&self, chain: &GuardianTrustChain) -> Vec<CredentialVerification> {
+        chain
+            .credentials()
+            .into_iter()
+            .map(|credential| {
+                let result = self.verify_credential(credential);
+                CredentialVerification {
+                    id: credential.get("id").and_then(Value::as_str).map(str::to_string),
+                    issuer: issuer(credential),
+                    verified: result.is_ok(),
+                    error: result.err().map(|e| e.to_string()),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The issuer DID, given either as a string or as an object with an id
+pub(crate) fn issuer(credential: &Value) -> Option<String> {
+    match credential.get("issuer")? {
+        Value::String(issuer) => Some(issuer.clone()),
+        issuer => issuer.get("id").and_then(Value::as_str).map(str::to_string),
+    }
+}
+
+/// DIDs allowed to sign: the issuer of a VC, or the holder of a VP. Guardian presentations name no holder and are
+/// signed by the standard registry that issued their credentials
+fn signers(credential: &Value) -> Vec<String> {
+    if let Some(issuer) = issuer(credential) {
+        return vec![issuer];
+    }
+    match credential.get("holder") {
+        Some(Value::String(holder)) => vec![holder.clone()],
+        Some(holder) => holder
+            .get("id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .into_iter()
+            .collect(),
+        None => credential
+            .get("verifiableCredential")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(issuer)
+            .collect(),
+    }
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|e| Error::InvalidProof(e.to_string()))
+}
+
+fn public_key(method: &VerificationMethod) -> Result<PublicKey> {
+    let json = serde_json::to_value(method)?;
+    let bytes = match json.pointer("/publicKeyJwk/x").and_then(Value::as_str) {
+        Some(x) => decode(x)?,
+        None => method
+            .data()
+            .try_decode()
+            .map_err(|e| Error::InvalidProof(e.to_string()))?,
+    };
+    let bytes = <[u8; PublicKey::LENGTH]>::try_from(bytes.as_slice())
+        .map_err(|_| Error::InvalidProof(format!("Invalid public key length {}", bytes.len())))?;
+    PublicKey::try_from_bytes(bytes).map_err(|e| Error::InvalidProof(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use iota_sdk::crypto::signatures::ed25519::SecretKey;
+    use serde_json::json;
+
+    use super::*;
+    use crate::identity_demia::core::BaseEncoding;
+
+    const ISSUER: &str = "did:hedera:testnet:z6MkGuardian_0.0.1";
+    const OTHER: &str = "did:hedera:testnet:z6MkOther_0.0.2";
+
+    fn document(did: &str, key: &SecretKey) -> CoreDocument {
+        serde_json::from_value(json!({
+            "id": did,
+            "verificationMethod": [{
+                "id": format!("{}#did-root-key", did),
+                "type": "Ed25519VerificationKey2018",
+                "controller": did,
+                "publicKeyMultibase": BaseEncoding::encode_multibase(key.public_key().as_slice(), None),
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn credential(issuer: &str) -> Value {
+        json!({
+            "@context": ["https://www.w3.org/2018/credentials/v1"],
+            "id": "urn:uuid:report",
+            "type": ["VerifiableCredential"],
+            "issuer": issuer,
+            "issuanceDate": "2024-01-01T00:00:00.000Z",
+            "credentialSubject": [{ "id": "subject", "field0": "report-1", "field4": 1.0 }],
+        })
+    }
+
+    /// Adds a detached JWS proof by the root key of `signer`, canonicalized with JCS
+    fn sign(mut credential: Value, signer: &str, key: &SecretKey) -> Value {
+        let mut proof = json!({
+            "type": "Ed25519Signature2018",
+            "created": "2024-01-01T00:00:00Z",
+            "verificationMethod": format!("{}#did-root-key", signer),
+            "proofPurpose": "assertionMethod",
+            "@context": credential["@context"],
+        });
+        let canonicalizer = JcsCanonicalizer;
+        let mut data = Sha256::digest(canonicalizer.canonicalize(&proof).unwrap()).to_vec();
+        data.extend(Sha256::digest(canonicalizer.canonicalize(&credential).unwrap()));
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"EdDSA","b64":false,"crit":["b64"]}"#);
+        let mut signing_input = format!("{}.", header).into_bytes();
+        signing_input.extend(data);
+        let signature = URL_SAFE_NO_PAD.encode(key.sign(&signing_input).to_bytes());
+
+        proof.as_object_mut().unwrap().remove("@context");
+        proof["jws"] = json!(format!("{}..{}", header, signature));
+        credential["proof"] = proof;
+        credential
+    }
+
+    fn verifier(documents: &[(&str, &SecretKey)]) -> GuardianVerifier {
+        let mut verifier = GuardianVerifier::new(Arc::new(JcsCanonicalizer));
+        for (did, key) in documents {
+            verifier.add_document(document(did, key));
+        }
+        verifier
+    }
+
+    #[test]
+    fn test_verify_credential() {
+        let key = SecretKey::generate().unwrap();
+        let verifier = verifier(&[(ISSUER, &key)]);
+        let mut credential = sign(credential(ISSUER), ISSUER, &key);
+        verifier.verify_credential(&credential).unwrap();
+
+        credential["credentialSubject"][0]["field0"] = json!("report-2");
+        assert!(matches!(
+            verifier.verify_credential(&credential),
+            Err(Error::InvalidProof(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_proof_by_another_did() {
+        let (issuer_key, other_key) = (SecretKey::generate().unwrap(), SecretKey::generate().unwrap());
+        let verifier = verifier(&[(ISSUER, &issuer_key), (OTHER, &other_key)]);
+
+        // A valid signature, but not by the issuer the credential names
+        let credential = sign(credential(ISSUER), OTHER, &other_key);
+        let error = verifier.verify_credential(&credential).unwrap_err();
+        assert!(error.to_string().contains(OTHER), "{}", error);
+
+        let mut anonymous = sign(credential(ISSUER), ISSUER, &issuer_key);
+        anonymous.as_object_mut().unwrap().remove("issuer");
+        assert!(verifier.verify_credential(&anonymous).is_err());
+    }
+
+    #[test]
+    fn test_presentation_is_signed_by_holder() {
+        let key = SecretKey::generate().unwrap();
+        let verifier = verifier(&[(ISSUER, &key)]);
+        let presentation = json!({
+            "@context": ["https://www.w3.org/2018/credentials/v1"],
+            "id": "urn:uuid:presentation",
+            "type": ["VerifiablePresentation"],
+            "holder": { "id": ISSUER },
+            "verifiableCredential": [],
+        });
+        verifier.verify_credential(&sign(presentation, ISSUER, &key)).unwrap();
+
+        // Without a holder, the issuer of a contained credential signs
+        let presentation = json!({
+            "@context": ["https://www.w3.org/2018/credentials/v1"],
+            "type": ["VerifiablePresentation"],
+            "verifiableCredential": [credential(ISSUER)],
+        });
+        verifier
+            .verify_credential(&sign(presentation.clone(), ISSUER, &key))
+            .unwrap();
+        let other_key = SecretKey::generate().unwrap();
+        let verifier = self::verifier(&[(ISSUER, &key), (OTHER, &other_key)]);
+        assert!(
+            verifier
+                .verify_credential(&sign(presentation, OTHER, &other_key))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use super::GuardianSession;
+use crate::{
+    configuration::{GuardianAction, GuardianWorkflowConfiguration, GuardianWorkflowStep},
+    errors::{ApiError as Error, ApiResult as Result},
+    models::{GuardianBlockData, GuardianBlockSubmission, GuardianDocument, GuardianRole},
+    utils::GUARDIAN_POLL_INTERVAL,
+};
+
+/// Named values of a workflow run, the inputs and whatever the steps saved
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuardianWorkflowContext {
+    pub values: HashMap<String, Value>,
+}
+
+impl GuardianWorkflowContext {
+    pub fn new(inputs: HashMap<String, Value>) -> Self {
+        Self { values: inputs }
+    }
+
+    pub fn insert(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Value> {
+        self.values
+            .get(name)
+            .ok_or_else(|| Error::Guardian(format!("Workflow has no value {}", name)))
+    }
+
+    pub fn document(&self, name: &str) -> Result<GuardianDocument> {
+        Ok(serde_json::from_value(self.get(name)?.clone())?)
+    }
+}
+
+/// Runs a configured flow through a Guardian policy
+#[derive(Debug, Clone)]
+pub struct GuardianWorkflow {
+    session: GuardianSession,
+    config: GuardianWorkflowConfiguration,
+}
+
+impl GuardianWorkflow {
+    pub fn new(session: GuardianSession, config: GuardianWorkflowConfiguration) -> Self {
+        Self { session, config }
+    }
+
+    /// Picks the workflow by name out of the configured ones
+    pub fn from_configs(
+        session: GuardianSession,
+        configs: &[GuardianWorkflowConfiguration],
+        name: &str,
+    ) -> Result<Self> {
+        let config = configs
+            .iter()
+            .find(|config| config.name == name)
+            .ok_or_else(|| Error::Guardian(format!("No workflow {}", name)))?;
+        Ok(Self::new(session, config.clone()))
+    }
+
+    /// Submits `input` to the block tagged `send_block`, referencing the `project_sr` document the block serves
+    pub fn monitoring_report(session: GuardianSession, role: GuardianRole, policy_id: &str, send_block: &str) -> Self {
+        let step = |action| GuardianWorkflowStep {
+            role: role.clone(),
+            tag: Some(send_block.to_string()),
+            block_type: None,
+            action,
+        };
+        let config = GuardianWorkflowConfiguration {
+            name: "monitoring_report".to_string(),
+            policy_id: policy_id.to_string(),
+            steps: vec![
+                step(GuardianAction::SelectDocument {
+                    document_type: Some("project_sr".to_string()),
+                    filter: HashMap::new(),
+                    save_as: "project".to_string(),
+                    wait: 0,
+                }),
+                step(GuardianAction::RequestVc {
+                    input: "input".to_string(),
+                    reference: Some("project".to_string()),
+                    save_as: Some("response".to_string()),
+                }),
+            ],
+        };
+        Self::new(session, config)
+    }
+
+    pub fn config(&self) -> &GuardianWorkflowConfiguration {
+        &self.config
+    }
+
+    pub async fn run(&self, inputs: HashMap<String, Value>) -> Result<GuardianWorkflowContext> {
+        let mut context = GuardianWorkflowContext::new(inputs);
+        for (index, step) in self.config.steps.iter().enumerate() {
+            log::debug!("Workflow {} step {}: {:?}", self.config.name, index, step.action);
+            if let Err(e) = self.run_step(step, &mut context).await {
+                log::warn!("Workflow {} failed at step {}: {}", self.config.name, index, e);
+                return Err(e);
+            }
+        }
+        Ok(context)
+    }
+
+    pub async fn run_step(&self, step: &GuardianWorkflowStep, context: &mut GuardianWorkflowContext) -> Result<()> {
+        let block_id = self.block_id(step).await?;
+        match &step.action {
+            GuardianAction::SelectDocument {
+                document_type,
+                filter,
+                save_as,
+                wait,
+            } => {
+                let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(*wait);
+                let document = loop {
+                    let data = self.block_data(&step.role, &block_id).await?;
+                    if let Some(document) = select_document(&data, document_type.as_deref(), filter) {
+                        break document;
+                    }
+                    if tokio::time::Instant::now() >= deadline {
+                        return Err(Error::Guardian(format!(
+                            "No {} document in block {}",
+                            document_type.as_deref().unwrap_or("matching"),
+                            block_id
+                        )));
+                    }
+                    tokio::time::sleep(GUARDIAN_POLL_INTERVAL).await;
+                };
+                context.insert(save_as, serde_json::to_value(document)?);
+            }
+            GuardianAction::RequestVc {
+                input,
+                reference,
+                save_as,
+            } => {
+                let submission = GuardianBlockSubmission {
+                    document: context.get(input)?.clone(),
+                    reference: reference.as_deref().map(|name| context.document(name)).transpose()?,
+                };
+                let response = self
+                    .post(&step.role, &block_id, serde_json::to_value(submission)?)
+                    .await?;
+                if let Some(save_as) = save_as {
+                    context.insert(save_as, response);
+                }
+            }
+            GuardianAction::Button {
+                button,
+                document,
+                save_as,
+            } => {
+                let response = self
+                    .press(&step.role, &block_id, button, context.get(document)?)
+                    .await?;
+                if let Some(save_as) = save_as {
+                    context.insert(save_as, response);
+                }
+            }
+            GuardianAction::Approve { document, button } | GuardianAction::Reject { document, button } => {
+                self.press(&step.role, &block_id, button, context.get(document)?)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the block of the step by tag, or by type from the block tree
+    async fn block_id(&self, step: &GuardianWorkflowStep) -> Result<String> {
+        let client = self.session.client();
+        let policy_id = self.config.policy_id.as_str();
+        let tree = match (&step.tag, &step.block_type) {
+            (Some(tag), None) => {
+                let block = self
+                    .session
+                    .call(&step.role, |token| async move {
+                        client.ref_block(&token, policy_id, tag).await
+                    })
+                    .await?;
+                return Ok(block.id);
+            }
+            (None, None) => return Err(Error::Guardian("Workflow step needs a tag or a block type".to_string())),
+            _ => {
+                self.session
+                    .call(
+                        &step.role,
+                        |token| async move { client.blocks(&token, policy_id).await },
+                    )
+                    .await?
+            }
+        };
+
+        let block = match (&step.tag, &step.block_type) {
+            (Some(tag), Some(block_type)) => tree.find_by_tag(tag).filter(|block| &block.block_type == block_type),
+            (_, Some(block_type)) => tree.blocks().into_iter().find(|block| &block.block_type == block_type),
+            _ => None,
+        };
+        block
+            .map(|block| block.id.clone())
+            .ok_or_else(|| Error::Guardian(format!("No block for {:?}/{:?}", step.tag, step.block_type)))
+    }
+
+    async fn block_data(&self, role: &GuardianRole, block_id: &str) -> Result<GuardianBlockData> {
+        let (client, policy_id) = (self.session.client(), self.config.policy_id.as_str());
+        self.session
+            .call(role, |token| async move {
+                client.get_block(&token, policy_id, block_id).await
+            })
+            .await
+    }
+
+    async fn post(&self, role: &GuardianRole, block_id: &str, data: Value) -> Result<Value> {
+        let (client, policy_id, data) = (self.session.client(), self.config.policy_id.as_str(), &data);
+        self.session
+            .call(role, |token| async move {
+                client.post_block(&token, policy_id, block_id, data).await
+            })
+            .await
+    }
+
+    async fn press(&self, role: &GuardianRole, block_id: &str, button: &str, document: &Value) -> Result<Value> {
+        self.post(role, block_id, json!({ "document": document, "tag": button }))
+            .await
+    }
+}
+
+fn select_document(
+    data: &GuardianBlockData,
+    document_type: Option<&str>,
+    filter: &HashMap<String, Value>,
+) -> Option<GuardianDocument> {
+    data.documents()
+        .iter()
+        .filter(|document| document_type.is_none() || document.document_type.as_deref() == document_type)
+        .find(|document| {
+            let json = serde_json::to_value(document).unwrap_or_default();
+            filter
+                .iter()
+                .all(|(pointer, expected)| json.pointer(pointer) == Some(expected))
+        })
+        .cloned()
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests {
+    use super::*;
+    use crate::{
+        clients::GuardianCredentials,
+        test_support::{MockGuardian, MockGuardianBlock, MockGuardianConfig, MockGuardianPolicy, MockGuardianUser},
+    };
+
+    #[tokio::test]
+    async fn test_verification_workflow() {
+        let report =
+            |id: &str| json!({ "id": id, "type": "report", "document": { "credentialSubject": [{ "field0": id }] } });
+        let grid = MockGuardianBlock::new("interfaceDocumentsSourceBlock", "vvb_grid")
+            .with_document(report("report-1"))
+            .with_document(report("report-2"));
+        let approve = MockGuardianBlock::new("buttonBlock", "approve_btn");
+        let policy = MockGuardianPolicy::new(
+            "verification",
+            MockGuardianBlock::new("interfaceContainerBlock", "root")
+                .with_child(grid)
+                .with_child(approve.clone()),
+        );
+        let guardian = MockGuardian::start(MockGuardianConfig {
+            users: vec![MockGuardianUser::new("vvb", "test", "USER")],
+            policies: vec![policy.clone()],
+        })
+        .await
+        .unwrap();
+
+        let config: GuardianWorkflowConfiguration = serde_json::from_value(json!({
+            "name": "verify_report",
+            "policy_id": policy.id,
+            "steps": [
+                {
+                    "role": "VVB",
+                    "block_type": "interfaceDocumentsSourceBlock",
+                    "action": "select_document",
+                    "document_type": "report",
+                    "filter": { "/document/credentialSubject/0/field0": "report-2" },
+                    "save_as": "report"
+                },
+                { "role": "VVB", "tag": "approve_btn", "action": "approve", "document": "report" }
+            ]
+        }))
+        .unwrap();
+
+        let session = GuardianSession::new(guardian.api_client())
+            .with_account(GuardianRole::Vvb, GuardianCredentials::new("vvb", "test"));
+        let context = GuardianWorkflow::new(session, config)
+            .run(HashMap::new())
+            .await
+            .unwrap();
+        assert_eq!(context.document("report").unwrap().id.as_deref(), Some("report-2"));
+
+        let posts = guardian.posts();
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].block_id, approve.id);
+        assert_eq!(posts[0].username, "vvb");
+        assert_eq!(posts[0].body["tag"], "Option_0");
+        assert_eq!(posts[0].body["document"]["id"], "report-2");
+    }
+}
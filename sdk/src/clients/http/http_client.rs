@@ -3,72 +3,100 @@
 
 //! The user manager that takes care of sending requests with healthy users and quorum if enabled
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use reqwest::RequestBuilder;
 use serde::de::DeserializeOwned;
 use serde_json::Value;
 use url::Url;
 
-use crate::errors::{ApiError, ApiResult as Result};
+use super::{
+    retry::{CircuitBreaker, RetryPolicy, is_retryable, retry_after},
+    transport::{HttpRequest, HttpResponse, HttpTransport, ReqwestTransport, TransportError},
+};
+use crate::{
+    errors::{ApiError, ApiResult as Result},
+    telemetry::Call,
+};
 
-pub(crate) struct Response(reqwest::Response);
+pub(crate) struct Response(HttpResponse);
 
 impl Response {
     pub(crate) fn status(&self) -> u16 {
-        self.0.status().as_u16()
+        self.0.status
+    }
+
+    pub(crate) fn header(&self, name: &str) -> Option<String> {
+        self.0.header(name).map(str::to_string)
     }
 
     pub(crate) async fn into_json<T: DeserializeOwned>(self) -> Result<T> {
-        self.0.json().await.map_err(Into::into)
+        serde_json::from_slice(&self.0.body).map_err(Into::into)
     }
 
     #[cfg(not(target_family = "wasm"))]
     pub(crate) async fn into_text(self) -> Result<String> {
-        self.0.text().await.map_err(Into::into)
+        String::from_utf8(self.0.body).map_err(|e| ApiError::Serde(e.to_string()))
     }
 
     #[allow(dead_code)]
     pub(crate) async fn into_bytes(self) -> Result<Vec<u8>> {
-        self.0.bytes().await.map(|b| b.to_vec()).map_err(Into::into)
+        Ok(self.0.body)
     }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct HttpClient {
-    client: reqwest::Client,
+    transport: Arc<dyn HttpTransport>,
     pub(crate) user_agent: String,
+    policy: RetryPolicy,
+    breaker: CircuitBreaker,
+    /// Whether requests are recorded as `http` calls
+    observed: bool,
 }
 
 impl Default for HttpClient {
     fn default() -> Self {
-        Self {
-            client: reqwest::Client::new(),
-            user_agent: "demia".to_string(),
-        }
+        Self::new("demia".to_string())
     }
 }
 
 impl HttpClient {
     pub(crate) fn new(user_agent: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            transport: Arc::new(ReqwestTransport::default()),
             user_agent,
+            policy: RetryPolicy::default(),
+            breaker: CircuitBreaker::default(),
+            observed: true,
         }
     }
 
-    async fn parse_response(response: reqwest::Response, url: &url::Url) -> Result<Response> {
-        let status = response.status();
-        if status.is_success() {
+    /// Leaves the metrics to the owning client, for clients that record their calls with more detail
+    pub(crate) fn unobserved(mut self) -> Self {
+        self.observed = false;
+        self
+    }
+
+    pub(crate) fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    pub(crate) fn set_transport(&mut self, transport: Arc<dyn HttpTransport>) {
+        self.transport = transport;
+    }
+
+    fn parse_response(response: HttpResponse, url: &Url) -> Result<Response> {
+        let status = response.status;
+        if (200..300).contains(&status) {
             Ok(Response(response))
         } else {
-            let text = response.text().await?;
+            let text = String::from_utf8_lossy(&response.body).to_string();
 
-            if status.as_u16() == 404 {
+            if status == 404 {
                 Err(ApiError::NotFound(url.to_string()))
             } else {
                 Err(ApiError::ResponseError {
-                    code: status.as_u16(),
+                    code: status,
                     text,
                     url: url.to_string(),
                 })
@@ -76,58 +104,244 @@ impl HttpClient {
         }
     }
 
-    fn build_request(
-        &self,
-        request_builder: RequestBuilder,
-        bearer: &str,
-        _timeout: Duration,
-    ) -> Result<RequestBuilder> {
-        let mut request_builder = request_builder.header(reqwest::header::USER_AGENT, &self.user_agent);
+    fn build_request(&self, method: &str, url: &Url, bearer: &str, timeout: Duration) -> HttpRequest {
+        let request = HttpRequest::new(method, url.clone(), timeout).header("user-agent", self.user_agent.as_str());
+
+        // Login endpoints are called without a token
+        match bearer.is_empty() {
+            true => request,
+            false => request.header("authorization", format!("Bearer {}", bearer)),
+        }
+    }
+
+    /// Sends the request in a span, recording its latency and failures
+    async fn execute(&self, request: HttpRequest) -> Result<Response> {
+        if !self.observed {
+            return self.send(request).await;
+        }
+        Call::new("http", request.method.as_str())
+            .with("host", request.url.host_str().unwrap_or_default())
+            .observe(self.send(request))
+            .await
+    }
+
+    /// Sends the request, retrying transient failures with backoff.
+    ///
+    /// Idempotent requests are retried on connection errors, timeouts and 429/502/503/504 responses, honouring
+    /// `Retry-After`. Other requests are only retried when the connection failed, so they never reached the server.
+    /// Every request passes the circuit breaker of its host first.
+    async fn send(&self, request: HttpRequest) -> Result<Response> {
+        let url = request.url.clone();
+        let idempotent = request.is_idempotent();
+        let host = format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or_default()
+        );
+
+        let mut attempt = 0;
+        loop {
+            self.breaker.acquire(&host, &self.policy)?;
+            let start_time = tokio::time::Instant::now();
 
-        request_builder = request_builder.bearer_auth(bearer);
+            let delay = match self.transport.send(request.clone()).await {
+                Ok(response) => {
+                    let status = response.status;
+                    log::debug!(
+                        "{}: {:?} ms for {} {}",
+                        request.method,
+                        start_time.elapsed().as_millis(),
+                        status,
+                        &url
+                    );
+                    // A 429 counts against the host like a 503, or a storm of them would keep the circuit closed
+                    self.breaker
+                        .record(&host, status < 500 && !is_retryable(status), &self.policy);
 
-        #[cfg(not(target_family = "wasm"))]
-        {
-            request_builder = request_builder.timeout(_timeout);
+                    if !idempotent || !is_retryable(status) || attempt >= self.policy.max_retries {
+                        return Self::parse_response(response, &url);
+                    }
+                    match response.header("retry-after").and_then(retry_after) {
+                        Some(delay) if delay > self.policy.max_backoff => {
+                            return Self::parse_response(response, &url);
+                        }
+                        Some(delay) => delay,
+                        None => self.policy.backoff(attempt),
+                    }
+                }
+                Err(e) => {
+                    self.breaker.record(&host, false, &self.policy);
+                    let retryable = match e {
+                        TransportError::Connect(_) => true,
+                        TransportError::Timeout(_) => idempotent,
+                        TransportError::Other(_) => false,
+                    };
+                    if !retryable || attempt >= self.policy.max_retries {
+                        return Err(match e {
+                            TransportError::Connect(e) => ApiError::Unreachable(e),
+                            e => ApiError::ReqwestError(e.to_string()),
+                        });
+                    }
+                    self.policy.backoff(attempt)
+                }
+            };
+
+            attempt += 1;
+            log::warn!("Retrying {} in {:?}, attempt {}", url, delay, attempt);
+            tokio::time::sleep(delay).await;
         }
-        Ok(request_builder)
     }
 
     #[allow(dead_code)]
     pub(crate) async fn get(&self, url: Url, bearer: &str, timeout: Duration) -> Result<Response> {
-        let mut request_builder = self.client.get(url.clone());
-        request_builder = self.build_request(request_builder, bearer, timeout)?;
-        let start_time = tokio::time::Instant::now();
-        let resp = request_builder.send().await?;
-        log::debug!(
-            "GET: {:?} ms for {} {}",
-            start_time.elapsed().as_millis(),
-            resp.status(),
-            &url
-        );
-        Self::parse_response(resp, &url).await
+        self.execute(self.build_request("GET", &url, bearer, timeout)).await
     }
 
     // Get with header: "accept", "application/vnd.iota.serializer-v2"
     pub(crate) async fn get_bytes(&self, url: Url, bearer: &str, timeout: Duration) -> Result<Response> {
-        let mut request_builder = self.client.get(url.clone());
-        request_builder = self.build_request(request_builder, bearer, timeout)?;
-        request_builder = request_builder.header("accept", "application/vnd.demia.serializer-v2");
-        let resp = request_builder.send().await?;
-        Self::parse_response(resp, &url).await
+        let request = self
+            .build_request("GET", &url, bearer, timeout)
+            .header("accept", "application/vnd.demia.serializer-v2");
+        self.execute(request).await
     }
 
     pub(crate) async fn post_json(&self, url: Url, bearer: &str, timeout: Duration, json: Value) -> Result<Response> {
-        let mut request_builder = self.client.post(url.clone());
-        request_builder = self.build_request(request_builder, bearer, timeout)?;
-        Self::parse_response(request_builder.json(&json).send().await?, &url).await
+        self.execute(self.build_request("POST", &url, bearer, timeout).json(&json))
+            .await
     }
 
     #[allow(dead_code)]
     pub(crate) async fn post_bytes(&self, url: Url, bearer: &str, timeout: Duration, body: &[u8]) -> Result<Response> {
-        let mut request_builder = self.client.post(url.clone());
-        request_builder = self.build_request(request_builder, bearer, timeout)?;
-        request_builder = request_builder.header("Content-Type", "application/vnd.demia.serializer-v2");
-        Self::parse_response(request_builder.body(body.to_vec()).send().await?, &url).await
+        let request = self
+            .build_request("POST", &url, bearer, timeout)
+            .header("content-type", "application/vnd.demia.serializer-v2")
+            .body(body.to_vec());
+        self.execute(request).await
+    }
+
+    pub(crate) async fn put_json(&self, url: Url, bearer: &str, timeout: Duration, json: Value) -> Result<Response> {
+        self.execute(self.build_request("PUT", &url, bearer, timeout).json(&json))
+            .await
+    }
+
+    pub(crate) async fn patch_json(&self, url: Url, bearer: &str, timeout: Duration, json: Value) -> Result<Response> {
+        self.execute(self.build_request("PATCH", &url, bearer, timeout).json(&json))
+            .await
+    }
+
+    /// Deletes, with a json body for APIs that take one (e.g. Keycloak role mappings)
+    pub(crate) async fn delete(
+        &self,
+        url: Url,
+        bearer: &str,
+        timeout: Duration,
+        json: Option<Value>,
+    ) -> Result<Response> {
+        let request = self.build_request("DELETE", &url, bearer, timeout);
+        match json {
+            Some(json) => self.execute(request.json(&json)).await,
+            None => self.execute(request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::clients::MockTransport;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn client(transport: Arc<MockTransport>, policy: RetryPolicy) -> HttpClient {
+        let mut client = HttpClient::default().unobserved();
+        client.set_transport(transport);
+        client.set_retry_policy(RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..policy
+        });
+        client
+    }
+
+    fn url() -> Url {
+        Url::parse("http://retry.test/status").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_is_retried() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let transport =
+            Arc::new(MockTransport::new().on(
+                "GET",
+                "/status",
+                move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => HttpResponse::new(503, Vec::new()),
+                    _ => HttpResponse::json(200, &serde_json::json!({ "ok": true })),
+                },
+            ));
+        let client = client(transport.clone(), RetryPolicy::default());
+
+        let response = client.get(url(), "", TIMEOUT).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_post_is_not_retried() {
+        let transport = Arc::new(MockTransport::new().on_json("POST", "/status", 503, Value::Null));
+        let client = client(transport.clone(), RetryPolicy::default());
+
+        let result = client.post_json(url(), "", TIMEOUT, Value::Null).await;
+        assert!(matches!(result, Err(ApiError::ResponseError { code: 503, .. })));
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_long_retry_after_is_not_waited_for() {
+        let transport = Arc::new(MockTransport::new().on("GET", "/status", |_| {
+            let mut response = HttpResponse::new(503, Vec::new());
+            response.headers.insert("retry-after".to_string(), "3600".to_string());
+            response
+        }));
+        let client = client(
+            transport.clone(),
+            RetryPolicy {
+                max_backoff: Duration::from_secs(1),
+                ..Default::default()
+            },
+        );
+
+        let start = tokio::time::Instant::now();
+        let result = client.get(url(), "", TIMEOUT).await;
+        assert!(matches!(result, Err(ApiError::ResponseError { code: 503, .. })));
+        assert_eq!(transport.requests().len(), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_requests_open_the_circuit() {
+        let transport = Arc::new(MockTransport::new().on_json("GET", "/status", 429, Value::Null));
+        let client = client(
+            transport.clone(),
+            RetryPolicy {
+                max_retries: 0,
+                failure_threshold: 2,
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..2 {
+            assert!(matches!(
+                client.get(url(), "", TIMEOUT).await,
+                Err(ApiError::ResponseError { code: 429, .. })
+            ));
+        }
+        assert!(matches!(
+            client.get(url(), "", TIMEOUT).await,
+            Err(ApiError::CircuitOpen(_))
+        ));
+        assert_eq!(transport.requests().len(), 2);
     }
 }
@@ -1,12 +1,28 @@
 mod api;
+#[cfg(any(test, feature = "test-support"))]
+mod fixture;
 mod guardian;
+mod guardian_outbox;
+mod guardian_session;
+mod guardian_verifier;
+mod guardian_workflow;
 mod http_client;
 mod retriever;
+mod retry;
+mod transport;
 
 pub use api::ApiClient;
+#[cfg(any(test, feature = "test-support"))]
+pub use fixture::{Fixture, FixtureTransport, MockTransport};
 pub use guardian::{GuardianApiClient, GuardianClient};
+pub use guardian_outbox::{FileOutboxStore, GuardianOutbox, OutboxEntry, OutboxStatus, OutboxStore, idempotency_key};
+pub use guardian_session::{GuardianCredentials, GuardianSession};
+pub use guardian_verifier::{GuardianVerifier, JcsCanonicalizer, ProofCanonicalizer};
+pub use guardian_workflow::{GuardianWorkflow, GuardianWorkflowContext};
 pub(crate) use http_client::*;
 pub use retriever::RetrieverApi;
+pub use retry::RetryPolicy;
+pub use transport::{HttpRequest, HttpResponse, HttpTransport, ReqwestTransport, TransportError};
 
 pub(crate) fn query_tuples_to_query_string(
     tuples: impl IntoIterator<Item = Option<(&'static str, String)>>,
@@ -1,14 +1,18 @@
-use std::time::Duration;
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
+use chrono::SecondsFormat;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Value, json};
 use streams::Address;
 use url::Url;
 
-use super::HttpClient;
+use super::{HttpClient, HttpTransport, RetryPolicy, query_tuples_to_query_string};
 use crate::{
-    configuration::BaseConfiguration,
-    errors::{ApiError, ApiResult},
+    errors::{ApiError, ApiResult, SdkResult},
+    models::{
+        RetrievedMessage, RetrievedMessagesQuery, RetrieverAddressSync, RetrieverAddresses, RetrieverRegistration,
+        RetrieverSyncStatus, RetrieverUser, UserIdentity,
+    },
     utils::{API_TIMEOUT, RETRIEVER_API},
 };
 
@@ -39,46 +43,230 @@ impl RetrieverApi {
         })
     }
 
-    // TODO: Make this... cleaner we have a token we can use to fetch!
-    pub async fn add_retriever_new_user(&self, bearer: &str, config: &BaseConfiguration) -> ApiResult<Value> {
-        let path = "new-user";
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.http_client.set_retry_policy(policy);
+    }
+
+    pub fn set_transport(&mut self, transport: Arc<dyn HttpTransport>) {
+        self.http_client.set_transport(transport);
+    }
 
+    pub(crate) fn get_timeout() -> Duration {
+        API_TIMEOUT
+    }
+
+    /// Appends the path segments to the url of the retriever, keeping any path it is served under
+    fn endpoint(&self, segments: &[&str], query: Option<&str>) -> ApiResult<Url> {
         let mut url = self.url.clone();
-        url.set_path(path);
+        url.path_segments_mut()
+            .map_err(|_| ApiError::NotFound(self.url.to_string()))?
+            .pop_if_empty()
+            .extend(segments);
+        url.set_query(query);
+        Ok(url)
+    }
 
-        let json = serde_json::to_value(config)?;
+    /// Registers the user, with the addresses in the registration if any
+    pub async fn add_retriever_new_user(
+        &self,
+        bearer: &str,
+        registration: &RetrieverRegistration,
+    ) -> ApiResult<RetrieverUser> {
+        let json = serde_json::to_value(registration)?;
         let res = self
             .http_client
-            .post_json(url, bearer, RetrieverApi::get_timeout(), json)
+            .post_json(self.endpoint(&["new-user"], None)?, bearer, Self::get_timeout(), json)
             .await?;
         res.into_json().await
     }
 
-    pub(crate) fn get_timeout() -> Duration {
-        API_TIMEOUT
-    }
-
     pub async fn add_retriever_user_address(
         &self,
         bearer: &str,
         user_id: String,
         address: &Address,
     ) -> ApiResult<Value> {
-        let path = "add-address";
+        self.add_address(bearer, &user_id, &address.to_string()).await
+    }
 
-        let mut url = self.url.clone();
-        url.set_path(path);
+    pub async fn remove_retriever_user_address(
+        &self,
+        bearer: &str,
+        user_id: &str,
+        address: &Address,
+    ) -> ApiResult<Value> {
+        self.remove_address(bearer, user_id, &address.to_string()).await
+    }
 
-        let json = serde_json::json!({
-            "id": user_id,
-            "address": address.to_string(),
-        });
+    async fn add_address(&self, bearer: &str, user_id: &str, address: &str) -> ApiResult<Value> {
+        let json = json!({ "id": user_id, "address": address });
+        let res = self
+            .http_client
+            .post_json(
+                self.endpoint(&["add-address"], None)?,
+                bearer,
+                Self::get_timeout(),
+                json,
+            )
+            .await?;
+        res.into_json().await
+    }
 
+    async fn remove_address(&self, bearer: &str, user_id: &str, address: &str) -> ApiResult<Value> {
+        let json = json!({ "id": user_id, "address": address });
         let res = self
             .http_client
-            .post_json(url, bearer, Self::get_timeout(), json)
+            .post_json(
+                self.endpoint(&["remove-address"], None)?,
+                bearer,
+                Self::get_timeout(),
+                json,
+            )
             .await?;
-        print!("code: {}", &res.status());
         res.into_json().await
     }
+
+    /// The addresses the retriever follows for the user
+    pub async fn user_addresses(&self, bearer: &str, user_id: &str) -> ApiResult<RetrieverAddresses> {
+        let url = self.endpoint(&["addresses", user_id], None)?;
+        let res = self.http_client.get(url, bearer, Self::get_timeout()).await?;
+        res.into_json().await
+    }
+
+    /// Messages the retriever read from the stream at the address
+    pub async fn messages(
+        &self,
+        bearer: &str,
+        address: &Address,
+        query: &RetrievedMessagesQuery,
+    ) -> ApiResult<Vec<RetrievedMessage>> {
+        let query = query_tuples_to_query_string([
+            Some(("address", address.to_string())),
+            query
+                .since
+                .map(|since| ("since", since.to_rfc3339_opts(SecondsFormat::Millis, true))),
+            query.limit.map(|limit| ("limit", limit.to_string())),
+        ]);
+        let res = self
+            .http_client
+            .get(
+                self.endpoint(&["messages"], query.as_deref())?,
+                bearer,
+                Self::get_timeout(),
+            )
+            .await?;
+        res.into_json().await
+    }
+
+    pub async fn sync_status(&self, bearer: &str, user_id: &str) -> ApiResult<RetrieverSyncStatus> {
+        let url = self.endpoint(&["sync-status", user_id], None)?;
+        let res = self.http_client.get(url, bearer, Self::get_timeout()).await?;
+        res.into_json().await
+    }
+
+    /// Stores the address in the identity's vault and has the retriever follow it
+    pub async fn track_address(
+        &self,
+        bearer: &str,
+        user_id: &str,
+        identity: &UserIdentity,
+        address: &Address,
+    ) -> SdkResult<()> {
+        identity.store_streams_address(address.clone()).await?;
+        self.add_address(bearer, user_id, &address.to_string()).await?;
+        Ok(())
+    }
+
+    /// Stops the retriever following the address and removes it from the identity's vault
+    pub async fn untrack_address(
+        &self,
+        bearer: &str,
+        user_id: &str,
+        identity: &UserIdentity,
+        address: &Address,
+    ) -> SdkResult<()> {
+        self.remove_address(bearer, user_id, &address.to_string()).await?;
+        identity.remove_streams_address(address).await?;
+        Ok(())
+    }
+
+    /// Makes the retriever follow exactly the addresses vaulted in the identity
+    pub async fn sync_addresses(
+        &self,
+        bearer: &str,
+        user_id: &str,
+        identity: &UserIdentity,
+    ) -> SdkResult<RetrieverAddressSync> {
+        let vaulted = identity.vaulted_streams_addresses().await?.0;
+        let followed = self
+            .user_addresses(bearer, user_id)
+            .await?
+            .addresses
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        let mut sync = RetrieverAddressSync::default();
+        for address in vaulted.difference(&followed) {
+            self.add_address(bearer, user_id, address).await?;
+            sync.added.push(address.clone());
+        }
+        for address in followed.difference(&vaulted) {
+            self.remove_address(bearer, user_id, address).await?;
+            sync.removed.push(address.clone());
+        }
+        Ok(sync)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clients::MockTransport, configuration::BaseConfiguration};
+
+    #[tokio::test]
+    async fn test_register_and_status() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on_json(
+                    "POST",
+                    "/retriever/new-user",
+                    200,
+                    json!({ "id": "user", "username": "demia", "addresses": ["a:0"] }),
+                )
+                .on_json(
+                    "GET",
+                    "/retriever/sync-status/user",
+                    200,
+                    json!({ "id": "user", "syncing": true, "addresses": [{ "address": "a:0", "messages": 3 }] }),
+                ),
+        );
+        let mut retriever = RetrieverApi::new("http://retriever.test/retriever").unwrap();
+        retriever.set_transport(transport.clone());
+
+        let mut config = BaseConfiguration::default();
+        config.application.username = "demia".to_string();
+        config.stronghold.password = "secret".to_string();
+        let registration = RetrieverRegistration::from_config("user", &config).with_addresses(["a:0".to_string()]);
+        let user = retriever.add_retriever_new_user("token", &registration).await.unwrap();
+        assert_eq!(user.addresses, vec!["a:0".to_string()]);
+
+        let status = retriever.sync_status("token", "user").await.unwrap();
+        assert_eq!(status.addresses[0].messages, 3);
+
+        let requests = transport.requests();
+        let body: Value = serde_json::from_slice(requests[0].body.as_ref().unwrap()).unwrap();
+        // The configuration fields the retriever reads, without the password
+        assert_eq!(body["application"]["username"], "demia");
+        assert_eq!(body["stronghold"]["password"], "");
+        assert_eq!(body["addresses"], json!(["a:0"]));
+        assert_eq!(requests[1].url.path(), "/retriever/sync-status/user");
+    }
+
+    #[test]
+    fn test_endpoint_escapes_ids() {
+        let retriever = RetrieverApi::new("http://retriever.test/retriever/").unwrap();
+        let url = retriever.endpoint(&["addresses", "a/b?c"], None).unwrap();
+        assert_eq!(url.path(), "/retriever/addresses/a%2Fb%3Fc");
+        assert_eq!(url.query(), None);
+    }
 }
@@ -0,0 +1,254 @@
+//! Retries with exponential backoff and a per-host circuit breaker, shared by the http clients
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+use crate::errors::{ApiError, ApiResult as Result};
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    /// Upper bound of the backoff. A `Retry-After` longer than this is not waited for
+    pub max_backoff: Duration,
+    /// Consecutive failures after which the circuit of a host opens
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects requests before letting a trial request through
+    pub open_duration: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(10),
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Sends every request once and never opens the circuit
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            failure_threshold: u32::MAX,
+            ..Default::default()
+        }
+    }
+
+    /// Exponential backoff with jitter, between half and the full delay of the attempt
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// Statuses worth retrying, the request was not processed or the server asked to come back later
+pub(crate) fn is_retryable(status: u16) -> bool {
+    matches!(status, 429 | 502 | 503 | 504)
+}
+
+/// Delay requested in a `Retry-After` header, in seconds or as an http date
+pub(crate) fn retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[derive(Debug, Default)]
+struct HostState {
+    failures: u32,
+    open_until: Option<Instant>,
+    trial_started: Option<Instant>,
+}
+
+/// Tracks consecutive failures per host. Clones share the state
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CircuitBreaker {
+    hosts: Arc<Mutex<HashMap<String, HostState>>>,
+}
+
+impl CircuitBreaker {
+    /// Errors while the circuit of the host is open. Once the open period has passed a single trial request is let
+    /// through, its outcome closes or re-opens the circuit
+    pub(crate) fn acquire(&self, host: &str, policy: &RetryPolicy) -> Result<()> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_string()).or_default();
+        let now = Instant::now();
+        match state.open_until {
+            None => Ok(()),
+            Some(until) if now < until => Err(ApiError::CircuitOpen(host.to_string())),
+            // A trial that never reported back (e.g. a dropped future) expires after another open period
+            Some(_)
+                if state
+                    .trial_started
+                    .is_some_and(|started| now.duration_since(started) < policy.open_duration) =>
+            {
+                Err(ApiError::CircuitOpen(host.to_string()))
+            }
+            Some(_) => {
+                state.trial_started = Some(now);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn record(&self, host: &str, success: bool, policy: &RetryPolicy) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_string()).or_default();
+        if success {
+            if state.open_until.is_some() {
+                log::info!("Circuit for {} closed", host);
+            }
+            *state = HostState::default();
+            return;
+        }
+
+        state.failures = state.failures.saturating_add(1);
+        state.trial_started = None;
+        if state.failures >= policy.failure_threshold {
+            if state.open_until.is_none() {
+                log::warn!("Circuit for {} opened after {} failures", host, state.failures);
+            }
+            state.open_until = Some(Instant::now() + policy.open_duration);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(open_duration: Duration) -> RetryPolicy {
+        RetryPolicy {
+            failure_threshold: 2,
+            open_duration,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            ..Default::default()
+        };
+        // 100, 200, 400 and then capped at 500ms, each with up to half of it taken off as jitter
+        for attempt in 0..40 {
+            let expected = Duration::from_millis(100 * 2u64.pow(attempt.min(3))).min(Duration::from_millis(500));
+            let backoff = policy.backoff(attempt);
+            assert!(backoff >= expected / 2, "attempt {}: {:?}", attempt, backoff);
+            assert!(backoff <= expected, "attempt {}: {:?}", attempt, backoff);
+        }
+        // Large attempts saturate instead of overflowing
+        assert!(policy.backoff(u32::MAX) <= policy.max_backoff);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(" 5 "), Some(Duration::from_secs(5)));
+        assert_eq!(retry_after("soon"), None);
+        assert_eq!(retry_after("-1"), None);
+
+        // Dates in the past mean no wait
+        assert_eq!(retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        let later = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        let delay = retry_after(&later).unwrap();
+        assert!(
+            delay > Duration::from_secs(55) && delay <= Duration::from_secs(60),
+            "{:?}",
+            delay
+        );
+    }
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let (breaker, policy) = (CircuitBreaker::default(), policy(Duration::from_secs(60)));
+
+        breaker.record("a.test", false, &policy);
+        assert!(breaker.acquire("a.test", &policy).is_ok());
+        breaker.record("a.test", false, &policy);
+        assert!(matches!(
+            breaker.acquire("a.test", &policy),
+            Err(ApiError::CircuitOpen(host)) if host == "a.test"
+        ));
+        // Hosts are tracked separately
+        assert!(breaker.acquire("b.test", &policy).is_ok());
+
+        // A success before the threshold resets the count
+        breaker.record("b.test", false, &policy);
+        breaker.record("b.test", true, &policy);
+        breaker.record("b.test", false, &policy);
+        assert!(breaker.acquire("b.test", &policy).is_ok());
+    }
+
+    #[test]
+    fn test_breaker_half_open() {
+        let (breaker, policy) = (CircuitBreaker::default(), policy(Duration::from_millis(20)));
+        breaker.record("a.test", false, &policy);
+        breaker.record("a.test", false, &policy);
+        assert!(breaker.acquire("a.test", &policy).is_err());
+
+        // After the open period a single trial goes through
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.acquire("a.test", &policy).is_ok());
+        assert!(breaker.acquire("a.test", &policy).is_err());
+
+        // A failed trial opens the circuit again
+        breaker.record("a.test", false, &policy);
+        assert!(breaker.acquire("a.test", &policy).is_err());
+
+        // A successful trial closes it
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.acquire("a.test", &policy).is_ok());
+        breaker.record("a.test", true, &policy);
+        assert!(breaker.acquire("a.test", &policy).is_ok());
+        assert!(breaker.acquire("a.test", &policy).is_ok());
+    }
+
+    #[test]
+    fn test_abandoned_trial_expires() {
+        let (breaker, policy) = (CircuitBreaker::default(), policy(Duration::from_millis(20)));
+        breaker.record("a.test", false, &policy);
+        breaker.record("a.test", false, &policy);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.acquire("a.test", &policy).is_ok());
+        // The trial never reports back, another one is allowed after an open period
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.acquire("a.test", &policy).is_ok());
+    }
+
+    #[test]
+    fn test_none_never_opens() {
+        let (breaker, policy) = (CircuitBreaker::default(), RetryPolicy::none());
+        for _ in 0..100 {
+            breaker.record("a.test", false, &policy);
+        }
+        assert!(breaker.acquire("a.test", &policy).is_ok());
+        assert!(is_retryable(503) && is_retryable(429) && !is_retryable(500) && !is_retryable(404));
+    }
+}
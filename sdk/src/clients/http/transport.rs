@@ -0,0 +1,150 @@
+use std::{collections::BTreeMap, fmt::Debug, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// A request as handed to a [`HttpTransport`]. Header names are lowercase
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: Url,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<Vec<u8>>,
+    pub timeout: Duration,
+}
+
+impl HttpRequest {
+    pub fn new(method: &str, url: Url, timeout: Duration) -> Self {
+        Self {
+            method: method.to_string(),
+            url,
+            headers: BTreeMap::new(),
+            body: None,
+            timeout,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.insert(name.to_lowercase(), value.into());
+        self
+    }
+
+    pub fn json(self, json: &serde_json::Value) -> Self {
+        let mut request = self.header("content-type", "application/json");
+        request.body = Some(json.to_string().into_bytes());
+        request
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// GET, HEAD, PUT and DELETE can be repeated without changing the outcome
+    pub fn is_idempotent(&self) -> bool {
+        matches!(self.method.as_str(), "GET" | "HEAD" | "PUT" | "DELETE")
+    }
+}
+
+/// A fully read response. Header names are lowercase
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            headers: BTreeMap::new(),
+            body,
+        }
+    }
+
+    pub fn json(status: u16, json: &serde_json::Value) -> Self {
+        let mut response = Self::new(status, json.to_string().into_bytes());
+        response
+            .headers
+            .insert("content-type".to_string(), "application/json".to_string());
+        response
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+/// Why a request got no response, which decides whether it may be retried
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum TransportError {
+    /// No connection was made, the request never reached the server
+    #[error("Connection failed: {0}")]
+    Connect(String),
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+    #[error("Transport error: {0}")]
+    Other(String),
+}
+
+impl From<reqwest::Error> for TransportError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_connect() {
+            Self::Connect(error.to_string())
+        } else if error.is_timeout() {
+            Self::Timeout(error.to_string())
+        } else {
+            Self::Other(error.to_string())
+        }
+    }
+}
+
+/// Sends requests for the http clients. Implement it to run the clients against fixtures, an in-process mock or a
+/// differently configured client
+#[async_trait::async_trait]
+pub trait HttpTransport: Debug + Send + Sync {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, TransportError>;
+}
+
+/// The default transport. Build it from a configured `reqwest::Client` for proxies or mTLS
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait::async_trait]
+impl HttpTransport for ReqwestTransport {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, TransportError> {
+        let method =
+            reqwest::Method::from_bytes(request.method.as_bytes()).map_err(|e| TransportError::Other(e.to_string()))?;
+        let mut builder = self.client.request(method, request.url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+        #[cfg(not(target_family = "wasm"))]
+        {
+            builder = builder.timeout(request.timeout);
+        }
+
+        let response = builder.send().await?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let body = response.bytes().await?.to_vec();
+
+        Ok(HttpResponse { status, headers, body })
+    }
+}
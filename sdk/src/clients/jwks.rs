@@ -0,0 +1,279 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::{
+    Algorithm, DecodingKey,
+    jwk::{AlgorithmParameters, Jwk, JwkSet, PublicKeyUse},
+};
+use serde_json::Value;
+
+use crate::{
+    errors::{SecretError, SecretResult},
+    utils::{JWKS_CACHE_TTL, JWKS_MIN_REFETCH},
+};
+
+/// Signing keys of an issuer, downloaded from its JWKS endpoint and kept for [`JWKS_CACHE_TTL`].
+/// Clones share the cached keys.
+#[derive(Default, Clone)]
+pub(crate) struct JwksCache {
+    keys: Arc<RwLock<Option<(Instant, JwkSet)>>>,
+}
+
+impl JwksCache {
+    /// The key named by `kid`, or the only signing key when the token names none. A `kid` missing from the cached
+    /// set may come from a key rotation, so the JWKS is fetched again, at most once per [`JWKS_MIN_REFETCH`]
+    pub(crate) async fn jwk(&self, client: &reqwest::Client, jwks_url: &str, kid: Option<&str>) -> SecretResult<Jwk> {
+        let cached = self
+            .keys
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(fetched, _)| fetched.elapsed() < JWKS_CACHE_TTL)
+            .map(|(fetched, keys)| (fetched.elapsed(), find_key(keys, kid)));
+        match cached {
+            Some((_, Some(jwk))) => return Ok(jwk),
+            Some((age, None)) if age < JWKS_MIN_REFETCH => return Err(unknown_key(kid)),
+            _ => {}
+        }
+
+        let keys = self.fetch(client, jwks_url).await?;
+        find_key(&keys, kid).ok_or_else(|| unknown_key(kid))
+    }
+
+    async fn fetch(&self, client: &reqwest::Client, jwks_url: &str) -> SecretResult<JwkSet> {
+        log::debug!("Fetching jwks from {}", jwks_url);
+        let keys: JwkSet = client
+            .get(jwks_url)
+            .send()
+            .await
+            .map_err(|_| SecretError::Jwt("couldn't query jwks".to_string()))?
+            .json()
+            .await
+            .map_err(|_| SecretError::Jwt("couldn't convert jwk response to json".to_string()))?;
+        self.keys.write().unwrap().replace((Instant::now(), keys.clone()));
+        Ok(keys)
+    }
+
+    /// Drops the cached keys if they were fetched more than `age` ago. Returns whether they were dropped
+    fn invalidate_older_than(&self, age: Duration) -> bool {
+        let mut keys = self.keys.write().unwrap();
+        match &*keys {
+            Some((fetched, _)) if fetched.elapsed() < age => false,
+            _ => keys.take().is_some(),
+        }
+    }
+}
+
+fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None => {
+            let mut signing = keys
+                .keys
+                .iter()
+                .filter(|jwk| !matches!(jwk.common.public_key_use, Some(PublicKeyUse::Encryption)));
+            let jwk = signing.next()?;
+            signing.next().is_none().then(|| jwk.clone())
+        }
+    }
+}
+
+fn unknown_key(kid: Option<&str>) -> SecretError {
+    match kid {
+        Some(kid) => SecretError::Jwt(format!("No jwk with kid {}", kid)),
+        None => SecretError::Jwt("Token has no kid and the jwks holds more than one signing key".to_string()),
+    }
+}
+
+/// Asymmetric algorithms a key can verify. The algorithm in the token header is only trusted within these, so a token
+/// can't pick HMAC with the public key as the secret
+fn algorithms(jwk: &Jwk) -> SecretResult<Vec<Algorithm>> {
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => Ok(vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ]),
+        AlgorithmParameters::EllipticCurve(_) => Ok(vec![Algorithm::ES256, Algorithm::ES384]),
+        _ => Err(SecretError::Jwt("Unsupported jwk key type".to_string())),
+    }
+}
+
+fn decode(
+    token: &str,
+    jwk: &Jwk,
+    validation: &jsonwebtoken::Validation,
+) -> jsonwebtoken::errors::Result<jsonwebtoken::TokenData<Value>> {
+    let key = DecodingKey::from_jwk(jwk)?;
+    jsonwebtoken::decode::<Value>(token, &key, validation)
+}
+
+/// Decodes with the cached key named in the token header. The algorithms of `validation` are replaced by those of
+/// the key type. A signature mismatch may be caused by a key rotation, so the JWKS is fetched again, at most once per
+/// [`JWKS_MIN_REFETCH`] to keep forged tokens from hammering the issuer.
+pub(crate) async fn decode_with_cache(
+    cache: &JwksCache,
+    client: &reqwest::Client,
+    jwks_url: &str,
+    token: &str,
+    validation: &jsonwebtoken::Validation,
+) -> SecretResult<jsonwebtoken::TokenData<Value>> {
+    let header = jsonwebtoken::decode_header(token).map_err(|e| SecretError::Jwt(e.to_string()))?;
+    let kid = header.kid.as_deref();
+    let jwk = cache.jwk(client, jwks_url, kid).await?;
+    let mut validation = validation.clone();
+    validation.algorithms = algorithms(&jwk)?;
+
+    match decode(token, &jwk, &validation) {
+        Err(e)
+            if matches!(e.kind(), jsonwebtoken::errors::ErrorKind::InvalidSignature)
+                && cache.invalidate_older_than(JWKS_MIN_REFETCH) =>
+        {
+            let jwk = cache.jwk(client, jwks_url, kid).await?;
+            validation.algorithms = algorithms(&jwk)?;
+            decode(token, &jwk, &validation).map_err(|e| SecretError::Jwt(e.to_string()))
+        }
+        result => result.map_err(|e| SecretError::Jwt(e.to_string())),
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{Json, Router, routing::get};
+    use jsonwebtoken::{EncodingKey, Header, Validation};
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::{MockClaims, MockKeyAlgorithm, MockOidcConfig, MockOidcIssuer};
+
+    async fn issuer(algorithm: MockKeyAlgorithm, key_id: &str) -> MockOidcIssuer {
+        MockOidcIssuer::start(MockOidcConfig {
+            algorithm,
+            key_id: key_id.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Serves the keys of several issuers from one JWKS endpoint and counts the requests
+    async fn serve_jwks(issuers: &[&MockOidcIssuer]) -> (String, Arc<AtomicUsize>) {
+        let keys: Vec<Value> = issuers
+            .iter()
+            .flat_map(|issuer| issuer.jwks()["keys"].as_array().cloned().unwrap_or_default())
+            .collect();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let router = Router::new().route(
+            "/jwks",
+            get(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let keys = keys.clone();
+                async move { Json(json!({ "keys": keys })) }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/jwks", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (url, fetches)
+    }
+
+    fn validation() -> Validation {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.validate_aud = false;
+        validation
+    }
+
+    fn claims(sub: &str) -> MockClaims {
+        MockClaims {
+            sub: sub.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_selects_key_by_kid() {
+        let ec = issuer(MockKeyAlgorithm::Es256, "ec-key").await;
+        let rsa = issuer(MockKeyAlgorithm::Rs256, "rsa-key").await;
+        // The RSA key is not the first one of the set
+        let (url, fetches) = serve_jwks(&[&ec, &rsa]).await;
+        let (cache, client) = (JwksCache::default(), reqwest::Client::new());
+
+        for (issuer, sub) in [(&rsa, "rsa-user"), (&ec, "ec-user")] {
+            let token = issuer.mint_token(&claims(sub));
+            let data = decode_with_cache(&cache, &client, &url, &token, &validation())
+                .await
+                .unwrap();
+            assert_eq!(data.claims["sub"], json!(sub));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_kid_refetch_is_limited() {
+        let known = issuer(MockKeyAlgorithm::Rs256, "known").await;
+        let unknown = issuer(MockKeyAlgorithm::Rs256, "unknown").await;
+        let (url, fetches) = serve_jwks(&[&known]).await;
+        let (cache, client) = (JwksCache::default(), reqwest::Client::new());
+
+        let forged = unknown.mint_token(&claims("forged"));
+        for _ in 0..3 {
+            assert!(
+                decode_with_cache(&cache, &client, &url, &forged, &validation())
+                    .await
+                    .is_err()
+            );
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        let token = known.mint_token(&claims("user"));
+        assert!(
+            decode_with_cache(&cache, &client, &url, &token, &validation())
+                .await
+                .is_ok()
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_symmetric_algorithm() {
+        let rsa = issuer(MockKeyAlgorithm::Rs256, "rsa-key").await;
+        let (url, _) = serve_jwks(&[&rsa]).await;
+        let (cache, client) = (JwksCache::default(), reqwest::Client::new());
+
+        // Signed with HS256 using the public modulus as the secret, a classic key confusion attempt
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("rsa-key".to_string());
+        let secret = rsa.jwks()["keys"][0]["n"].as_str().unwrap().as_bytes();
+        let token =
+            jsonwebtoken::encode(&header, &json!({ "sub": "forged" }), &EncodingKey::from_secret(secret)).unwrap();
+
+        assert!(
+            decode_with_cache(&cache, &client, &url, &token, &validation())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_without_kid() {
+        let rsa = issuer(MockKeyAlgorithm::Rs256, "rsa-key").await;
+        let ec = issuer(MockKeyAlgorithm::Es256, "ec-key").await;
+        let (single, _) = serve_jwks(&[&rsa]).await;
+        let (multiple, _) = serve_jwks(&[&rsa, &ec]).await;
+        let client = reqwest::Client::new();
+
+        let jwk = JwksCache::default().jwk(&client, &single, None).await.unwrap();
+        assert_eq!(jwk.common.key_id.as_deref(), Some("rsa-key"));
+        // With several keys there is no telling which one signed the token
+        assert!(JwksCache::default().jwk(&client, &multiple, None).await.is_err());
+    }
+}
@@ -1,15 +1,19 @@
 use std::fmt::Debug;
 
-use base64::Engine;
-use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use jsonwebtoken::{Algorithm, Validation};
 use reqwest::Response;
 use serde_json::{Value, json};
 
 use crate::{
-    clients::SecretManager,
+    clients::{
+        SecretManager,
+        jwks::{JwksCache, decode_with_cache},
+        read_token_response,
+    },
     configuration::ApplicationConfiguration,
-    errors::SecretResult,
+    errors::{SecretError, SecretResult},
     models::{TokenResponse, TokenType, TokenWrap},
+    utils::{ACCESS_TOKEN_TYPE, TOKEN_EXCHANGE_GRANT},
 };
 
 #[derive(Default)]
@@ -17,6 +21,7 @@ pub struct Keycloak {
     client: reqwest::Client,
     url: String,
     session_refresh: Option<String>,
+    jwks: JwksCache,
 }
 
 impl Keycloak {
@@ -25,42 +30,25 @@ impl Keycloak {
             client: reqwest::Client::new(),
             url: config.secrets_api.clone(),
             session_refresh: None,
+            jwks: JwksCache::default(),
         }
     }
 
     async fn get_token_data(&self, token: &TokenResponse) -> SecretResult<jsonwebtoken::TokenData<Value>> {
-        let jwks_url = format!("{}/protocol/openid-connect/certs", self.url);
-        let jwks_json: Value = reqwest::get(jwks_url)
-            .await
-            .expect("couldn't query jwks")
-            .json()
+        self.decode_token(token, &[TokenType::VAULT.client_id(), TokenType::AWS.client_id()])
             .await
-            .expect("couldn't convert to json");
-        let jwk = jwks_json["keys"][0]["x5c"][0]
-            .as_str()
-            .expect("Failed to extract public key");
-
-        // println!("Jwk: {}", jwk);
-        // The public key is Base64-encoded in the JWKS, so decode it
-        let engine = base64::engine::general_purpose::STANDARD;
-        let public_key_der = engine.decode(jwk).expect("coudln't decode base64");
-
-        let public_key_pem = format!(
-            "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----",
-            engine.encode(public_key_der)
-        );
-
-        // println!("Public key pem: {}", public_key_pem);
+    }
 
-        let decoding_key =
-            DecodingKey::from_rsa_pem(public_key_pem.as_bytes()).expect("Failed to turn key into decodingkey");
+    async fn decode_token(
+        &self,
+        token: &TokenResponse,
+        audiences: &[&str],
+    ) -> SecretResult<jsonwebtoken::TokenData<Value>> {
+        let jwks_url = format!("{}/protocol/openid-connect/certs", self.url);
         let mut validator = Validation::new(Algorithm::RS256);
+        validator.set_audience(audiences);
 
-        validator.set_audience(&[TokenType::VAULT.client_id(), TokenType::AWS.client_id()]);
-        Ok(
-            jsonwebtoken::decode::<Value>(&token.access_token, &decoding_key, &validator)
-                .expect("Could not decode jwt"),
-        )
+        decode_with_cache(&self.jwks, &self.client, &jwks_url, &token.access_token, &validator).await
     }
 
     async fn token_from_response(&mut self, token_type: TokenType, response: Response) -> SecretResult<TokenWrap> {
@@ -139,7 +127,7 @@ impl SecretManager for Keycloak {
         });
 
         let response = self.client.post(url).form(&params).send().await?;
-        let token: TokenResponse = response.json().await?;
+        let token = read_token_response(response).await?;
         self.session_refresh.replace(token.refresh_token.clone());
         let token_data = self.get_token_data(&token).await?;
 
@@ -160,4 +148,37 @@ impl SecretManager for Keycloak {
 
         Ok(TokenWrap::new(token_type.clone(), token_data, token.to_string()))
     }
+
+    async fn exchange_token(&mut self, subject: &TokenWrap, token_type: &TokenType) -> SecretResult<TokenWrap> {
+        let audience = token_type.client_id();
+        log::debug!("Exchanging token for: {}", audience);
+
+        let url = format!("{}/protocol/openid-connect/token", self.url);
+        let params = json!({
+            "grant_type": TOKEN_EXCHANGE_GRANT,
+            "client_id": TokenType::AUTH0.client_id(),
+            "subject_token": subject.raw(),
+            "subject_token_type": ACCESS_TOKEN_TYPE,
+            "requested_token_type": ACCESS_TOKEN_TYPE,
+            "audience": audience,
+        });
+
+        let response = self.client.post(url).form(&params).send().await?;
+        if !response.status().is_success() {
+            return Err(SecretError::TokenExchange(response.text().await?));
+        }
+
+        // The exchanged token belongs to the same session, so the session refresh token is kept
+        let token: TokenResponse = response.json().await?;
+        let token_data = self.decode_token(&token, &[audience]).await?;
+        Ok(TokenWrap::new(token_type.clone(), token_data, token.access_token))
+    }
+
+    fn session_refresh(&self) -> Option<String> {
+        self.session_refresh.clone()
+    }
+
+    fn set_session_refresh(&mut self, refresh_token: String) {
+        self.session_refresh.replace(refresh_token);
+    }
 }
@@ -0,0 +1,553 @@
+use std::fmt::Debug;
+
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::{
+    clients::{HttpClient, SecretManager, TokenManager},
+    configuration::ApplicationConfiguration,
+    errors::{ApiError, ApiResult},
+    models::{
+        ClientScopeKind, KeycloakClientScope, KeycloakCredential, KeycloakGroup, KeycloakRole, KeycloakUser,
+        Permission, TokenType,
+    },
+    utils::API_TIMEOUT,
+};
+
+/// Client for the Keycloak admin REST API of the realm in `ApplicationConfiguration::secrets_api`.
+///
+/// Authenticates with a service account token from `get_token_with_secret`. The service account client needs the
+/// `realm-management` roles for the calls made (`manage-users`, `view-users`, `manage-clients`, `manage-realm`).
+pub struct KeycloakAdmin {
+    admin_url: Url,
+    http_client: HttpClient,
+    token_manager: Mutex<TokenManager>,
+    token_type: TokenType,
+    client_secret: String,
+}
+
+impl Debug for KeycloakAdmin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeycloakAdmin")
+            .field("admin_url", &self.admin_url.as_str())
+            .field("token_type", &self.token_type)
+            .finish()
+    }
+}
+
+impl KeycloakAdmin {
+    pub fn new(
+        config: &ApplicationConfiguration,
+        token_manager: TokenManager,
+        token_type: TokenType,
+        client_secret: &str,
+    ) -> ApiResult<Self> {
+        // https://host/realms/<realm> -> https://host/admin/realms/<realm>
+        let mut admin_url = Url::parse(&config.secrets_api)?;
+        let path = admin_url.path().trim_end_matches('/').to_string();
+        match path.rfind("/realms/") {
+            Some(index) => admin_url.set_path(&format!("{}/admin{}", &path[..index], &path[index..])),
+            None => {
+                return Err(ApiError::Configuration(format!(
+                    "secrets_api {} does not name a realm",
+                    config.secrets_api
+                )));
+            }
+        }
+
+        Ok(Self {
+            admin_url,
+            http_client: HttpClient::new("demia".to_string()),
+            token_manager: Mutex::new(token_manager),
+            token_type,
+            client_secret: client_secret.to_string(),
+        })
+    }
+
+    async fn bearer(&self) -> ApiResult<String> {
+        let token = self
+            .token_manager
+            .lock()
+            .await
+            .get_token_with_secret(&self.token_type, &self.client_secret)
+            .await?;
+        Ok(token.raw().to_string())
+    }
+
+    fn url(&self, segments: &[&str]) -> ApiResult<Url> {
+        let mut url = self.admin_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| ApiError::NotFound(self.admin_url.to_string()))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: Url) -> ApiResult<T> {
+        let bearer = self.bearer().await?;
+        self.http_client.get(url, &bearer, API_TIMEOUT).await?.into_json().await
+    }
+
+    async fn post(&self, url: Url, json: Value) -> ApiResult<Option<String>> {
+        let bearer = self.bearer().await?;
+        let response = self.http_client.post_json(url, &bearer, API_TIMEOUT, json).await?;
+        // Created resources are only referenced by the Location header
+        Ok(response
+            .header("location")
+            .and_then(|location| location.rsplit('/').next().map(str::to_string)))
+    }
+
+    async fn put(&self, url: Url, json: Value) -> ApiResult<()> {
+        let bearer = self.bearer().await?;
+        self.http_client.put_json(url, &bearer, API_TIMEOUT, json).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, url: Url, json: Option<Value>) -> ApiResult<()> {
+        let bearer = self.bearer().await?;
+        self.http_client.delete(url, &bearer, API_TIMEOUT, json).await?;
+        Ok(())
+    }
+
+    /// Creates the user and returns its id
+    pub async fn create_user(&self, user: &KeycloakUser) -> ApiResult<String> {
+        match self.post(self.url(&["users"])?, serde_json::to_value(user)?).await? {
+            Some(id) => Ok(id),
+            None => self
+                .find_user_by_username(&user.username)
+                .await?
+                .and_then(|user| user.id)
+                .ok_or_else(|| ApiError::NotFound(format!("Created user {}", user.username))),
+        }
+    }
+
+    /// Finds the user by email or creates it, then grants the permissions. Returns the user id.
+    /// Meant for onboarding, so the user can log in and run `UserIdentity::new` right after.
+    pub async fn provision_user(&self, user: &KeycloakUser, permissions: &[Permission]) -> ApiResult<String> {
+        let existing = match &user.email {
+            Some(email) => self.find_user_by_email(email).await?,
+            None => self.find_user_by_username(&user.username).await?,
+        };
+        let user_id = match existing.and_then(|existing| existing.id) {
+            Some(id) => id,
+            None => self.create_user(user).await?,
+        };
+
+        for permission in permissions {
+            self.grant(&user_id, permission).await?;
+        }
+        Ok(user_id)
+    }
+
+    pub async fn get_user(&self, user_id: &str) -> ApiResult<KeycloakUser> {
+        self.get(self.url(&["users", user_id])?).await
+    }
+
+    pub async fn find_user_by_email(&self, email: &str) -> ApiResult<Option<KeycloakUser>> {
+        self.find_user("email", email).await
+    }
+
+    pub async fn find_user_by_username(&self, username: &str) -> ApiResult<Option<KeycloakUser>> {
+        self.find_user("username", username).await
+    }
+
+    async fn find_user(&self, field: &str, value: &str) -> ApiResult<Option<KeycloakUser>> {
+        let mut url = self.url(&["users"])?;
+        url.query_pairs_mut()
+            .append_pair(field, value)
+            .append_pair("exact", "true");
+        let users: Vec<KeycloakUser> = self.get(url).await?;
+        Ok(users.into_iter().next())
+    }
+
+    pub async fn update_user(&self, user_id: &str, user: &KeycloakUser) -> ApiResult<()> {
+        self.put(self.url(&["users", user_id])?, serde_json::to_value(user)?)
+            .await
+    }
+
+    pub async fn delete_user(&self, user_id: &str) -> ApiResult<()> {
+        self.delete(self.url(&["users", user_id])?, None).await
+    }
+
+    /// Sets a new password, which the user has to change on next login if `temporary`
+    pub async fn reset_password(&self, user_id: &str, password: &str, temporary: bool) -> ApiResult<()> {
+        let credential = KeycloakCredential::password(password, temporary);
+        self.put(
+            self.url(&["users", user_id, "reset-password"])?,
+            serde_json::to_value(credential)?,
+        )
+        .await
+    }
+
+    pub async fn realm_role(&self, name: &str) -> ApiResult<Option<KeycloakRole>> {
+        match self.get(self.url(&["roles", name])?).await {
+            Ok(role) => Ok(Some(role)),
+            Err(ApiError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn create_realm_role(&self, role: &KeycloakRole) -> ApiResult<()> {
+        self.post(self.url(&["roles"])?, serde_json::to_value(role)?).await?;
+        Ok(())
+    }
+
+    /// Returns the realm role, creating it first if it doesn't exist
+    pub async fn ensure_realm_role(&self, name: &str, description: Option<&str>) -> ApiResult<KeycloakRole> {
+        if let Some(role) = self.realm_role(name).await? {
+            return Ok(role);
+        }
+        self.create_realm_role(&KeycloakRole::new(name, description)).await?;
+        self.realm_role(name)
+            .await?
+            .ok_or_else(|| ApiError::NotFound(format!("Created role {}", name)))
+    }
+
+    pub async fn user_realm_roles(&self, user_id: &str) -> ApiResult<Vec<KeycloakRole>> {
+        self.get(self.url(&["users", user_id, "role-mappings", "realm"])?).await
+    }
+
+    pub async fn assign_realm_roles(&self, user_id: &str, roles: &[&str]) -> ApiResult<()> {
+        let roles = self.realm_roles(roles).await?;
+        self.post(
+            self.url(&["users", user_id, "role-mappings", "realm"])?,
+            serde_json::to_value(roles)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_realm_roles(&self, user_id: &str, roles: &[&str]) -> ApiResult<()> {
+        let roles = self.realm_roles(roles).await?;
+        self.delete(
+            self.url(&["users", user_id, "role-mappings", "realm"])?,
+            Some(serde_json::to_value(roles)?),
+        )
+        .await
+    }
+
+    // Role mappings need the full representations, including ids
+    async fn realm_roles(&self, names: &[&str]) -> ApiResult<Vec<KeycloakRole>> {
+        let mut roles = Vec::with_capacity(names.len());
+        for name in names {
+            let role = self
+                .realm_role(name)
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("Realm role {}", name)))?;
+            roles.push(role);
+        }
+        Ok(roles)
+    }
+
+    /// Internal id of a client from its client id
+    pub async fn client_uuid(&self, client_id: &str) -> ApiResult<String> {
+        let mut url = self.url(&["clients"])?;
+        url.query_pairs_mut().append_pair("clientId", client_id);
+        let clients: Vec<Value> = self.get(url).await?;
+        clients
+            .first()
+            .and_then(|client| client["id"].as_str())
+            .map(str::to_string)
+            .ok_or_else(|| ApiError::NotFound(format!("Client {}", client_id)))
+    }
+
+    pub async fn assign_client_roles(&self, user_id: &str, client_id: &str, roles: &[&str]) -> ApiResult<()> {
+        let client = self.client_uuid(client_id).await?;
+        let roles = self.client_roles(&client, roles).await?;
+        self.post(
+            self.url(&["users", user_id, "role-mappings", "clients", client.as_str()])?,
+            serde_json::to_value(roles)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_client_roles(&self, user_id: &str, client_id: &str, roles: &[&str]) -> ApiResult<()> {
+        let client = self.client_uuid(client_id).await?;
+        let roles = self.client_roles(&client, roles).await?;
+        self.delete(
+            self.url(&["users", user_id, "role-mappings", "clients", client.as_str()])?,
+            Some(serde_json::to_value(roles)?),
+        )
+        .await
+    }
+
+    async fn client_roles(&self, client_uuid: &str, names: &[&str]) -> ApiResult<Vec<KeycloakRole>> {
+        let mut roles = Vec::with_capacity(names.len());
+        for name in names {
+            roles.push(self.get(self.url(&["clients", client_uuid, "roles", name])?).await?);
+        }
+        Ok(roles)
+    }
+
+    pub async fn groups(&self, search: Option<&str>) -> ApiResult<Vec<KeycloakGroup>> {
+        let mut url = self.url(&["groups"])?;
+        if let Some(search) = search {
+            url.query_pairs_mut().append_pair("search", search);
+        }
+        self.get(url).await
+    }
+
+    /// Top level group with exactly this name
+    pub async fn group_by_name(&self, name: &str) -> ApiResult<Option<KeycloakGroup>> {
+        Ok(self
+            .groups(Some(name))
+            .await?
+            .into_iter()
+            .find(|group| group.name == name))
+    }
+
+    /// Creates a top level group and returns its id
+    pub async fn create_group(&self, name: &str) -> ApiResult<String> {
+        match self.post(self.url(&["groups"])?, json!({ "name": name })).await? {
+            Some(id) => Ok(id),
+            None => self
+                .group_by_name(name)
+                .await?
+                .map(|group| group.id)
+                .ok_or_else(|| ApiError::NotFound(format!("Created group {}", name))),
+        }
+    }
+
+    pub async fn user_groups(&self, user_id: &str) -> ApiResult<Vec<KeycloakGroup>> {
+        self.get(self.url(&["users", user_id, "groups"])?).await
+    }
+
+    pub async fn add_user_to_group(&self, user_id: &str, group_id: &str) -> ApiResult<()> {
+        self.put(self.url(&["users", user_id, "groups", group_id])?, json!({}))
+            .await
+    }
+
+    pub async fn remove_user_from_group(&self, user_id: &str, group_id: &str) -> ApiResult<()> {
+        self.delete(self.url(&["users", user_id, "groups", group_id])?, None)
+            .await
+    }
+
+    pub async fn client_scopes(&self) -> ApiResult<Vec<KeycloakClientScope>> {
+        self.get(self.url(&["client-scopes"])?).await
+    }
+
+    /// Creates the client scope and returns its id
+    pub async fn create_client_scope(&self, scope: &KeycloakClientScope) -> ApiResult<String> {
+        match self
+            .post(self.url(&["client-scopes"])?, serde_json::to_value(scope)?)
+            .await?
+        {
+            Some(id) => Ok(id),
+            None => self
+                .client_scopes()
+                .await?
+                .into_iter()
+                .find(|existing| existing.name == scope.name)
+                .and_then(|existing| existing.id)
+                .ok_or_else(|| ApiError::NotFound(format!("Created client scope {}", scope.name))),
+        }
+    }
+
+    pub async fn delete_client_scope(&self, scope_id: &str) -> ApiResult<()> {
+        self.delete(self.url(&["client-scopes", scope_id])?, None).await
+    }
+
+    pub async fn client_scopes_of(
+        &self,
+        client_id: &str,
+        kind: ClientScopeKind,
+    ) -> ApiResult<Vec<KeycloakClientScope>> {
+        let client = self.client_uuid(client_id).await?;
+        self.get(self.url(&["clients", client.as_str(), kind.path()])?).await
+    }
+
+    pub async fn add_client_scope(&self, client_id: &str, scope_id: &str, kind: ClientScopeKind) -> ApiResult<()> {
+        let client = self.client_uuid(client_id).await?;
+        self.put(
+            self.url(&["clients", client.as_str(), kind.path(), scope_id])?,
+            json!({}),
+        )
+        .await
+    }
+
+    pub async fn remove_client_scope(&self, client_id: &str, scope_id: &str, kind: ClientScopeKind) -> ApiResult<()> {
+        let client = self.client_uuid(client_id).await?;
+        self.delete(self.url(&["clients", client.as_str(), kind.path(), scope_id])?, None)
+            .await
+    }
+
+    /// Assigns the role behind a permission, creating site roles on first use.
+    /// Scopes are granted through client scopes and can't be assigned to a user.
+    pub async fn grant(&self, user_id: &str, permission: &Permission) -> ApiResult<()> {
+        match permission {
+            Permission::Role(role) => self.assign_realm_roles(user_id, &[role.as_str()]).await,
+            Permission::ClientRole { client, role } => {
+                self.assign_client_roles(user_id, client, &[role.as_str()]).await
+            }
+            Permission::SiteAdmin(site) => {
+                let role = Permission::site_admin_name(site);
+                self.ensure_realm_role(&role, Some("Can manage the site")).await?;
+                self.assign_realm_roles(user_id, &[role.as_str()]).await
+            }
+            Permission::SiteAccess(site) => {
+                let role = Permission::site_access_name(site);
+                self.ensure_realm_role(&role, Some("Can view the site")).await?;
+                self.assign_realm_roles(user_id, &[role.as_str()]).await
+            }
+            Permission::Scope(_) => Err(ApiError::BadRequest),
+        }
+    }
+
+    pub async fn revoke(&self, user_id: &str, permission: &Permission) -> ApiResult<()> {
+        match permission {
+            Permission::Role(role) => self.remove_realm_roles(user_id, &[role.as_str()]).await,
+            Permission::ClientRole { client, role } => {
+                self.remove_client_roles(user_id, client, &[role.as_str()]).await
+            }
+            Permission::SiteAdmin(site) => {
+                self.remove_realm_roles(user_id, &[Permission::site_admin_name(site).as_str()])
+                    .await
+            }
+            Permission::SiteAccess(site) => {
+                self.remove_realm_roles(user_id, &[Permission::site_access_name(site).as_str()])
+                    .await
+            }
+            Permission::Scope(_) => Err(ApiError::BadRequest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use base64::Engine;
+
+    use super::*;
+    use crate::{
+        clients::{HttpResponse, MockTransport, StaticSecretManager},
+        configuration::{StaticKey, StaticSecretConfiguration},
+    };
+
+    fn admin(secrets_api: &str) -> ApiResult<KeycloakAdmin> {
+        let manager = StaticSecretManager::new(StaticSecretConfiguration {
+            issuer: "https://static.demia.test".to_string(),
+            key: StaticKey::Hmac {
+                secret: base64::engine::general_purpose::STANDARD.encode(b"a static secret for tests"),
+            },
+            users: Vec::new(),
+            clients: HashMap::from([(TokenType::VAULT.client_id().to_string(), "secret".to_string())]),
+            token_lifetime: 300,
+            refresh_lifetime: 3600,
+        })
+        .unwrap();
+        let config = ApplicationConfiguration {
+            secrets_api: secrets_api.to_string(),
+            ..Default::default()
+        };
+        KeycloakAdmin::new(
+            &config,
+            TokenManager::new(Box::new(manager)),
+            TokenType::VAULT,
+            "secret",
+        )
+    }
+
+    fn with_transport(mut admin: KeycloakAdmin, transport: Arc<MockTransport>) -> KeycloakAdmin {
+        admin.http_client.set_transport(transport);
+        admin
+    }
+
+    #[test]
+    fn test_admin_url() {
+        let client = admin("https://auth.demia.test/realms/demia/").unwrap();
+        assert_eq!(client.admin_url.as_str(), "https://auth.demia.test/admin/realms/demia");
+        assert!(matches!(
+            admin("https://auth.demia.test/"),
+            Err(ApiError::Configuration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_partial_update_keeps_enabled() {
+        let transport =
+            Arc::new(MockTransport::new().on("PUT", "/users/user-1", |_| HttpResponse::new(204, Vec::new())));
+        let admin = with_transport(
+            admin("https://auth.demia.test/realms/demia").unwrap(),
+            transport.clone(),
+        );
+
+        let user = KeycloakUser {
+            username: "alice".to_string(),
+            first_name: Some("Alice".to_string()),
+            ..Default::default()
+        };
+        admin.update_user("user-1", &user).await.unwrap();
+
+        let body: Value = serde_json::from_slice(transport.requests()[0].body.as_ref().unwrap()).unwrap();
+        assert_eq!(body, json!({ "username": "alice", "firstName": "Alice" }));
+        assert_eq!(
+            serde_json::to_value(KeycloakUser::new("bob@demia.test")).unwrap()["enabled"],
+            json!(true)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_provision_user() {
+        let transport = Arc::new(
+            MockTransport::new()
+                .on_json("GET", "/users", 200, json!([]))
+                .on("POST", "/users", |_| {
+                    let mut response = HttpResponse::new(201, Vec::new());
+                    response.headers.insert(
+                        "location".to_string(),
+                        "https://auth.demia.test/admin/realms/demia/users/new-user".to_string(),
+                    );
+                    response
+                })
+                .on_json(
+                    "GET",
+                    "/roles/operator",
+                    200,
+                    json!({ "id": "role-1", "name": "operator" }),
+                )
+                .on("POST", "/users/new-user/role-mappings/realm", |_| {
+                    HttpResponse::new(204, Vec::new())
+                }),
+        );
+        let admin = with_transport(
+            admin("https://auth.demia.test/realms/demia").unwrap(),
+            transport.clone(),
+        );
+
+        let user = KeycloakUser::new("carol@demia.test").with_password("password", true);
+        let id = admin
+            .provision_user(&user, &[Permission::Role("operator".to_string())])
+            .await
+            .unwrap();
+        assert_eq!(id, "new-user");
+
+        let requests = transport.requests();
+        assert_eq!(
+            requests[0].url.as_str(),
+            "https://auth.demia.test/admin/realms/demia/users?email=carol%40demia.test&exact=true"
+        );
+        let mapping: Value = serde_json::from_slice(requests[3].body.as_ref().unwrap()).unwrap();
+        assert_eq!(mapping[0]["id"], json!("role-1"));
+        assert!(
+            requests
+                .iter()
+                .all(|request| request.headers.contains_key("authorization"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_role_is_not_found() {
+        let transport = Arc::new(MockTransport::new());
+        let admin = with_transport(admin("https://auth.demia.test/realms/demia").unwrap(), transport);
+
+        assert!(admin.realm_role("missing").await.unwrap().is_none());
+        assert!(matches!(
+            admin.assign_realm_roles("user-1", &["missing"]).await,
+            Err(ApiError::NotFound(_))
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 mod auth0;
+mod auth0_management;
 mod http;
 
 #[cfg(feature = "aws")]
@@ -10,8 +11,13 @@ mod aws_rusoto;
 #[cfg(feature = "google_cloud")]
 mod gc;
 
+mod jwks;
 mod keycloak;
+mod keycloak_admin;
+mod session;
+mod static_secret;
 mod token;
+mod token_store;
 
 use core::fmt::Debug;
 use std::{
@@ -21,6 +27,7 @@ use std::{
 };
 
 pub use auth0::Auth0Client;
+pub use auth0_management::Auth0Management;
 #[cfg(feature = "aws")]
 pub use aws::AwsClient;
 #[cfg(feature = "aws_rusoto")]
@@ -30,12 +37,17 @@ use chrono::{DateTime, Utc};
 pub use gc::GoogleCloud;
 pub use http::*;
 pub use keycloak::Keycloak;
+pub use keycloak_admin::KeycloakAdmin;
 use rocket_okapi::okapi::schemars;
+pub use session::{EncryptedFileSessionStore, PersistedSession, SessionStore, StrongholdSessionStore};
+pub use static_secret::StaticSecretManager;
 pub use token::TokenManager;
+pub use token_store::TokenStore;
 
 use crate::{
-    errors::{SecretResult, StorageResult},
-    models::{Asset, TokenType, TokenWrap},
+    errors::{SecretError, SecretResult, StorageResult},
+    models::{Asset, TokenResponse, TokenType, TokenWrap},
+    telemetry::Call,
 };
 
 pub const STRONGHOLD_PATH: &str = "stronghold";
@@ -140,6 +152,32 @@ pub trait SecretManager: Debug + Send + Sync {
     async fn refresh_token(&mut self) -> SecretResult<TokenWrap>;
     /// Get token data from raw token response
     async fn token_from_raw(&self, token_type: &TokenType, token: &str) -> SecretResult<TokenWrap>;
+    /// Exchanges a token from this manager for one issued to the audience of `token_type` (RFC 8693)
+    async fn exchange_token(&mut self, _subject: &TokenWrap, token_type: &TokenType) -> SecretResult<TokenWrap> {
+        Err(SecretError::TokenExchange(format!(
+            "{:?} does not support exchanging for {}",
+            self, token_type
+        )))
+    }
+    /// The refresh token of the current session, if the manager holds one
+    fn session_refresh(&self) -> Option<String> {
+        None
+    }
+    /// Resumes a session from a previously stored refresh token
+    fn set_session_refresh(&mut self, _refresh_token: String) {}
+}
+
+/// Reads a token endpoint response. A 400 or 401 is the provider rejecting the grant, any other failure may pass on
+/// a retry and is reported as a client error
+pub(crate) async fn read_token_response(response: reqwest::Response) -> SecretResult<TokenResponse> {
+    let status = response.status();
+    if status == reqwest::StatusCode::BAD_REQUEST || status == reqwest::StatusCode::UNAUTHORIZED {
+        return Err(SecretError::InvalidGrant(response.text().await?));
+    }
+    if !status.is_success() {
+        return Err(SecretError::ReqwestError(format!("Token endpoint returned {}", status)));
+    }
+    Ok(response.json().await?)
 }
 
 pub(crate) fn default_secret() -> Box<impl SecretManager> {
@@ -203,13 +241,12 @@ impl<T: Storage + std::fmt::Debug> StorageClient<T> {
             data
         });
 
-        self.storage
-            .upload(StorageInfo {
-                url: storage_path,
-                bucket,
-                data: Some(data),
-            })
-            .await
+        let upload = self.storage.upload(StorageInfo {
+            url: storage_path,
+            bucket,
+            data: Some(data),
+        });
+        Self::call("upload").observe(upload).await
     }
 
     /// Uploads the data from a file on the system
@@ -218,14 +255,12 @@ impl<T: Storage + std::fmt::Debug> StorageClient<T> {
     }
 
     pub async fn list_objects(&self, path: String, get_metadata: bool, public: bool) -> StorageResult<Vec<FileInfo>> {
-        let mut objs = self
-            .storage
-            .list_objects(StorageInfo {
-                url: path,
-                bucket: self.get_bucket_path(public),
-                data: None,
-            })
-            .await?;
+        let list = self.storage.list_objects(StorageInfo {
+            url: path,
+            bucket: self.get_bucket_path(public),
+            data: None,
+        });
+        let mut objs = Self::call("list_objects").observe(list).await?;
 
         match get_metadata {
             false => Ok(objs),
@@ -241,35 +276,32 @@ impl<T: Storage + std::fmt::Debug> StorageClient<T> {
 
     /// Function expects raw path of file for the storage provider.
     pub async fn get_metadata_raw(&self, file: String) -> StorageResult<FileMetadata> {
-        self.storage
-            .get_metadata(StorageInfo {
-                url: file,
-                bucket: &self.private_bucket_path,
-                data: None,
-            })
-            .await
+        let metadata = self.storage.get_metadata(StorageInfo {
+            url: file,
+            bucket: &self.private_bucket_path,
+            data: None,
+        });
+        Self::call("get_metadata").observe(metadata).await
     }
 
     pub async fn delete(&self, data: StorageDataType<'_>) -> StorageResult<()> {
         let (_, storage_path) = data.get_paths(&self.sub);
-        self.storage
-            .delete(StorageInfo {
-                url: storage_path,
-                bucket: &self.private_bucket_path,
-                data: None,
-            })
-            .await
+        let delete = self.storage.delete(StorageInfo {
+            url: storage_path,
+            bucket: &self.private_bucket_path,
+            data: None,
+        });
+        Self::call("delete").observe(delete).await
     }
 
     pub async fn upload_metadata<S: serde::Serialize + Send>(&self, metadata: &S) -> StorageResult<()> {
         let (_, storage_path) = StorageDataType::IdentityMetadata("").get_paths(&self.sub);
-        self.storage
-            .upload(StorageInfo {
-                url: storage_path,
-                bucket: &self.private_bucket_path,
-                data: Some(serde_json::to_vec(metadata).expect("Metadata is serializable, should not fail")),
-            })
-            .await
+        let upload = self.storage.upload(StorageInfo {
+            url: storage_path,
+            bucket: &self.private_bucket_path,
+            data: Some(serde_json::to_vec(metadata).expect("Metadata is serializable, should not fail")),
+        });
+        Self::call("upload").observe(upload).await
     }
 
     pub async fn download_data(
@@ -284,7 +316,9 @@ impl<T: Storage + std::fmt::Debug> StorageClient<T> {
             ..Default::default()
         };
 
-        let raw = self.storage.download(info, last_modified).await;
+        let raw = Self::call("download")
+            .observe(self.storage.download(info, last_modified))
+            .await;
         match storage_type {
             StorageDataType::IdentityMetadata(_) | StorageDataType::Document(_, _) => match raw {
                 Ok(object) => Ok(object),
@@ -311,6 +345,13 @@ impl<T: Storage + std::fmt::Debug> StorageClient<T> {
     }
 
     pub async fn update_credentials(&mut self, token: TokenWrap) -> StorageResult<()> {
-        self.storage.update_credentials(token).await
+        let update = self.storage.update_credentials(token);
+        Self::call("update_credentials").observe(update).await
+    }
+
+    /// The call for metrics, labelled with the storage backend
+    fn call(operation: &str) -> Call {
+        let backend = std::any::type_name::<T>().rsplit("::").next().unwrap_or_default();
+        Call::new("storage", operation).with("backend", backend)
     }
 }
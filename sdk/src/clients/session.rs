@@ -0,0 +1,202 @@
+use std::{
+    fmt::Debug,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::Engine;
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, AeadCore, OsRng},
+};
+
+use crate::{
+    errors::{SecretError, SecretResult},
+    models::UserIdentity,
+    utils::STRONGHOLD_KEY_TOKEN_SESSION,
+};
+
+/// The part of a `TokenManager` session that survives a restart
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PersistedSession {
+    pub refresh_token: String,
+    /// Seconds since the epoch
+    pub saved_at: u64,
+}
+
+impl PersistedSession {
+    pub fn new(refresh_token: String) -> Self {
+        Self {
+            refresh_token,
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        }
+    }
+}
+
+/// Encrypted storage for a persisted session
+#[async_trait::async_trait]
+pub trait SessionStore: Debug + Send + Sync {
+    async fn save(&self, session: &PersistedSession) -> SecretResult<()>;
+    async fn load(&self) -> SecretResult<Option<PersistedSession>>;
+    async fn clear(&self) -> SecretResult<()>;
+}
+
+/// Keeps the session inside the user's Stronghold snapshot, next to the identity keys
+pub struct StrongholdSessionStore {
+    identity: UserIdentity,
+}
+
+impl Debug for StrongholdSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StrongholdSessionStore").finish()
+    }
+}
+
+impl StrongholdSessionStore {
+    /// The store shares the identity's stronghold
+    pub fn new(identity: &UserIdentity) -> Self {
+        Self {
+            identity: identity.clone(),
+        }
+    }
+}
+
+fn session_error(error: impl std::fmt::Display) -> SecretError {
+    SecretError::Session(error.to_string())
+}
+
+#[async_trait::async_trait]
+impl SessionStore for StrongholdSessionStore {
+    async fn save(&self, session: &PersistedSession) -> SecretResult<()> {
+        let bytes = serde_json::to_vec(session).map_err(session_error)?;
+        self.identity
+            .set_stronghold_bytes(STRONGHOLD_KEY_TOKEN_SESSION, &bytes)
+            .await
+            .map_err(session_error)?;
+        self.identity.write_stronghold_snapshot().await.map_err(session_error)
+    }
+
+    async fn load(&self) -> SecretResult<Option<PersistedSession>> {
+        match self
+            .identity
+            .get_stronghold_bytes::<Vec<u8>>(STRONGHOLD_KEY_TOKEN_SESSION)
+            .await
+            .map_err(session_error)?
+        {
+            Some(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(session_error),
+            None => Ok(None),
+        }
+    }
+
+    async fn clear(&self) -> SecretResult<()> {
+        self.identity
+            .delete_stronghold_bytes(STRONGHOLD_KEY_TOKEN_SESSION)
+            .await
+            .map_err(session_error)?;
+        self.identity.write_stronghold_snapshot().await.map_err(session_error)
+    }
+}
+
+/// Keeps the session in a XChaCha20-Poly1305 encrypted file, for hosts that have no Stronghold before login.
+///
+/// The file holds the 24 byte nonce followed by the ciphertext.
+pub struct EncryptedFileSessionStore {
+    path: PathBuf,
+    cipher: XChaCha20Poly1305,
+}
+
+impl Debug for EncryptedFileSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedFileSessionStore")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl EncryptedFileSessionStore {
+    /// `key` is a base64 encoded 32 byte key, such as one made by [`crate::utils::new_stronghold_key`]
+    pub fn new(path: impl Into<PathBuf>, key: &str) -> SecretResult<Self> {
+        let key = base64::engine::general_purpose::STANDARD
+            .decode(key)
+            .map_err(|e| SecretError::Session(e.to_string()))?;
+        let cipher = XChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|_| SecretError::Session(format!("Session key must be 32 bytes, got {}", key.len())))?;
+
+        Ok(Self {
+            path: path.into(),
+            cipher,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for EncryptedFileSessionStore {
+    async fn save(&self, session: &PersistedSession) -> SecretResult<()> {
+        let plaintext = serde_json::to_vec(session).map_err(|e| SecretError::Session(e.to_string()))?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| SecretError::Session("Failed to encrypt session".to_string()))?;
+
+        let mut contents = nonce.to_vec();
+        contents.extend(ciphertext);
+        tokio::fs::write(&self.path, contents)
+            .await
+            .map_err(|e| SecretError::Session(e.to_string()))
+    }
+
+    async fn load(&self) -> SecretResult<Option<PersistedSession>> {
+        let contents = match tokio::fs::read(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(SecretError::Session(e.to_string())),
+        };
+
+        if contents.len() < 24 {
+            return Err(SecretError::Session("Session file is truncated".to_string()));
+        }
+        let (nonce, ciphertext) = contents.split_at(24);
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SecretError::Session("Failed to decrypt session, wrong key?".to_string()))?;
+
+        serde_json::from_slice(&plaintext)
+            .map(Some)
+            .map_err(|e| SecretError::Session(e.to_string()))
+    }
+
+    async fn clear(&self) -> SecretResult<()> {
+        match tokio::fs::remove_file(&self.path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(SecretError::Session(e.to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::new_stronghold_key;
+
+    #[tokio::test]
+    async fn test_encrypted_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("demia-session-{}", uuid::Uuid::new_v4()));
+        let key = new_stronghold_key();
+        let store = EncryptedFileSessionStore::new(&path, &key).unwrap();
+
+        assert!(store.load().await.unwrap().is_none());
+        store.save(&PersistedSession::new("refresh".to_string())).await.unwrap();
+        let contents = std::fs::read(&path).unwrap();
+        assert!(!contents.windows(7).any(|window| window == b"refresh"));
+        assert_eq!(store.load().await.unwrap().unwrap().refresh_token, "refresh");
+
+        let other = EncryptedFileSessionStore::new(&path, &new_stronghold_key()).unwrap();
+        assert!(other.load().await.is_err());
+
+        store.clear().await.unwrap();
+        assert!(store.load().await.unwrap().is_none());
+        store.clear().await.unwrap();
+    }
+}
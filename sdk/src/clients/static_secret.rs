@@ -0,0 +1,365 @@
+use std::{
+    fmt::Debug,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde_json::{Map, Value, json};
+
+use crate::{
+    clients::SecretManager,
+    configuration::{StaticKey, StaticSecretConfiguration, StaticUser},
+    errors::{SecretError, SecretResult},
+    models::{TokenType, TokenWrap},
+};
+
+const REFRESH_TOKEN_TYPE: &str = "Refresh";
+const SERVICE_ACCOUNT_PREFIX: &str = "service-account-";
+
+/// Issues locally signed tokens for a fixed list of users, so the SDK runs without the hosted Keycloak realm.
+///
+/// Tokens carry the same claims as Keycloak ones and refresh tokens are signed JWTs as well, so sessions survive a
+/// restart of the issuing process. Meant for local development, CI and air-gapped demos: passwords live in the
+/// configuration in plain text.
+#[derive(Clone)]
+pub struct StaticSecretManager {
+    config: StaticSecretConfiguration,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    session_refresh: Option<String>,
+}
+
+impl Debug for StaticSecretManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaticSecretManager")
+            .field("issuer", &self.config.issuer)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+impl StaticSecretManager {
+    pub fn new(config: StaticSecretConfiguration) -> SecretResult<Self> {
+        let (algorithm, encoding_key, decoding_key) = match &config.key {
+            StaticKey::Hmac { secret } => {
+                let secret = base64::engine::general_purpose::STANDARD
+                    .decode(secret)
+                    .map_err(|e| SecretError::Configuration(format!("Invalid hmac secret: {}", e)))?;
+                (
+                    Algorithm::HS256,
+                    EncodingKey::from_secret(&secret),
+                    DecodingKey::from_secret(&secret),
+                )
+            }
+            StaticKey::Rsa {
+                private_key,
+                public_key,
+            } => {
+                let private_pem = read_key(private_key)?;
+                let public_pem = read_key(public_key)?;
+                (
+                    Algorithm::RS256,
+                    EncodingKey::from_rsa_pem(&private_pem).map_err(|e| SecretError::Configuration(e.to_string()))?,
+                    DecodingKey::from_rsa_pem(&public_pem).map_err(|e| SecretError::Configuration(e.to_string()))?,
+                )
+            }
+        };
+
+        Ok(Self {
+            config,
+            algorithm,
+            encoding_key,
+            decoding_key,
+            session_refresh: None,
+        })
+    }
+
+    /// Reads a json [`StaticSecretConfiguration`] from disk
+    pub fn from_file(path: &str) -> SecretResult<Self> {
+        let file = std::fs::File::open(path).map_err(|e| SecretError::Configuration(e.to_string()))?;
+        let config = serde_json::from_reader(file).map_err(|e| SecretError::Configuration(e.to_string()))?;
+        Self::new(config)
+    }
+
+    pub fn config(&self) -> &StaticSecretConfiguration {
+        &self.config
+    }
+
+    fn user_by_sub(&self, sub: &str) -> Option<&StaticUser> {
+        self.config.users.iter().find(|user| user.sub == sub)
+    }
+
+    fn user_claims(user: &StaticUser) -> Map<String, Value> {
+        let mut claims = Map::new();
+        claims.insert("sub".to_string(), json!(user.sub));
+        claims.insert("preferred_username".to_string(), json!(user.username));
+        claims.insert("realm_access".to_string(), json!({ "roles": user.roles }));
+        if let Some(email) = &user.email {
+            claims.insert("email".to_string(), json!(email));
+        }
+        if let Some(nickname) = &user.nickname {
+            claims.insert("nickname".to_string(), json!(nickname));
+        }
+        claims
+    }
+
+    fn service_account_claims(client_id: &str) -> Map<String, Value> {
+        let mut claims = Map::new();
+        claims.insert(
+            "sub".to_string(),
+            json!(format!("{}{}", SERVICE_ACCOUNT_PREFIX, client_id)),
+        );
+        claims
+    }
+
+    /// Claims of a configured user or client credentials service account
+    fn subject_claims(&self, sub: &str) -> Option<Map<String, Value>> {
+        if let Some(user) = self.user_by_sub(sub) {
+            return Some(Self::user_claims(user));
+        }
+        sub.strip_prefix(SERVICE_ACCOUNT_PREFIX)
+            .filter(|client_id| self.config.clients.contains_key(*client_id))
+            .map(Self::service_account_claims)
+    }
+
+    fn sign(&self, mut claims: Map<String, Value>, audience: &str, lifetime: u64) -> SecretResult<String> {
+        let now = now();
+        claims.insert("iss".to_string(), json!(self.config.issuer));
+        claims.insert("aud".to_string(), json!(audience));
+        claims.insert("iat".to_string(), json!(now));
+        claims.insert("exp".to_string(), json!(now + lifetime));
+
+        jsonwebtoken::encode(&Header::new(self.algorithm), &Value::Object(claims), &self.encoding_key)
+            .map_err(|e| SecretError::Jwt(e.to_string()))
+    }
+
+    /// Verifies the signature and issuer, and the audience unless it is `None`
+    fn verify(&self, token: &str, audience: Option<&str>) -> SecretResult<jsonwebtoken::TokenData<Value>> {
+        let mut validation = Validation::new(self.algorithm);
+        validation.set_issuer(&[&self.config.issuer]);
+        match audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        jsonwebtoken::decode::<Value>(token, &self.decoding_key, &validation)
+            .map_err(|e| SecretError::Jwt(e.to_string()))
+    }
+
+    /// Signs an access token for the audience of `token_type`, plus a refresh token when `refreshable`
+    fn issue(
+        &mut self,
+        token_type: &TokenType,
+        claims: Map<String, Value>,
+        refreshable: bool,
+    ) -> SecretResult<TokenWrap> {
+        let raw = self.sign(claims.clone(), token_type.client_id(), self.config.token_lifetime)?;
+
+        if refreshable {
+            let mut refresh_claims = Map::new();
+            refresh_claims.insert("sub".to_string(), claims["sub"].clone());
+            refresh_claims.insert("typ".to_string(), json!(REFRESH_TOKEN_TYPE));
+            refresh_claims.insert("token_type".to_string(), json!(token_type));
+            let refresh = self.sign(refresh_claims, &self.config.issuer, self.config.refresh_lifetime)?;
+            self.session_refresh.replace(refresh);
+        }
+
+        let token_data = self.verify(&raw, Some(token_type.client_id()))?;
+        Ok(TokenWrap::new(token_type.clone(), token_data, raw))
+    }
+}
+
+#[async_trait::async_trait]
+impl SecretManager for StaticSecretManager {
+    async fn get_token(&mut self, token_type: &TokenType, username: &str, password: &str) -> SecretResult<TokenWrap> {
+        let claims = self
+            .config
+            .users
+            .iter()
+            .find(|user| user.username == username && user.password == password)
+            .map(Self::user_claims)
+            .ok_or(SecretError::InvalidCredentials)?;
+
+        self.issue(token_type, claims, true)
+    }
+
+    async fn get_token_with_secret(&mut self, token_type: &TokenType, client_secret: &str) -> SecretResult<TokenWrap> {
+        let client_id = token_type.client_id();
+        if self.config.clients.get(client_id).map(String::as_str) != Some(client_secret) {
+            return Err(SecretError::InvalidCredentials);
+        }
+
+        self.issue(token_type, Self::service_account_claims(client_id), false)
+    }
+
+    async fn refresh_token(&mut self) -> SecretResult<TokenWrap> {
+        let refresh = self
+            .session_refresh
+            .clone()
+            .ok_or_else(|| SecretError::TokenNotFound("refresh".to_string()))?;
+        let refresh = self.verify(&refresh, Some(self.config.issuer.as_str()))?;
+        if refresh.claims["typ"] != REFRESH_TOKEN_TYPE {
+            return Err(SecretError::Jwt("Not a refresh token".to_string()));
+        }
+
+        // Users removed from the configuration lose their sessions
+        let claims = refresh.claims["sub"]
+            .as_str()
+            .and_then(|sub| self.user_by_sub(sub))
+            .map(Self::user_claims)
+            .ok_or(SecretError::InvalidCredentials)?;
+        let token_type = serde_json::from_value(refresh.claims["token_type"].clone()).unwrap_or(TokenType::VAULT);
+
+        self.issue(&token_type, claims, true)
+    }
+
+    async fn token_from_raw(&self, token_type: &TokenType, token: &str) -> SecretResult<TokenWrap> {
+        let token_data = self.verify(token, Some(token_type.client_id()))?;
+        Ok(TokenWrap::new(token_type.clone(), token_data, token.to_string()))
+    }
+
+    async fn exchange_token(&mut self, subject: &TokenWrap, token_type: &TokenType) -> SecretResult<TokenWrap> {
+        let subject = self.verify(subject.raw(), None)?;
+        if subject.claims["typ"] == REFRESH_TOKEN_TYPE {
+            return Err(SecretError::TokenExchange(
+                "Refresh tokens can't be exchanged".to_string(),
+            ));
+        }
+
+        // Claims are issued from the current configuration, so removed users and clients can't exchange old tokens
+        let claims = subject.claims["sub"]
+            .as_str()
+            .and_then(|sub| self.subject_claims(sub))
+            .ok_or(SecretError::InvalidCredentials)?;
+        self.issue(token_type, claims, false)
+    }
+
+    fn session_refresh(&self) -> Option<String> {
+        self.session_refresh.clone()
+    }
+
+    fn set_session_refresh(&mut self, refresh_token: String) {
+        self.session_refresh.replace(refresh_token);
+    }
+}
+
+fn read_key(path: &str) -> SecretResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| SecretError::Configuration(format!("Could not read key {}: {}", path, e)))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn manager() -> StaticSecretManager {
+        StaticSecretManager::new(StaticSecretConfiguration {
+            issuer: "https://static.demia.test".to_string(),
+            key: StaticKey::Hmac {
+                secret: base64::engine::general_purpose::STANDARD.encode(b"a static secret for tests"),
+            },
+            users: vec![StaticUser {
+                username: "alice".to_string(),
+                password: "password".to_string(),
+                sub: "alice-sub".to_string(),
+                email: None,
+                nickname: None,
+                roles: vec!["user".to_string()],
+            }],
+            clients: HashMap::new(),
+            token_lifetime: 300,
+            refresh_lifetime: 3600,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_exchange() {
+        let mut manager = manager();
+        let subject = manager.get_token(&TokenType::VAULT, "alice", "password").await.unwrap();
+        let refresh = manager.session_refresh();
+
+        let exchanged = manager.exchange_token(&subject, &TokenType::AWS).await.unwrap();
+        assert_eq!(exchanged.token_type(), &TokenType::AWS);
+        assert_eq!(exchanged.get_sub(), Some("alice-sub".to_string()));
+        assert_eq!(exchanged.token_data().claims["aud"], json!(TokenType::AWS.client_id()));
+        assert_eq!(manager.session_refresh(), refresh);
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let mut manager = manager();
+        let access = manager.get_token(&TokenType::AWS, "alice", "password").await.unwrap();
+
+        let refreshed = manager.refresh_token().await.unwrap();
+        assert_eq!(refreshed.token_type(), &TokenType::AWS);
+        assert_eq!(refreshed.get_sub(), access.get_sub());
+        assert_eq!(refreshed.token_data().claims["aud"], json!(TokenType::AWS.client_id()));
+        assert!(refreshed.token_data().claims.get("typ").is_none());
+        assert!(manager.session_refresh().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_access_token_is_not_refreshed() {
+        let mut manager = manager();
+        let access = manager.get_token(&TokenType::VAULT, "alice", "password").await.unwrap();
+
+        manager.set_session_refresh(access.raw().to_string());
+        assert!(matches!(manager.refresh_token().await, Err(SecretError::Jwt(_))));
+
+        // Even one issued for the refresh audience needs the refresh type
+        let issuer = manager.config.issuer.clone();
+        let untyped = manager
+            .sign(StaticSecretManager::user_claims(&manager.config.users[0]), &issuer, 300)
+            .unwrap();
+        manager.set_session_refresh(untyped);
+        assert!(matches!(manager.refresh_token().await, Err(SecretError::Jwt(_))));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_is_not_exchanged() {
+        let mut manager = manager();
+        manager.get_token(&TokenType::VAULT, "alice", "password").await.unwrap();
+        let refresh = manager.session_refresh().unwrap();
+        let subject = TokenWrap::new(TokenType::VAULT, manager.verify(&refresh, None).unwrap(), refresh);
+
+        assert!(matches!(
+            manager.exchange_token(&subject, &TokenType::AWS).await,
+            Err(SecretError::TokenExchange(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_removed_user_is_not_exchanged() {
+        let mut manager = manager();
+        let subject = manager.get_token(&TokenType::VAULT, "alice", "password").await.unwrap();
+        manager.config.users.clear();
+
+        assert!(matches!(
+            manager.exchange_token(&subject, &TokenType::AWS).await,
+            Err(SecretError::InvalidCredentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_service_account_exchange() {
+        let mut manager = manager();
+        manager
+            .config
+            .clients
+            .insert(TokenType::AWS.client_id().to_string(), "secret".to_string());
+        let subject = manager.get_token_with_secret(&TokenType::AWS, "secret").await.unwrap();
+
+        let exchanged = manager.exchange_token(&subject, &TokenType::VAULT).await.unwrap();
+        assert_eq!(exchanged.get_sub(), subject.get_sub());
+
+        manager.config.clients.clear();
+        assert!(manager.exchange_token(&subject, &TokenType::VAULT).await.is_err());
+    }
+}
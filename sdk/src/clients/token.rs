@@ -3,9 +3,10 @@ use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
 
 use crate::{
-    clients::SecretManager,
+    clients::{PersistedSession, SecretManager, SessionStore},
     errors::{SecretError, SecretResult},
     models::{TokenType, TokenWrap},
+    telemetry::{self, Call},
 };
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -15,6 +16,14 @@ pub struct TokenManager {
 
     #[serde(skip_serializing, skip_deserializing, default = "crate::clients::default_secret")]
     secret_manager: Box<dyn SecretManager>,
+
+    /// Opt-in storage for the refresh token, so a restart doesn't require a new login
+    #[serde(skip_serializing, skip_deserializing)]
+    session_store: Option<Arc<dyn SessionStore>>,
+
+    /// Type of the token obtained at login, used as the subject of token exchanges
+    #[serde(skip_serializing, skip_deserializing)]
+    subject_type: Option<TokenType>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -28,6 +37,8 @@ impl Default for TokenManager {
         Self {
             tokens: Default::default(),
             secret_manager: crate::clients::default_secret(),
+            session_store: None,
+            subject_type: None,
         }
     }
 }
@@ -44,8 +55,12 @@ impl SecretManager for TokenManager {
             }
         }
 
-        let token = self.secret_manager.get_token(token_type, username, password).await?;
+        let token = Self::call("get_token", token_type)
+            .observe(self.secret_manager.get_token(token_type, username, password))
+            .await?;
         self.tokens.write().await.insert(token_type.clone(), token.clone());
+        self.subject_type = Some(token_type.clone());
+        self.save_session().await;
 
         Ok(token)
     }
@@ -60,11 +75,12 @@ impl SecretManager for TokenManager {
             }
         }
 
-        let token = self
-            .secret_manager
-            .get_token_with_secret(token_type, client_secret)
+        let token = Self::call("get_token_with_secret", token_type)
+            .observe(self.secret_manager.get_token_with_secret(token_type, client_secret))
             .await?;
         self.tokens.write().await.insert(token_type.clone(), token.clone());
+        self.subject_type = Some(token_type.clone());
+        self.save_session().await;
 
         Ok(token)
     }
@@ -72,7 +88,13 @@ impl SecretManager for TokenManager {
     /// Refreshes the "refresh" token. Doesn't update tokens held by the tokenmanager.
     /// That operation is called refresh_token_type() or refresh()
     async fn refresh_token(&mut self) -> SecretResult<TokenWrap> {
-        self.secret_manager.refresh_token().await
+        let token = Call::new("secret_manager", "refresh_token")
+            .observe(self.secret_manager.refresh_token())
+            .await;
+        telemetry::record_token_refresh("secret_manager", token.is_ok());
+        let token = token?;
+        self.save_session().await;
+        Ok(token)
     }
 
     /// Creates a TokenWrap for a raw id token string and stores the token locally. This is for API
@@ -80,27 +102,166 @@ impl SecretManager for TokenManager {
     async fn token_from_raw(&self, token_type: &TokenType, token: &str) -> SecretResult<TokenWrap> {
         self.secret_manager.token_from_raw(token_type, token).await
     }
+
+    async fn exchange_token(&mut self, subject: &TokenWrap, token_type: &TokenType) -> SecretResult<TokenWrap> {
+        Self::call("exchange_token", token_type)
+            .observe(self.secret_manager.exchange_token(subject, token_type))
+            .await
+    }
+
+    fn session_refresh(&self) -> Option<String> {
+        self.secret_manager.session_refresh()
+    }
+
+    fn set_session_refresh(&mut self, refresh_token: String) {
+        self.secret_manager.set_session_refresh(refresh_token)
+    }
 }
 
 impl TokenManager {
+    fn call(operation: &str, token_type: &TokenType) -> Call {
+        Call::new("secret_manager", operation).with("token_type", token_type.name())
+    }
+
     pub fn new(secret_manager: Box<impl SecretManager + 'static>) -> Self {
         Self {
             tokens: Default::default(),
             secret_manager,
+            session_store: None,
+            subject_type: None,
         }
     }
 
-    /// Refreshes the refresh token and all active tokens held by the token manager.
-    pub async fn refresh(&mut self) -> SecretResult<()> {
-        let new_token = self.refresh_token().await?;
-        let mut new_tokens: HashMap<TokenType, TokenWrap> = HashMap::default();
-        for el in self.tokens.clone().read().await.keys() {
-            new_tokens.insert(el.clone(), new_token.clone());
+    /// Enables session persistence. Every new refresh token is saved to the store
+    pub fn with_session_store(mut self, store: impl SessionStore + 'static) -> Self {
+        self.session_store = Some(Arc::new(store));
+        self
+    }
+
+    pub fn set_session_store(&mut self, store: Arc<dyn SessionStore>) {
+        self.session_store = Some(store);
+    }
+
+    /// Saves the current refresh token if session persistence is enabled
+    pub async fn persist_session(&self) -> SecretResult<()> {
+        if let (Some(store), Some(refresh_token)) = (&self.session_store, self.secret_manager.session_refresh()) {
+            store.save(&PersistedSession::new(refresh_token)).await?;
         }
-        self.tokens.write().await.extend(new_tokens);
         Ok(())
     }
 
+    /// Saves the session after a successful login or refresh. A failure only costs the next restart a login, so
+    /// it doesn't fail the token request
+    async fn save_session(&self) {
+        if let Err(e) = self.persist_session().await {
+            log::warn!("Could not persist session: {}", e);
+        }
+    }
+
+    /// Resumes a persisted session by exchanging the stored refresh token for a fresh token.
+    /// Returns false if persistence is disabled or nothing was stored.
+    pub async fn restore_session(&mut self) -> SecretResult<bool> {
+        let store = match &self.session_store {
+            Some(store) => store.clone(),
+            None => return Ok(false),
+        };
+        let session = match store.load().await? {
+            Some(session) => session,
+            None => return Ok(false),
+        };
+
+        match self.resume(session.refresh_token).await {
+            Ok(_) => Ok(true),
+            // Revoked or expired, a new login is needed either way
+            Err(e @ SecretError::InvalidGrant(_)) => {
+                log::warn!("Persisted session was rejected: {}", e);
+                store.clear().await?;
+                Err(e)
+            }
+            // The provider may be unreachable for now, the session stays for the next attempt
+            Err(e) => {
+                log::warn!("Could not restore persisted session: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Starts from an existing refresh token instead of a login, e.g. one held by a server for a user
+    pub async fn resume(&mut self, refresh_token: String) -> SecretResult<TokenWrap> {
+        self.secret_manager.set_session_refresh(refresh_token);
+        let token = Call::new("secret_manager", "refresh_token")
+            .observe(self.secret_manager.refresh_token())
+            .await;
+        telemetry::record_token_refresh("secret_manager", token.is_ok());
+        let token = token?;
+        self.subject_type = Some(token.token_type().clone());
+        self.tokens
+            .write()
+            .await
+            .insert(token.token_type().clone(), token.clone());
+        self.save_session().await;
+        Ok(token)
+    }
+
+    /// Removes the persisted session, e.g. on logout
+    pub async fn clear_session(&self) -> SecretResult<()> {
+        match &self.session_store {
+            Some(store) => store.clear().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Refreshes the login token through the refresh token. Tokens of other audiences are dropped, as the login token
+    /// can't stand in for them. [`TokenManager::exchange`] exchanges them again when needed
+    pub async fn refresh(&mut self) -> SecretResult<()> {
+        let token = self.refresh_token().await?;
+        let subject_type = self.subject_type.clone().unwrap_or_else(|| token.token_type().clone());
+        let mut tokens = self.tokens.write().await;
+        tokens.clear();
+        tokens.insert(subject_type.clone(), token);
+        self.subject_type = Some(subject_type);
+        Ok(())
+    }
+
+    /// Returns an unexpired token for the audience of `token_type`, exchanging the login token for it when needed.
+    /// The login token is renewed through the session refresh token, so no credentials have to be kept around.
+    pub async fn exchange(&mut self, token_type: &TokenType) -> SecretResult<TokenWrap> {
+        {
+            let lock = self.tokens.read().await;
+            if let Some(token) = lock.get(token_type) {
+                if !token.is_expired() {
+                    return Ok(token.clone());
+                }
+            }
+        }
+
+        let subject = self.subject_token().await?;
+        if subject.token_type() == token_type {
+            return Ok(subject);
+        }
+
+        let token = self.exchange_token(&subject, token_type).await?;
+        self.tokens.write().await.insert(token_type.clone(), token.clone());
+        Ok(token)
+    }
+
+    async fn subject_token(&mut self) -> SecretResult<TokenWrap> {
+        let subject_type = self
+            .subject_type
+            .clone()
+            .ok_or_else(|| SecretError::TokenNotFound("login".to_string()))?;
+
+        if let Some(token) = self.tokens.read().await.get(&subject_type) {
+            if !token.is_expired() {
+                return Ok(token.clone());
+            }
+        }
+
+        let token = self.refresh_token().await?;
+        self.tokens.write().await.insert(subject_type, token.clone());
+        Ok(token)
+    }
+
     /// Refreshes the specific token regardless wether its expired or not
     async fn _refresh_token_type(&mut self, token_type: TokenType, username: &str, password: &str) -> SecretResult<()> {
         let token = self.secret_manager.get_token(&token_type, username, password).await?;
@@ -147,3 +308,96 @@ impl TokenManager {
         }
     }
 }
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests {
+    use super::*;
+    use crate::{
+        clients::{EncryptedFileSessionStore, Keycloak},
+        configuration::ApplicationConfiguration,
+        test_support::{MockClaims, MockOidcConfig, MockOidcIssuer, MockUser},
+        utils::new_stronghold_key,
+    };
+
+    fn session_store() -> EncryptedFileSessionStore {
+        let path = std::env::temp_dir().join(format!("demia-session-{}", uuid::Uuid::new_v4()));
+        EncryptedFileSessionStore::new(path, &new_stronghold_key()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_restore_session() {
+        let user = MockUser::new("alice", "correct horse");
+        let issuer = MockOidcIssuer::start(MockOidcConfig::default()).await.unwrap();
+        let store = session_store();
+        let refresh_token = issuer.token_response(&MockClaims::from(&user)).refresh_token;
+        store.save(&PersistedSession::new(refresh_token)).await.unwrap();
+
+        let mut manager = TokenManager::new(Box::new(issuer.keycloak())).with_session_store(store);
+        assert!(manager.restore_session().await.unwrap());
+        assert!(manager.get_status(TokenType::VAULT).await);
+        // The refresh token is single use, so the new one replaces it
+        let saved = manager.session_store.as_ref().unwrap().load().await.unwrap().unwrap();
+        assert_eq!(Some(saved.refresh_token), manager.session_refresh());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_drops_exchanged_tokens() {
+        let user = MockUser::new("alice", "correct horse");
+        let issuer = MockOidcIssuer::start(MockOidcConfig {
+            users: vec![user.clone()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let mut manager = TokenManager::new(Box::new(issuer.keycloak()));
+        manager
+            .get_token(&TokenType::VAULT, &user.username, &user.password)
+            .await
+            .unwrap();
+        let audience = |token: &TokenWrap| token.token_data().claims["aud"].clone();
+
+        let exchanged = manager.exchange(&TokenType::AWS).await.unwrap();
+        assert_eq!(audience(&exchanged), serde_json::json!([TokenType::AWS.client_id()]));
+
+        manager.refresh().await.unwrap();
+        assert!(manager.get_status(TokenType::VAULT).await);
+        assert!(!manager.get_status(TokenType::AWS).await);
+        // Exchanged again instead of handing out the login token
+        let exchanged = manager.exchange(&TokenType::AWS).await.unwrap();
+        assert_eq!(audience(&exchanged), serde_json::json!([TokenType::AWS.client_id()]));
+    }
+
+    #[tokio::test]
+    async fn test_restore_session_unreachable() {
+        // Nothing listens on the discard port
+        let keycloak = Keycloak::new(&ApplicationConfiguration {
+            secrets_api: "http://127.0.0.1:9".to_string(),
+            ..Default::default()
+        });
+        let store = Arc::new(session_store());
+        store.save(&PersistedSession::new("refresh".to_string())).await.unwrap();
+
+        let mut manager = TokenManager::new(Box::new(keycloak));
+        manager.set_session_store(store.clone());
+        assert!(matches!(
+            manager.restore_session().await,
+            Err(SecretError::ReqwestError(_))
+        ));
+        assert_eq!(store.load().await.unwrap().unwrap().refresh_token, "refresh");
+    }
+
+    #[tokio::test]
+    async fn test_restore_session_rejected() {
+        let issuer = MockOidcIssuer::start(MockOidcConfig::default()).await.unwrap();
+        let store = Arc::new(session_store());
+        store.save(&PersistedSession::new("revoked".to_string())).await.unwrap();
+
+        let mut manager = TokenManager::new(Box::new(issuer.keycloak()));
+        manager.set_session_store(store.clone());
+        assert!(matches!(
+            manager.restore_session().await,
+            Err(SecretError::InvalidGrant(_))
+        ));
+        assert!(store.load().await.unwrap().is_none());
+    }
+}
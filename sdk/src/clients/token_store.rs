@@ -0,0 +1,414 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{Mutex, RwLock};
+
+use crate::{
+    clients::{SecretManager, TokenManager},
+    errors::{SecretError, SecretResult},
+    models::{TokenType, TokenWrap},
+    utils::SESSION_IDLE_TIMEOUT,
+};
+
+pub const DEFAULT_MAX_SESSIONS: usize = 10_000;
+pub const DEFAULT_MAX_VALIDATED: usize = 50_000;
+
+type ManagerFactory = Box<dyn Fn() -> TokenManager + Send + Sync>;
+
+struct UserSession {
+    manager: Mutex<TokenManager>,
+    last_used: StdMutex<Instant>,
+}
+
+impl UserSession {
+    fn new(manager: TokenManager) -> Self {
+        Self {
+            manager: Mutex::new(manager),
+            last_used: StdMutex::new(Instant::now()),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_used.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used.lock().unwrap().elapsed()
+    }
+}
+
+/// Tokens of many users at once, for servers acting on behalf of their callers.
+///
+/// Every user gets their own [`TokenManager`] keyed by the token `sub`, holding that user's refresh token. Sessions
+/// idle for longer than the idle timeout are evicted, as is the least recently used one when the store is full.
+/// Requests for the same user are serialized, so concurrent callers that find an expired token trigger a single
+/// refresh and all receive its result.
+///
+/// Incoming bearer tokens are validated through [`TokenStore::validate`], which keeps validated tokens until they
+/// expire.
+pub struct TokenStore {
+    factory: ManagerFactory,
+    validator: Box<dyn SecretManager>,
+    sessions: RwLock<HashMap<String, Arc<UserSession>>>,
+    validated: RwLock<HashMap<(TokenType, String), TokenWrap>>,
+    max_sessions: usize,
+    max_validated: usize,
+    idle_timeout: Duration,
+}
+
+impl Debug for TokenStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenStore")
+            .field("validator", &self.validator)
+            .field("max_sessions", &self.max_sessions)
+            .field("max_validated", &self.max_validated)
+            .field("idle_timeout", &self.idle_timeout)
+            .finish()
+    }
+}
+
+impl TokenStore {
+    /// `factory` creates the secret manager of each user session, plus one used to validate bearer tokens
+    pub fn new<S, F>(factory: F) -> Self
+    where
+        S: SecretManager + 'static,
+        F: Fn() -> S + Send + Sync + 'static,
+    {
+        let validator = Box::new(factory());
+        Self {
+            factory: Box::new(move || TokenManager::new(Box::new(factory()))),
+            validator,
+            sessions: Default::default(),
+            validated: Default::default(),
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            max_validated: DEFAULT_MAX_VALIDATED,
+            idle_timeout: SESSION_IDLE_TIMEOUT,
+        }
+    }
+
+    pub fn with_max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = max_sessions.max(1);
+        self
+    }
+
+    pub fn with_max_validated(mut self, max_validated: usize) -> Self {
+        self.max_validated = max_validated.max(1);
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Logs a user in and keeps the session under the token subject
+    pub async fn login(&self, token_type: &TokenType, username: &str, password: &str) -> SecretResult<TokenWrap> {
+        let mut manager = (self.factory)();
+        let token = manager.get_token(token_type, username, password).await?;
+        self.insert(&token, manager).await?;
+        Ok(token)
+    }
+
+    /// Starts a session from a refresh token the server already holds for the user
+    pub async fn resume(&self, refresh_token: String) -> SecretResult<TokenWrap> {
+        let mut manager = (self.factory)();
+        let token = manager.resume(refresh_token).await?;
+        self.insert(&token, manager).await?;
+        Ok(token)
+    }
+
+    async fn insert(&self, token: &TokenWrap, manager: TokenManager) -> SecretResult<()> {
+        let sub = token
+            .get_sub()
+            .ok_or_else(|| SecretError::Jwt("Token has no subject".to_string()))?;
+
+        let mut sessions = self.sessions.write().await;
+        sessions.retain(|_, session| session.idle_for() < self.idle_timeout);
+        if sessions.len() >= self.max_sessions && !sessions.contains_key(&sub) {
+            if let Some(oldest) = sessions
+                .iter()
+                .max_by_key(|(_, session)| session.idle_for())
+                .map(|(sub, _)| sub.clone())
+            {
+                log::debug!("Token store full, evicting session of {}", oldest);
+                sessions.remove(&oldest);
+            }
+        }
+        sessions.insert(sub, Arc::new(UserSession::new(manager)));
+        Ok(())
+    }
+
+    async fn session(&self, sub: &str) -> SecretResult<Arc<UserSession>> {
+        let session = self
+            .sessions
+            .read()
+            .await
+            .get(sub)
+            .cloned()
+            .ok_or_else(|| SecretError::SessionNotFound(sub.to_string()))?;
+        session.touch();
+        Ok(session)
+    }
+
+    /// An unexpired token of the user, refreshed or exchanged when needed
+    pub async fn token(&self, sub: &str, token_type: &TokenType) -> SecretResult<TokenWrap> {
+        let session = self.session(sub).await?;
+        // Callers queue here while a refresh is running and then find the renewed token cached
+        let mut manager = session.manager.lock().await;
+        manager.exchange(token_type).await
+    }
+
+    /// The current refresh token of the user, e.g. to persist it alongside the user record
+    pub async fn refresh_token(&self, sub: &str) -> SecretResult<Option<String>> {
+        let session = self.session(sub).await?;
+        let manager = session.manager.lock().await;
+        Ok(manager.session_refresh())
+    }
+
+    pub async fn contains(&self, sub: &str) -> bool {
+        self.sessions.read().await.contains_key(sub)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.sessions.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.sessions.read().await.is_empty()
+    }
+
+    /// Ends the session of a user, e.g. on logout
+    pub async fn remove(&self, sub: &str) -> bool {
+        self.sessions.write().await.remove(sub).is_some()
+    }
+
+    /// Drops sessions idle for longer than the idle timeout and expired validated tokens.
+    /// Returns the number of sessions removed.
+    pub async fn evict_idle(&self) -> usize {
+        self.validated.write().await.retain(|_, token| !token.is_expired());
+
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|_, session| session.idle_for() < self.idle_timeout);
+        before - sessions.len()
+    }
+
+    /// Validates a bearer token from an incoming request, with or without the `Bearer ` prefix.
+    /// Tokens seen before are served from cache until they expire, without decoding them again.
+    pub async fn validate(&self, token_type: &TokenType, bearer: &str) -> SecretResult<TokenWrap> {
+        let raw = bearer.strip_prefix("Bearer ").unwrap_or(bearer).trim();
+        let key = (token_type.clone(), raw.to_string());
+
+        if let Some(token) = self.validated.read().await.get(&key) {
+            if !token.is_expired() {
+                return Ok(token.clone());
+            }
+        }
+
+        let token = self.validator.token_from_raw(token_type, raw).await?;
+        if token.is_expired() {
+            return Err(SecretError::Jwt("Token is expired".to_string()));
+        }
+
+        let mut validated = self.validated.write().await;
+        if validated.len() >= self.max_validated {
+            validated.retain(|_, token| !token.is_expired());
+            if validated.len() >= self.max_validated {
+                validated.clear();
+            }
+        }
+        validated.insert(key, token.clone());
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use base64::Engine;
+
+    use super::*;
+    use crate::{
+        clients::StaticSecretManager,
+        configuration::{StaticKey, StaticSecretConfiguration, StaticUser},
+    };
+
+    /// Counts the tokens the store had to decode
+    #[derive(Debug, Clone)]
+    struct CountingManager {
+        inner: StaticSecretManager,
+        decoded: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl SecretManager for CountingManager {
+        async fn get_token(
+            &mut self,
+            token_type: &TokenType,
+            username: &str,
+            password: &str,
+        ) -> SecretResult<TokenWrap> {
+            self.inner.get_token(token_type, username, password).await
+        }
+
+        async fn get_token_with_secret(&mut self, token_type: &TokenType, secret: &str) -> SecretResult<TokenWrap> {
+            self.inner.get_token_with_secret(token_type, secret).await
+        }
+
+        async fn refresh_token(&mut self) -> SecretResult<TokenWrap> {
+            self.inner.refresh_token().await
+        }
+
+        async fn token_from_raw(&self, token_type: &TokenType, token: &str) -> SecretResult<TokenWrap> {
+            self.decoded.fetch_add(1, Ordering::SeqCst);
+            self.inner.token_from_raw(token_type, token).await
+        }
+
+        fn session_refresh(&self) -> Option<String> {
+            self.inner.session_refresh()
+        }
+
+        fn set_session_refresh(&mut self, refresh_token: String) {
+            self.inner.set_session_refresh(refresh_token)
+        }
+    }
+
+    fn user(name: &str) -> StaticUser {
+        StaticUser {
+            username: name.to_string(),
+            password: "password".to_string(),
+            sub: format!("{}-sub", name),
+            email: None,
+            nickname: None,
+            roles: Vec::new(),
+        }
+    }
+
+    fn store() -> (TokenStore, Arc<AtomicUsize>) {
+        let manager = StaticSecretManager::new(StaticSecretConfiguration {
+            issuer: "https://static.demia.test".to_string(),
+            key: StaticKey::Hmac {
+                secret: base64::engine::general_purpose::STANDARD.encode(b"a static secret for tests"),
+            },
+            users: vec![user("alice"), user("bob")],
+            clients: HashMap::new(),
+            token_lifetime: 300,
+            refresh_lifetime: 3600,
+        })
+        .unwrap();
+        let decoded = Arc::new(AtomicUsize::new(0));
+        let counter = decoded.clone();
+        let store = TokenStore::new(move || CountingManager {
+            inner: manager.clone(),
+            decoded: counter.clone(),
+        });
+        (store, decoded)
+    }
+
+    #[tokio::test]
+    async fn test_validation_is_cached() {
+        let (store, decoded) = store();
+        let token = store.login(&TokenType::VAULT, "alice", "password").await.unwrap();
+
+        let bearer = format!("Bearer {}", token.raw());
+        assert_eq!(
+            store.validate(&TokenType::VAULT, &bearer).await.unwrap().get_sub(),
+            Some("alice-sub".to_string())
+        );
+        store.validate(&TokenType::VAULT, token.raw()).await.unwrap();
+        assert_eq!(decoded.load(Ordering::SeqCst), 1);
+
+        // Cached tokens are still bound to the type they were validated for
+        assert!(store.validate(&TokenType::AWS, token.raw()).await.is_err());
+        assert!(store.validate(&TokenType::VAULT, "not a token").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validated_tokens_are_bounded() {
+        let (store, decoded) = store();
+        let store = store.with_max_validated(2);
+        let mut tokens = Vec::new();
+        for token_type in [TokenType::VAULT, TokenType::AWS, TokenType::AUTH0] {
+            let token = store.login(&token_type, "alice", "password").await.unwrap();
+            store.validate(&token_type, token.raw()).await.unwrap();
+            tokens.push(token);
+        }
+        assert_eq!(decoded.load(Ordering::SeqCst), 3);
+
+        // The cache was cleared when the third token did not fit, so the first is decoded again
+        store.validate(&TokenType::VAULT, tokens[0].raw()).await.unwrap();
+        assert_eq!(decoded.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_session_is_evicted() {
+        let (store, _) = store();
+        let store = store.with_max_sessions(1);
+        store.login(&TokenType::VAULT, "alice", "password").await.unwrap();
+        store.login(&TokenType::VAULT, "bob", "password").await.unwrap();
+
+        assert_eq!(store.len().await, 1);
+        assert!(!store.contains("alice-sub").await);
+        assert!(store.token("bob-sub", &TokenType::VAULT).await.is_ok());
+        assert!(matches!(
+            store.token("alice-sub", &TokenType::VAULT).await,
+            Err(SecretError::SessionNotFound(_))
+        ));
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn test_concurrent_refreshes_are_deduplicated() {
+        use crate::{
+            clients::Keycloak,
+            test_support::{MockOidcConfig, MockOidcIssuer, MockUser},
+        };
+
+        let user = MockUser::new("alice", "correct horse");
+        let issuer = MockOidcIssuer::start(MockOidcConfig {
+            users: vec![user.clone()],
+            token_lifetime: Duration::from_secs(2),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let config = issuer.application_config();
+        let store = Arc::new(TokenStore::new(move || Keycloak::new(&config)));
+        store
+            .login(&TokenType::VAULT, &user.username, &user.password)
+            .await
+            .unwrap();
+
+        // Let the login token expire, so every caller needs a refreshed one
+        tokio::time::sleep(Duration::from_millis(2100)).await;
+        let calls = (0..8).map(|_| {
+            let (store, sub) = (store.clone(), user.sub.clone());
+            tokio::spawn(async move { store.token(&sub, &TokenType::VAULT).await })
+        });
+        let tokens = futures_util::future::join_all(calls).await;
+
+        assert_eq!(issuer.grant_count("refresh_token"), 1);
+        let raw = tokens
+            .into_iter()
+            .map(|token| token.unwrap().unwrap().raw().to_string())
+            .collect::<Vec<_>>();
+        assert!(raw.iter().all(|token| token == &raw[0]));
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_are_evicted() {
+        let (store, _) = store();
+        let store = store.with_idle_timeout(Duration::from_millis(50));
+        store.login(&TokenType::VAULT, "alice", "password").await.unwrap();
+        assert_eq!(store.evict_idle().await, 0);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(store.evict_idle().await, 1);
+        assert!(store.is_empty().await);
+    }
+}
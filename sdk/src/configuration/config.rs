@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use identity_demia::{credential::Credential, document::CoreDocument};
 use serde::{Deserialize, Serialize};
 
-use crate::utils::constants::*;
+use crate::{models::GuardianRole, utils::constants::*};
 
 fn base_url() -> String {
     "http://localhost:14265".to_string()
@@ -33,6 +33,14 @@ fn debug_location() -> String {
     "log.out".to_string()
 }
 
+fn telemetry_service_name() -> String {
+    TELEMETRY_SERVICE_NAME.to_string()
+}
+
+fn telemetry_export_interval() -> u64 {
+    TELEMETRY_EXPORT_INTERVAL.as_secs()
+}
+
 fn local_api() -> String {
     LOCAL_API.to_string()
 }
@@ -49,6 +57,58 @@ fn secrets_api() -> String {
     SECRETS_API.to_string()
 }
 
+fn vault_api() -> String {
+    VAULT_API.to_string()
+}
+
+fn vault_kv_mount() -> String {
+    VAULT_KV_MOUNT.to_string()
+}
+
+fn vault_jwt_mount() -> String {
+    VAULT_JWT_MOUNT.to_string()
+}
+
+fn vault_jwt_role() -> Option<String> {
+    Some(VAULT_JWT_ROLE.to_string())
+}
+
+fn vault_external_jwt_mount() -> String {
+    VAULT_EXTERNAL_JWT_MOUNT.to_string()
+}
+
+fn vault_approle_mount() -> String {
+    VAULT_APPROLE_MOUNT.to_string()
+}
+
+fn vault_kubernetes_mount() -> String {
+    VAULT_KUBERNETES_MOUNT.to_string()
+}
+
+fn vault_transit_mount() -> String {
+    VAULT_TRANSIT_MOUNT.to_string()
+}
+
+fn static_issuer() -> String {
+    STATIC_ISSUER.to_string()
+}
+
+fn static_token_lifetime() -> u64 {
+    STATIC_TOKEN_LIFETIME
+}
+
+fn static_refresh_lifetime() -> u64 {
+    STATIC_REFRESH_LIFETIME
+}
+
+fn guardian_approve_button() -> String {
+    GUARDIAN_APPROVE_BUTTON.to_string()
+}
+
+fn guardian_reject_button() -> String {
+    GUARDIAN_REJECT_BUTTON.to_string()
+}
+
 fn public_bucket_path() -> String {
     PUBLIC_BUCKET_PATH.to_string()
 }
@@ -69,6 +129,10 @@ pub struct BaseConfiguration {
     pub stronghold: StrongholdConfiguration,
     #[serde(default)]
     pub identity: IdentityConfiguration,
+    #[serde(default)]
+    pub vault: VaultConfiguration,
+    #[serde(default)]
+    pub telemetry: TelemetryConfiguration,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -79,6 +143,28 @@ pub struct LoggingConfiguration {
     pub debug_location: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TelemetryConfiguration {
+    /// OTLP/HTTP collector, e.g. `http://localhost:4318`. Spans and metrics are only exported when set and the `otlp`
+    /// feature is enabled
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    #[serde(default = "telemetry_service_name")]
+    pub service_name: String,
+    #[serde(default = "telemetry_export_interval")]
+    pub export_interval_secs: u64,
+}
+
+impl Default for TelemetryConfiguration {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: telemetry_service_name(),
+            export_interval_secs: telemetry_export_interval(),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ApplicationConfiguration {
     pub username: String,
@@ -90,11 +176,20 @@ pub struct ApplicationConfiguration {
     pub retriever_api: String,
     #[serde(default = "guardian_api")]
     pub guardian_api: String,
+    /// Audience of the Auth0 Management API. Defaults to `https://<host of secrets_api>/api/v2/`, so it has to be set
+    /// to the canonical `https://<tenant>.auth0.com/api/v2/` when `secrets_api` is a custom domain
+    #[serde(default)]
+    pub auth0_management_audience: Option<String>,
+    /// API identifier requested by Auth0 client credentials tokens other than `TokenType::Auth0Admin`
+    #[serde(default)]
+    pub auth0_api_audience: Option<String>,
 
     #[serde(default = "public_bucket_path")]
     pub public_bucket_path: String,
     #[serde(default = "protected_bucket_path")]
     pub protected_bucket_path: String,
+    #[serde(default)]
+    pub guardian_workflows: Vec<GuardianWorkflowConfiguration>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -116,6 +211,87 @@ pub struct StrongholdConfiguration {
     pub password: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultConfiguration {
+    #[serde(default = "vault_api")]
+    pub address: String,
+    /// kv2 mount holding the stronghold passwords
+    #[serde(default = "vault_kv_mount")]
+    pub kv_mount: String,
+    /// jwt auth mount for `TokenType::VAULT` tokens
+    #[serde(default = "vault_jwt_mount")]
+    pub jwt_mount: String,
+    #[serde(default = "vault_jwt_role")]
+    pub jwt_role: Option<String>,
+    /// jwt auth mount for every other token type
+    #[serde(default = "vault_external_jwt_mount")]
+    pub external_jwt_mount: String,
+    #[serde(default = "vault_approle_mount")]
+    pub approle_mount: String,
+    #[serde(default = "vault_kubernetes_mount")]
+    pub kubernetes_mount: String,
+    /// transit engine mount used for server side signing
+    #[serde(default = "vault_transit_mount")]
+    pub transit_mount: String,
+}
+
+impl Default for VaultConfiguration {
+    fn default() -> Self {
+        Self {
+            address: vault_api(),
+            kv_mount: vault_kv_mount(),
+            jwt_mount: vault_jwt_mount(),
+            jwt_role: vault_jwt_role(),
+            external_jwt_mount: vault_external_jwt_mount(),
+            approle_mount: vault_approle_mount(),
+            kubernetes_mount: vault_kubernetes_mount(),
+            transit_mount: vault_transit_mount(),
+        }
+    }
+}
+
+/// Issuer used by the `StaticSecretManager` in place of Keycloak, for offline and development setups
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaticSecretConfiguration {
+    #[serde(default = "static_issuer")]
+    pub issuer: String,
+    pub key: StaticKey,
+    #[serde(default)]
+    pub users: Vec<StaticUser>,
+    /// Client secrets for the client credentials grant, keyed by client id
+    #[serde(default)]
+    pub clients: HashMap<String, String>,
+    /// Access token lifetime in seconds
+    #[serde(default = "static_token_lifetime")]
+    pub token_lifetime: u64,
+    /// Refresh token lifetime in seconds
+    #[serde(default = "static_refresh_lifetime")]
+    pub refresh_lifetime: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StaticKey {
+    /// HS256 with a base64 encoded shared secret
+    Hmac { secret: String },
+    /// RS256 with a key pair read from PEM files. Needed when Vault has to verify the tokens
+    Rsa { private_key: String, public_key: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StaticUser {
+    pub username: String,
+    pub password: String,
+    pub sub: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub nickname: Option<String>,
+    /// Issued as realm roles
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IdentityConfiguration {
     #[serde(default)]
@@ -159,3 +335,83 @@ pub struct GuardianConfigs {
     pub policy: String,
     pub vcs: HashMap<String, (String, Credential)>,
 }
+
+/// A multi-step flow through a Guardian policy, run by `GuardianWorkflow`.
+///
+/// Steps run in order and pass documents to each other by name. A VVB verification could look like
+/// ```json
+/// {
+///   "name": "verify_report",
+///   "policy_id": "64f...",
+///   "steps": [
+///     { "role": "VVB", "tag": "vvb_grid", "action": "select_document", "document_type": "report", "save_as": "report" },
+///     { "role": "VVB", "tag": "approve_report_btn", "action": "approve", "document": "report" }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardianWorkflowConfiguration {
+    pub name: String,
+    pub policy_id: String,
+    pub steps: Vec<GuardianWorkflowStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardianWorkflowStep {
+    /// Account the step is run as
+    #[serde(default)]
+    pub role: GuardianRole,
+    /// Tag of the block, resolved through the policy
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Block type, e.g. `interfaceDocumentsSourceBlock`. The first block of the type in the tree is used when no tag
+    /// is set, otherwise the tagged block must have this type
+    #[serde(default)]
+    pub block_type: Option<String>,
+    #[serde(flatten)]
+    pub action: GuardianAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum GuardianAction {
+    /// Picks a document served by the block, e.g. an interfaceDocumentsSource grid
+    SelectDocument {
+        #[serde(default)]
+        document_type: Option<String>,
+        /// Json pointers into the document with the values they must have, e.g.
+        /// `"/document/credentialSubject/0/field0": "report-1"`
+        #[serde(default)]
+        filter: HashMap<String, serde_json::Value>,
+        save_as: String,
+        /// Seconds to keep polling for the document, Guardian processes submissions in the background
+        #[serde(default)]
+        wait: u64,
+    },
+    /// Submits an input or a saved value as a new VC through a request-VC block
+    RequestVc {
+        input: String,
+        /// Saved document the new one links to
+        #[serde(default)]
+        reference: Option<String>,
+        #[serde(default)]
+        save_as: Option<String>,
+    },
+    /// Presses a button of the block on a saved document
+    Button {
+        button: String,
+        document: String,
+        #[serde(default)]
+        save_as: Option<String>,
+    },
+    Approve {
+        document: String,
+        #[serde(default = "guardian_approve_button")]
+        button: String,
+    },
+    Reject {
+        document: String,
+        #[serde(default = "guardian_reject_button")]
+        button: String,
+    },
+}
@@ -12,6 +12,10 @@ pub enum ApiError {
     #[error("Reqwest Error")]
     ReqwestError(String),
 
+    /// No connection could be made, the request never reached the server
+    #[error("Connection failed: {0}")]
+    Unreachable(String),
+
     #[error("Bad Request")]
     BadRequest,
     #[error("Internal Server Error")]
@@ -26,8 +30,27 @@ pub enum ApiError {
     #[error("Guardian {0}")]
     Guardian(String),
 
+    #[error("Guardian responded {code} for url {url}: {message}")]
+    GuardianResponse { code: u16, message: String, url: String },
+
+    #[error("Invalid proof: {0}")]
+    InvalidProof(String),
+
     #[error("Serde {0}")]
     Serde(String),
+
+    #[error("Circuit open for {0}, the service is failing")]
+    CircuitOpen(String),
+
+    #[error("Secret {0}")]
+    Secret(String),
+
+    #[error("Configuration {0}")]
+    Configuration(String),
+
+    /// The token does not grant the action, it was not sent
+    #[error("Not authorized: {0}")]
+    NotAuthorized(String),
 }
 
 impl From<url::ParseError> for ApiError {
@@ -42,6 +65,18 @@ impl From<reqwest::Error> for ApiError {
     }
 }
 
+impl From<crate::errors::SecretError> for ApiError {
+    fn from(error: crate::errors::SecretError) -> Self {
+        Self::Secret(error.to_string())
+    }
+}
+
+impl From<crate::errors::UserError> for ApiError {
+    fn from(error: crate::errors::UserError) -> Self {
+        Self::NotAuthorized(error.to_string())
+    }
+}
+
 impl From<serde_json::Error> for ApiError {
     fn from(error: serde_json::Error) -> Self {
         Self::Serde(error.to_string())
@@ -45,6 +45,12 @@ pub enum IdentityError {
 
     #[error("Identity document does not contain a method for fragment {0}")]
     MissingIdentityMethod(String),
+
+    #[error("A Vault error has occurred: {0}")]
+    VaultError(String),
+
+    #[error("Vault client has no user to resolve the stronghold secret for")]
+    NoVaultUser,
 }
 
 impl IdentityError {
@@ -94,6 +100,13 @@ impl From<iota_sdk::client::stronghold::Error> for IdentityError {
     }
 }
 
+impl From<vaultrs::error::ClientError> for IdentityError {
+    fn from(error: vaultrs::error::ClientError) -> Self {
+        log::debug!("Error: {}", error);
+        Self::VaultError(error.to_string())
+    }
+}
+
 impl From<iota_stronghold::ClientError> for IdentityError {
     fn from(error: iota_stronghold::ClientError) -> Self {
         log::debug!("Error: {}", error);
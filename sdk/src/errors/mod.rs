@@ -4,7 +4,9 @@ mod identification_error;
 mod node;
 mod secret;
 mod storage;
+mod telemetry;
 mod user_error;
+mod validation;
 
 pub use analytics::{AnalyticsError, AnalyticsResult};
 pub use api_error::{ApiError, ApiResult};
@@ -14,8 +16,10 @@ pub use secret::{SecretError, SecretResult};
 use serde::{Deserialize, Serialize};
 pub use storage::{StorageError, StorageResult};
 pub use streams::Error as StreamsError;
+pub use telemetry::{TelemetryError, TelemetryResult};
 use thiserror::Error;
 pub use user_error::{UserError, UserResult};
+pub use validation::{ValidationError, ValidationErrors, ValidationResult};
 
 pub type SdkResult<T> = core::result::Result<T, Error>;
 
@@ -56,6 +60,9 @@ pub enum Error {
 
     #[error("Config load Error: {0}")]
     Configuration(String),
+
+    #[error("Validation error: {0}")]
+    Validation(#[from] ValidationErrors),
 }
 
 impl From<streams::Error> for Error {
@@ -16,6 +16,25 @@ pub enum SecretError {
 
     #[error("JWT error {0}")]
     Jwt(String),
+
+    #[error("Invalid credentials")]
+    InvalidCredentials,
+
+    #[error("Configuration error: {0}")]
+    Configuration(String),
+
+    #[error("No session for user {0}")]
+    SessionNotFound(String),
+
+    #[error("Session persistence error: {0}")]
+    Session(String),
+
+    #[error("Token exchange error: {0}")]
+    TokenExchange(String),
+
+    /// The token endpoint definitively rejected the grant, e.g. a revoked or expired refresh token
+    #[error("Invalid grant: {0}")]
+    InvalidGrant(String),
 }
 
 #[cfg(feature = "google_cloud")]
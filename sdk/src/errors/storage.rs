@@ -20,6 +20,8 @@ pub enum StorageError {
     Credentials,
     #[error("Download file request was denied due to no update needed")]
     NotModified,
+    #[error("No object \"{0}\"")]
+    NotFound(String),
     #[error("Invalid name for file \"{0}\"")]
     InvalidName(String),
 }
@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+pub type TelemetryResult<T> = core::result::Result<T, TelemetryError>;
+
+#[derive(Clone, Debug, Error)]
+pub enum TelemetryError {
+    #[error("Could not build the {0} exporter: {1}")]
+    Exporter(&'static str, String),
+    #[error("A tracing subscriber is already installed: {0}")]
+    Subscriber(String),
+}
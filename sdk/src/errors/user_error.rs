@@ -33,6 +33,12 @@ pub enum UserError {
     #[error("No site attached to user with ID {0}")]
     SiteNotFound(String),
 
+    #[error("No access to site {0}")]
+    NoSiteAccess(String),
+
     #[error("This site action requires admin permissions")]
     NotSiteAdmin,
+
+    #[error("Missing permission: {0}")]
+    MissingPermission(String),
 }
@@ -0,0 +1,34 @@
+use rocket_okapi::okapi::schemars;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub type ValidationResult<T> = core::result::Result<T, ValidationErrors>;
+
+/// A value that does not match its schema, at the JSON pointer `path`
+#[derive(Clone, Debug, PartialEq, Eq, schemars::JsonSchema, Serialize, Deserialize)]
+pub struct ValidationError {
+    pub path: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(path: &str, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.path.is_empty() {
+            true => write!(f, "/: {}", self.message),
+            false => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Error, schemars::JsonSchema, Serialize, Deserialize)]
+#[error("Invalid payload: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+pub struct ValidationErrors(pub Vec<ValidationError>);
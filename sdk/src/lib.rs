@@ -7,6 +7,11 @@ pub mod configuration;
 pub mod errors;
 pub mod logger;
 pub mod models;
+pub mod schema;
+pub mod session;
+pub mod telemetry;
+#[cfg(feature = "test-support")]
+pub mod test_support;
 pub mod utils;
 
 pub use isocountry::{self, CountryCode};
@@ -0,0 +1,75 @@
+use rocket_okapi::okapi::schemars;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// `app_metadata` key holding the DID of the user's identity
+pub const AUTH0_DID_METADATA: &str = "did";
+
+/// A user as returned by the Auth0 Management API. `user_id` includes the connection prefix (`auth0|...`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct Auth0User {
+    pub user_id: String,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub nickname: Option<String>,
+    #[serde(default)]
+    pub picture: Option<String>,
+    #[serde(default)]
+    pub blocked: bool,
+    #[serde(default)]
+    pub app_metadata: Map<String, Value>,
+    #[serde(default)]
+    pub user_metadata: Map<String, Value>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub last_login: Option<String>,
+}
+
+impl Auth0User {
+    pub fn did(&self) -> Option<&str> {
+        self.app_metadata.get(AUTH0_DID_METADATA).and_then(Value::as_str)
+    }
+}
+
+/// Payload for creating a user in a database connection
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct NewAuth0User {
+    pub connection: String,
+    pub email: String,
+    pub password: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+    pub email_verified: bool,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub app_metadata: Map<String, Value>,
+}
+
+impl NewAuth0User {
+    pub fn new(email: &str, password: &str) -> Self {
+        Self {
+            connection: "Username-Password-Authentication".to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            name: None,
+            nickname: None,
+            email_verified: false,
+            app_metadata: Map::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct Auth0Role {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
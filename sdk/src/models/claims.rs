@@ -0,0 +1,289 @@
+use std::collections::{HashMap, HashSet};
+
+use rocket_okapi::okapi::schemars;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    errors::{UserError, UserResult},
+    utils::{API_CLIENT_ID, deserialize_string_or_vec},
+};
+
+/// Realm wide role assigned to platform administrators, satisfies every [`Permission`]
+pub const ADMIN_ROLE: &str = "admin";
+
+/// The claims of an id or access token. Every field is optional as Keycloak and Auth0 populate different subsets
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct TokenClaims {
+    pub sub: Option<String>,
+    pub email: Option<String>,
+    pub nickname: Option<String>,
+    pub preferred_username: Option<String>,
+    pub iss: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_string_or_vec")]
+    pub aud: Vec<String>,
+    pub exp: Option<u64>,
+    pub iat: Option<u64>,
+    /// Space separated OAuth scopes
+    pub scope: Option<String>,
+    /// Keycloak realm roles
+    pub realm_access: Option<RoleAccess>,
+    /// Keycloak client roles, keyed by client id
+    #[serde(default)]
+    pub resource_access: HashMap<String, RoleAccess>,
+    /// Auth0 RBAC permissions
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// Everything else, including Auth0 namespaced claims
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RoleAccess {
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl TokenClaims {
+    /// Parses the claims of a token. A claim of an unexpected type is dropped on its own instead of failing the rest
+    pub fn from_value(claims: &Value) -> Self {
+        if let Ok(parsed) = serde_json::from_value(claims.clone()) {
+            return parsed;
+        }
+
+        let valid = claims
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(name, value)| {
+                let single = Value::Object(Map::from_iter([(name.to_string(), (*value).clone())]));
+                match serde_json::from_value::<Self>(single) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        log::warn!("Ignoring token claim {}: {}", name, e);
+                        false
+                    }
+                }
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<Map<_, _>>();
+        serde_json::from_value(Value::Object(valid)).unwrap_or_default()
+    }
+
+    /// Subject without the Auth0 connection prefix
+    pub fn user_id(&self) -> Option<String> {
+        self.sub.as_ref().map(|sub| sub.replace("auth0|", ""))
+    }
+
+    /// Nickname, falling back to the Keycloak preferred username
+    pub fn name(&self) -> Option<&str> {
+        self.nickname.as_deref().or(self.preferred_username.as_deref())
+    }
+
+    /// Realm roles, client roles of the [`API_CLIENT_ID`] client and Auth0 namespaced roles
+    /// (`https://<namespace>/roles`)
+    pub fn roles(&self) -> HashSet<&str> {
+        self.roles_for(API_CLIENT_ID)
+    }
+
+    /// Realm roles, client roles of `api_client` and Auth0 namespaced roles. Roles of other clients are left out, as
+    /// they are named by whoever administers that client
+    pub fn roles_for(&self, api_client: &str) -> HashSet<&str> {
+        let realm = self.realm_access.iter().flat_map(|access| access.roles.iter());
+        let client = self
+            .resource_access
+            .get(api_client)
+            .into_iter()
+            .flat_map(|access| access.roles.iter());
+        realm
+            .chain(client)
+            .map(String::as_str)
+            .chain(self.namespaced("roles"))
+            .collect()
+    }
+
+    /// Roles assigned for a single Keycloak client
+    pub fn client_roles(&self, client_id: &str) -> HashSet<&str> {
+        self.resource_access
+            .get(client_id)
+            .map(|access| access.roles.iter().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    /// Auth0 RBAC permissions, namespaced permissions (`https://<namespace>/permissions`) and scopes
+    pub fn permissions(&self) -> HashSet<&str> {
+        self.permissions
+            .iter()
+            .map(String::as_str)
+            .chain(self.namespaced("permissions"))
+            .chain(self.scope.iter().flat_map(|scope| scope.split_whitespace()))
+            .collect()
+    }
+
+    fn namespaced<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.extra
+            .iter()
+            .filter(move |(key, _)| key.starts_with("http") && key.rsplit('/').next() == Some(name))
+            .filter_map(|(_, value)| value.as_array())
+            .flatten()
+            .filter_map(Value::as_str)
+    }
+
+    /// Checks whether the claims grant the permission, with roles of the [`API_CLIENT_ID`] client
+    pub fn has(&self, permission: &Permission) -> bool {
+        self.has_for(permission, API_CLIENT_ID)
+    }
+
+    /// Checks whether the claims grant the permission, with roles of the `api_client` client. The admin role grants
+    /// everything
+    pub fn has_for(&self, permission: &Permission, api_client: &str) -> bool {
+        let roles = self.roles_for(api_client);
+        if roles.contains(ADMIN_ROLE) {
+            return true;
+        }
+
+        let permissions = self.permissions();
+        let granted = |name: &str| roles.contains(name) || permissions.contains(name);
+        match permission {
+            Permission::Role(role) => roles.contains(role.as_str()),
+            Permission::ClientRole { client, role } => self.client_roles(client).contains(role.as_str()),
+            Permission::Scope(scope) => permissions.contains(scope.as_str()),
+            Permission::SiteAdmin(site) => granted(&Permission::site_admin_name(site)),
+            Permission::SiteAccess(site) => {
+                granted(&Permission::site_access_name(site)) || granted(&Permission::site_admin_name(site))
+            }
+        }
+    }
+
+    pub fn require(&self, permission: &Permission) -> UserResult<()> {
+        self.require_for(permission, API_CLIENT_ID)
+    }
+
+    pub fn require_for(&self, permission: &Permission, api_client: &str) -> UserResult<()> {
+        if self.has_for(permission, api_client) {
+            return Ok(());
+        }
+
+        match permission {
+            Permission::SiteAdmin(_) => Err(UserError::NotSiteAdmin),
+            Permission::SiteAccess(site) => Err(UserError::NoSiteAccess(site.clone())),
+            _ => Err(UserError::MissingPermission(permission.to_string())),
+        }
+    }
+}
+
+/// A permission a token can be checked against.
+///
+/// Site permissions are granted through a role or permission named `site-admin:<site id>` or `site:<site id>`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, schemars::JsonSchema)]
+pub enum Permission {
+    /// Realm role or Auth0 namespaced role
+    Role(String),
+    /// Keycloak client role
+    ClientRole { client: String, role: String },
+    /// OAuth scope or Auth0 RBAC permission
+    Scope(String),
+    /// Can manage the site
+    SiteAdmin(String),
+    /// Can view the site, also granted to site admins
+    SiteAccess(String),
+}
+
+impl Permission {
+    pub fn site_admin_name(site_id: &str) -> String {
+        format!("site-admin:{}", site_id)
+    }
+
+    pub fn site_access_name(site_id: &str) -> String {
+        format!("site:{}", site_id)
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Role(role) => write!(f, "role {}", role),
+            Self::ClientRole { client, role } => write!(f, "client role {}/{}", client, role),
+            Self::Scope(scope) => write!(f, "scope {}", scope),
+            Self::SiteAdmin(site) => write!(f, "{}", Self::site_admin_name(site)),
+            Self::SiteAccess(site) => write!(f, "{}", Self::site_access_name(site)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_keycloak_and_auth0_roles() {
+        let claims: TokenClaims = serde_json::from_value(json!({
+            "sub": "auth0|user",
+            "aud": "vault-client-public",
+            "realm_access": { "roles": ["site:alpha"] },
+            "resource_access": { "demia-api": { "roles": ["site-admin:beta"] } },
+            "https://demia.net/roles": ["operator"],
+            "permissions": ["read:sites"],
+        }))
+        .unwrap();
+
+        assert_eq!(claims.user_id().as_deref(), Some("user"));
+        assert_eq!(claims.aud, vec!["vault-client-public"]);
+        assert!(claims.has(&Permission::Role("operator".to_string())));
+        assert!(claims.has(&Permission::Scope("read:sites".to_string())));
+        assert!(claims.has(&Permission::SiteAccess("alpha".to_string())));
+        assert!(claims.has(&Permission::SiteAccess("beta".to_string())));
+        assert!(claims.require(&Permission::SiteAdmin("beta".to_string())).is_ok());
+        assert!(matches!(
+            claims.require(&Permission::SiteAdmin("alpha".to_string())),
+            Err(UserError::NotSiteAdmin)
+        ));
+        assert!(matches!(
+            claims.require(&Permission::SiteAccess("gamma".to_string())),
+            Err(UserError::NoSiteAccess(site)) if site == "gamma"
+        ));
+    }
+
+    #[test]
+    fn test_other_client_roles_are_ignored() {
+        let claims: TokenClaims = serde_json::from_value(json!({
+            "sub": "user",
+            "resource_access": {
+                "grafana": { "roles": ["admin", "site-admin:alpha"] },
+                "reporting": { "roles": ["site-admin:beta"] },
+            },
+        }))
+        .unwrap();
+
+        assert!(!claims.has(&Permission::Role(ADMIN_ROLE.to_string())));
+        assert!(!claims.has(&Permission::SiteAccess("alpha".to_string())));
+        assert!(!claims.has(&Permission::SiteAdmin("beta".to_string())));
+        assert!(claims.has_for(&Permission::SiteAdmin("beta".to_string()), "reporting"));
+        assert!(claims.has(&Permission::ClientRole {
+            client: "grafana".to_string(),
+            role: ADMIN_ROLE.to_string(),
+        }));
+    }
+
+    #[test]
+    fn test_mistyped_claim_keeps_the_others() {
+        let claims = TokenClaims::from_value(&json!({
+            "sub": "user",
+            "email": "user@demia.test",
+            "exp": 1_900_000_000u64,
+            "iat": "yesterday",
+            "realm_access": ["admin"],
+            "custom": 1,
+        }));
+
+        assert_eq!(claims.sub.as_deref(), Some("user"));
+        assert_eq!(claims.email.as_deref(), Some("user@demia.test"));
+        assert_eq!(claims.exp, Some(1_900_000_000));
+        assert_eq!(claims.iat, None);
+        assert!(claims.realm_access.is_none());
+        assert_eq!(claims.extra["custom"], json!(1));
+    }
+}
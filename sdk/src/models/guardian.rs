@@ -0,0 +1,347 @@
+use chrono::{DateTime, Utc};
+use rocket_okapi::okapi::schemars;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// Guardian adds fields with most releases. Everything but the ids is optional and unknown fields are kept in `extra`,
+// so an upgrade does not break deserialization
+
+/// Account roles the SDK acts as. The Standard Registry owns the policies, the others are policy roles of users
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub enum GuardianRole {
+    #[serde(rename = "STANDARD_REGISTRY")]
+    StandardRegistry,
+    #[default]
+    Installer,
+    #[serde(rename = "VVB")]
+    Vvb,
+}
+
+impl std::fmt::Display for GuardianRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StandardRegistry => write!(f, "STANDARD_REGISTRY"),
+            Self::Installer => write!(f, "Installer"),
+            Self::Vvb => write!(f, "VVB"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub enum GuardianPolicyStatus {
+    #[default]
+    #[serde(rename = "DRAFT")]
+    Draft,
+    #[serde(rename = "DRY-RUN")]
+    DryRun,
+    #[serde(rename = "PUBLISH")]
+    Published,
+    #[serde(rename = "PUBLISH_ERROR")]
+    PublishError,
+    #[serde(rename = "DISCONTINUED")]
+    Discontinued,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardianPolicy {
+    pub id: String,
+    #[serde(default)]
+    pub uuid: Option<String>,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub status: GuardianPolicyStatus,
+    #[serde(default)]
+    pub policy_tag: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub creator: Option<String>,
+    #[serde(default)]
+    pub topic_id: Option<String>,
+    #[serde(default)]
+    pub instance_topic_id: Option<String>,
+    #[serde(default)]
+    pub message_id: Option<String>,
+    #[serde(default)]
+    pub policy_roles: Vec<String>,
+    /// Role of the logged in user in this policy
+    #[serde(default)]
+    pub user_role: Option<String>,
+    #[serde(default)]
+    pub create_date: Option<DateTime<Utc>>,
+    /// Block tree of the policy, only returned for a single policy
+    #[serde(default)]
+    pub config: Option<GuardianBlock>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A block of the policy tree as returned by `/policies/{id}/blocks`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardianBlock {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub block_type: String,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub children: Vec<GuardianBlock>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl GuardianBlock {
+    /// Depth first search of the tree for the block with the tag
+    pub fn find_by_tag(&self, tag: &str) -> Option<&GuardianBlock> {
+        if self.tag.as_deref() == Some(tag) {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find_by_tag(tag))
+    }
+
+    pub fn find_by_id(&self, id: &str) -> Option<&GuardianBlock> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find_by_id(id))
+    }
+
+    /// This block and all of its descendants, depth first
+    pub fn blocks(&self) -> Vec<&GuardianBlock> {
+        let mut blocks = vec![self];
+        for child in &self.children {
+            blocks.extend(child.blocks());
+        }
+        blocks
+    }
+}
+
+/// Response of `/policies/{id}/tag/{tag}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct GuardianBlockId {
+    pub id: String,
+}
+
+/// The data a block serves to the logged in user, as returned by `/policies/{id}/blocks/{block}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardianBlockData {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub block_type: Option<String>,
+    #[serde(default)]
+    pub data: GuardianBlockContent,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl GuardianBlockData {
+    /// Documents served by the block, empty for blocks that do not serve documents
+    pub fn documents(&self) -> &[GuardianDocument] {
+        match &self.data {
+            GuardianBlockContent::Documents(documents) => documents,
+            GuardianBlockContent::Other(_) => &[],
+        }
+    }
+
+    pub fn document_of_type(&self, document_type: &str) -> Option<&GuardianDocument> {
+        self.documents()
+            .iter()
+            .find(|document| document.document_type.as_deref() == Some(document_type))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(untagged)]
+pub enum GuardianBlockContent {
+    Documents(Vec<GuardianDocument>),
+    Other(Value),
+}
+
+impl Default for GuardianBlockContent {
+    fn default() -> Self {
+        Self::Other(Value::Null)
+    }
+}
+
+/// A VC or VP document stored by a policy. Serializes back to the fields Guardian sent, so it can be posted as a
+/// reference
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardianDocument {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Document type set by the policy, e.g. `project_sr`
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub document_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relationships: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub create_date: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_date: Option<DateTime<Utc>>,
+    /// The credential itself
+    #[serde(default)]
+    pub document: Value,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Body posted to a block. `reference` links the new document to an existing one
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct GuardianBlockSubmission {
+    pub document: Value,
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub reference: Option<GuardianDocument>,
+}
+
+/// Error body returned by Guardian
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardianErrorResponse {
+    #[serde(default)]
+    pub status_code: Option<u16>,
+    #[serde(default)]
+    pub message: Value,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl GuardianErrorResponse {
+    /// The message, which Guardian sends either as a string or a list of validation errors
+    pub fn message(&self) -> String {
+        match &self.message {
+            Value::String(message) => message.clone(),
+            Value::Array(messages) => messages
+                .iter()
+                .map(|message| {
+                    message
+                        .as_str()
+                        .map(str::to_string)
+                        .unwrap_or_else(|| message.to_string())
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            Value::Null => self.error.clone().unwrap_or_default(),
+            message => message.to_string(),
+        }
+    }
+}
+
+/// Trust chain served by a policy's report block for a VP or VC hash, from the minted token back to the policy
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardianTrustChain {
+    #[serde(default)]
+    pub vp_document: Option<GuardianTrustChainItem>,
+    #[serde(default)]
+    pub vc_document: Option<GuardianTrustChainItem>,
+    #[serde(default)]
+    pub mint_document: Option<GuardianTrustChainItem>,
+    #[serde(default)]
+    pub policy_document: Option<GuardianTrustChainItem>,
+    #[serde(default)]
+    pub policy_creator_document: Option<GuardianTrustChainItem>,
+    /// Intermediate documents, i.e. the sensor reports and the project
+    #[serde(default)]
+    pub documents: Vec<GuardianTrustChainItem>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl GuardianTrustChain {
+    /// Every item, from the VP down to the policy
+    pub fn items(&self) -> Vec<&GuardianTrustChainItem> {
+        [&self.vp_document, &self.vc_document, &self.mint_document]
+            .into_iter()
+            .flatten()
+            .chain(self.documents.iter())
+            .chain(
+                [&self.policy_document, &self.policy_creator_document]
+                    .into_iter()
+                    .flatten(),
+            )
+            .collect()
+    }
+
+    /// Every VP and VC of the chain, VPs followed by the credentials they contain
+    pub fn credentials(&self) -> Vec<&Value> {
+        let mut credentials = Vec::new();
+        for item in self.items() {
+            let documents = match &item.document {
+                Value::Array(documents) => documents.iter().collect(),
+                document => vec![document],
+            };
+            for document in documents {
+                if document.get("proof").is_none() {
+                    continue;
+                }
+                credentials.push(document);
+                if let Some(Value::Array(embedded)) = document.get("verifiableCredential") {
+                    credentials.extend(embedded.iter());
+                }
+            }
+        }
+        credentials
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardianTrustChainItem {
+    #[serde(default, rename = "type")]
+    pub item_type: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// DID of the issuer
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Guardian account of the issuer
+    #[serde(default)]
+    pub username: Option<String>,
+    /// The VC or VP, or a list of them
+    #[serde(default)]
+    pub document: Value,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Outcome of verifying the proof of one credential
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct CredentialVerification {
+    pub id: Option<String>,
+    pub issuer: Option<String>,
+    pub verified: bool,
+    pub error: Option<String>,
+}
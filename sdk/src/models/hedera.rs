@@ -140,13 +140,13 @@ pub struct CredentialSubject {
 
 #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct Proof {
-    r#type: String,
+    pub r#type: String,
     #[serde(rename = "verificationMethod")]
-    verification_method: String,
-    jws: String,
-    created: String,
+    pub verification_method: String,
+    pub jws: String,
+    pub created: String,
     #[serde(rename = "proofPurpose")]
-    proof_purpose: String,
+    pub proof_purpose: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
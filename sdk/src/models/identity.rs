@@ -33,8 +33,10 @@ use crate::{
     iota_stronghold::{ClientError as StrongholdClientError, Location},
     models::{StreamsAddresses, VAULT_DOC_ID, VAULT_STREAMS_ADDRESSES},
     streams::id::did::STREAMS_VAULT,
+    telemetry::Call,
 };
 
+#[derive(Clone)]
 pub struct UserIdentity {
     doc_id: DemiaDID,
     config: StrongholdConfiguration,
@@ -57,6 +59,25 @@ impl UserIdentity {
         stronghold_config: &StrongholdConfiguration,
         client: IdentityClient,
         stronghold_adapter: StrongholdSecretManager,
+    ) -> SdkResult<UserIdentity> {
+        let create = Self::create(
+            api,
+            auth0_token,
+            identity_config,
+            stronghold_config,
+            client,
+            stronghold_adapter,
+        );
+        Call::new("identity", "new").observe(create).await
+    }
+
+    async fn create(
+        api: &ApiClient,
+        auth0_token: &TokenWrap,
+        identity_config: &IdentityConfiguration,
+        stronghold_config: &StrongholdConfiguration,
+        client: IdentityClient,
+        stronghold_adapter: StrongholdSecretManager,
     ) -> SdkResult<UserIdentity> {
         // if exists: ensure it is accessible (i.e. get pub key)
         // if not exists: create new stronghold
@@ -183,7 +204,8 @@ impl UserIdentity {
     }
 
     pub async fn doc(&self) -> Result<DemiaDocument> {
-        Ok(self.client.resolve_did(&self.doc_id).await?)
+        let resolve = async { Ok(self.client.resolve_did(&self.doc_id).await?) };
+        Call::new("identity", "resolve_did").observe(resolve).await
     }
 
     pub fn clone_stronghold(&self) -> Arc<RwLock<SecretManager>> {
@@ -203,33 +225,72 @@ impl UserIdentity {
     }
 
     pub async fn set_stronghold_bytes(&self, key: &str, record: &[u8]) -> Result<()> {
-        match &*self.write_stronghold().await {
-            SecretManager::Stronghold(adapter) => {
-                adapter.set_bytes(key, record).await?;
-                Ok(())
+        let set = async {
+            match &*self.write_stronghold().await {
+                SecretManager::Stronghold(adapter) => {
+                    adapter.set_bytes(key, record).await?;
+                    Ok(())
+                }
+                _ => unreachable!(),
             }
-            _ => unreachable!(),
-        }
+        };
+        Call::new("identity", "set_stronghold_bytes").observe(set).await
+    }
+
+    /// Re-encrypts the stronghold snapshot under a new password and writes it to disk
+    pub async fn change_stronghold_password(&self, password: String) -> Result<()> {
+        let change = async {
+            match &*self.write_stronghold().await {
+                SecretManager::Stronghold(adapter) => {
+                    adapter.change_password(password.into()).await?;
+                    Ok(())
+                }
+                _ => unreachable!(),
+            }
+        };
+        Call::new("identity", "change_stronghold_password")
+            .observe(change)
+            .await
+    }
+
+    /// Writes the stronghold snapshot to disk
+    pub async fn write_stronghold_snapshot(&self) -> Result<()> {
+        let write = async {
+            match &*self.write_stronghold().await {
+                SecretManager::Stronghold(adapter) => {
+                    adapter.write_stronghold_snapshot(None).await?;
+                    Ok(())
+                }
+                _ => unreachable!(),
+            }
+        };
+        Call::new("identity", "write_stronghold_snapshot").observe(write).await
     }
 
     pub async fn delete_stronghold_bytes(&self, key: &str) -> Result<()> {
-        match &*self.write_stronghold().await {
-            SecretManager::Stronghold(adapter) => {
-                adapter.delete(key).await?;
-                Ok(())
+        let delete = async {
+            match &*self.write_stronghold().await {
+                SecretManager::Stronghold(adapter) => {
+                    adapter.delete(key).await?;
+                    Ok(())
+                }
+                _ => unreachable!(),
             }
-            _ => unreachable!(),
-        }
+        };
+        Call::new("identity", "delete_stronghold_bytes").observe(delete).await
     }
 
     pub async fn get_stronghold_bytes<T: From<Vec<u8>>>(&self, key: &str) -> Result<Option<T>> {
-        match &*self.read_stronghold().await {
-            SecretManager::Stronghold(adapter) => match adapter.get_bytes(key).await? {
-                Some(id) => Ok(Some(id.into())),
-                None => Ok(None),
-            },
-            _ => unreachable!(),
-        }
+        let get = async {
+            match &*self.read_stronghold().await {
+                SecretManager::Stronghold(adapter) => match adapter.get_bytes(key).await? {
+                    Some(id) => Ok(Some(id.into())),
+                    None => Ok(None),
+                },
+                _ => unreachable!(),
+            }
+        };
+        Call::new("identity", "get_stronghold_bytes").observe(get).await
     }
 
     pub async fn get_stronghold_string(&self, key: &str) -> Result<Option<String>> {
@@ -296,13 +357,16 @@ impl UserIdentity {
 
         let doc_id = self.doc_id().clone();
         // Safe unwrap as this cannot be created without the check first
-        publish_identity_doc(
-            &self.client,
-            doc,
-            &mut *self.stronghold.write().await,
-            &CountryCode::for_alpha3_caseless(doc_id.country_str()).unwrap(),
-        )
-        .await
+        let publish = async {
+            publish_identity_doc(
+                &self.client,
+                doc,
+                &mut *self.stronghold.write().await,
+                &CountryCode::for_alpha3_caseless(doc_id.country_str()).unwrap(),
+            )
+            .await
+        };
+        Call::new("identity", "publish_doc").observe(publish).await
     }
 }
 
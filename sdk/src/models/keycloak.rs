@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use rocket_okapi::okapi::schemars;
+use serde::{Deserialize, Serialize};
+
+/// A Keycloak user representation, as returned and accepted by the admin API
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KeycloakUser {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    /// Left out of updates when `None`, so a partial representation doesn't disable the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub attributes: HashMap<String, Vec<String>>,
+    /// Only used on creation, never returned
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub credentials: Vec<KeycloakCredential>,
+    /// Required actions such as `UPDATE_PASSWORD` or `VERIFY_EMAIL`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required_actions: Vec<String>,
+}
+
+impl KeycloakUser {
+    /// An enabled user with the email as username
+    pub fn new(email: &str) -> Self {
+        Self {
+            username: email.to_string(),
+            email: Some(email.to_string()),
+            enabled: Some(true),
+            ..Default::default()
+        }
+    }
+
+    /// Sets an initial password, which the user has to change on first login if `temporary`
+    pub fn with_password(mut self, password: &str, temporary: bool) -> Self {
+        self.credentials = vec![KeycloakCredential::password(password, temporary)];
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct KeycloakCredential {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub value: String,
+    pub temporary: bool,
+}
+
+impl KeycloakCredential {
+    pub fn password(password: &str, temporary: bool) -> Self {
+        Self {
+            credential_type: "password".to_string(),
+            value: password.to_string(),
+            temporary,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KeycloakRole {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub composite: bool,
+    #[serde(default)]
+    pub client_role: bool,
+}
+
+impl KeycloakRole {
+    pub fn new(name: &str, description: Option<&str>) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KeycloakGroup {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub path: String,
+    #[serde(default)]
+    pub sub_groups: Vec<KeycloakGroup>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KeycloakClientScope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default = "openid_connect")]
+    pub protocol: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+}
+
+impl KeycloakClientScope {
+    pub fn new(name: &str, description: Option<&str>) -> Self {
+        Self {
+            id: None,
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            protocol: openid_connect(),
+            attributes: HashMap::from([("include.in.token.scope".to_string(), "true".to_string())]),
+        }
+    }
+}
+
+/// Whether a client scope is always applied or has to be requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, schemars::JsonSchema)]
+pub enum ClientScopeKind {
+    Default,
+    Optional,
+}
+
+impl ClientScopeKind {
+    pub(crate) fn path(&self) -> &'static str {
+        match self {
+            Self::Default => "default-client-scopes",
+            Self::Optional => "optional-client-scopes",
+        }
+    }
+}
+
+fn openid_connect() -> String {
+    "openid-connect".to_string()
+}
@@ -1,12 +1,17 @@
 mod analytics;
 mod asset;
+mod auth0;
+mod claims;
+mod guardian;
 mod hedera;
 mod identity;
 mod json_scheme_wrap;
+mod keycloak;
 mod notification;
 mod parameter;
 mod reading;
 mod record;
+mod retriever;
 mod sensor;
 mod site;
 mod token;
@@ -17,13 +22,18 @@ use std::collections::HashSet;
 
 pub use analytics::*;
 pub use asset::*;
+pub use auth0::*;
+pub use claims::*;
+pub use guardian::*;
 pub use hedera::*;
 pub use identity::*;
 pub use json_scheme_wrap::*;
+pub use keycloak::*;
 pub use notification::*;
 pub use parameter::*;
 pub use reading::*;
 pub use record::*;
+pub use retriever::*;
 use rocket_okapi::okapi::schemars;
 pub use sensor::*;
 pub use site::*;
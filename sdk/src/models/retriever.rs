@@ -0,0 +1,107 @@
+use chrono::{DateTime, Utc};
+use rocket_okapi::okapi::schemars;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::configuration::BaseConfiguration;
+
+/// A user to register with the retriever. The retriever reads the user's [`BaseConfiguration`], so it is sent in
+/// that shape, with the user id and the addresses to follow alongside. The stronghold password is left out
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetrieverRegistration {
+    pub id: String,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    #[serde(flatten)]
+    pub config: BaseConfiguration,
+}
+
+impl RetrieverRegistration {
+    pub fn from_config(id: &str, config: &BaseConfiguration) -> Self {
+        let mut config = config.clone();
+        config.stronghold.password.clear();
+        Self {
+            id: id.to_string(),
+            addresses: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn with_addresses(mut self, addresses: impl IntoIterator<Item = String>) -> Self {
+        self.addresses.extend(addresses);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetrieverUser {
+    pub id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetrieverAddresses {
+    pub id: String,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+}
+
+/// A message the retriever read from a stream
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetrievedMessage {
+    pub address: String,
+    #[serde(default)]
+    pub publisher: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Paging of [`RetrievedMessage`]s, oldest first
+#[derive(Debug, Clone, Default)]
+pub struct RetrievedMessagesQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetrieverAddressStatus {
+    pub address: String,
+    #[serde(default)]
+    pub messages: u64,
+    #[serde(default)]
+    pub last_message: Option<String>,
+    #[serde(default)]
+    pub last_synced: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetrieverSyncStatus {
+    pub id: String,
+    #[serde(default)]
+    pub syncing: bool,
+    #[serde(default)]
+    pub addresses: Vec<RetrieverAddressStatus>,
+}
+
+/// Addresses pushed to or dropped from the retriever to match the vaulted ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RetrieverAddressSync {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
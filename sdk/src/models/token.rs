@@ -4,11 +4,18 @@ use jsonwebtoken::TokenData;
 use rocket_okapi::okapi::schemars;
 use serde_json::Value;
 
+use crate::{
+    errors::UserResult,
+    models::{Permission, TokenClaims},
+};
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct TokenResponse {
     pub access_token: String,
     #[serde(default)]
     pub id_token: String,
+    // Not issued for client credentials and token exchange grants
+    #[serde(default)]
     pub refresh_token: String,
 }
 
@@ -16,48 +23,58 @@ pub struct TokenResponse {
 pub struct TokenWrap {
     token_type: TokenType,
     token: TokenData<Value>,
+    claims: TokenClaims,
     raw: String,
 }
 
 impl TokenWrap {
     pub fn new(token_type: TokenType, token: TokenData<Value>, raw: String) -> Self {
-        TokenWrap { token_type, token, raw }
+        let claims = TokenClaims::from_value(&token.claims);
+        TokenWrap {
+            token_type,
+            token,
+            claims,
+            raw,
+        }
+    }
+
+    pub fn claims(&self) -> &TokenClaims {
+        &self.claims
     }
 
     pub fn get_sub(&self) -> Option<String> {
-        let sub = self.token.claims.get("sub").expect("Should be able to pull sub");
-        Some(sub.to_string().replace('"', "").replace("auth0|", ""))
+        self.claims.user_id()
     }
 
     pub fn get_email(&self) -> Option<String> {
-        let email = self.token.claims.get("email").expect("Should be able to pull email");
-        Some(email.to_string().replace('"', ""))
+        self.claims.email.clone()
     }
 
     pub fn get_name(&self) -> Option<String> {
-        let name = self
-            .token
-            .claims
-            .get("nickname")
-            .expect("Should be able to pull nickname");
-        Some(name.to_string().replace('"', ""))
+        self.claims.name().map(str::to_string)
     }
 
     pub fn token_type(&self) -> &TokenType {
         &self.token_type
     }
 
+    /// Tokens without an expiry are treated as expired
     pub fn is_expired(&self) -> bool {
         let time_elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        self.get_expiration().unwrap() <= time_elapsed
+        self.get_expiration().map(|exp| exp <= time_elapsed).unwrap_or(true)
     }
 
     pub fn get_expiration(&self) -> Option<u64> {
-        self.token
-            .claims
-            .get("exp")
-            .expect("Should be able to pull sub")
-            .as_u64()
+        self.claims.exp
+    }
+
+    pub fn has(&self, permission: &Permission) -> bool {
+        self.claims.has(permission)
+    }
+
+    /// Errors if the token does not grant the permission, e.g. `token.require(&Permission::SiteAdmin(site_id))?`
+    pub fn require(&self, permission: &Permission) -> UserResult<()> {
+        self.claims.require(permission)
     }
 
     pub fn token_data(&self) -> &TokenData<Value> {
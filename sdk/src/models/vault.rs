@@ -3,27 +3,62 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use serde_json::Value;
 use vaultrs::{
-    api::AuthInfo,
-    auth::oidc,
-    client::{Client as _, VaultClient as Client},
+    api::{AuthInfo, kv2::requests::SetSecretRequestOptions},
+    auth::{approle, kubernetes, oidc},
+    client::{Client as _, VaultClient as Client, VaultClientSettingsBuilder},
+    error::ClientError,
 };
 
 use crate::{
-    configuration::StrongholdConfiguration,
-    errors::IdentityResult as Result,
-    models::{TokenType, TokenWrap},
-    utils::new_stronghold_key,
+    clients::{Storage, StorageClient, StorageDataType},
+    configuration::{BaseConfiguration, StrongholdConfiguration, VaultConfiguration},
+    errors::{IdentityError, IdentityResult as Result, SdkResult},
+    models::{TokenType, TokenWrap, UserIdentity},
+    utils::{KUBERNETES_SERVICE_ACCOUNT_TOKEN, new_stronghold_key},
 };
 
 pub const VAULT_DOC_ID: &str = "streams_doc_id";
 pub const VAULT_STREAMS_ADDRESSES: &str = "streams_addresses";
 
+/// How the client authenticates against Vault
+#[derive(Clone)]
+pub enum VaultAuth {
+    /// A user JWT, logged in through the jwt auth mounts
+    Jwt(TokenWrap),
+    /// Backend services holding an AppRole
+    AppRole { role_id: String, secret_id: String },
+    /// Pods authenticating with their service account token
+    Kubernetes { role: String, jwt: String },
+}
+
+impl VaultAuth {
+    /// Kubernetes auth using the service account token mounted into the pod
+    pub fn kubernetes(role: &str) -> Result<Self> {
+        let jwt = std::fs::read_to_string(KUBERNETES_SERVICE_ACCOUNT_TOKEN)
+            .map_err(|e| IdentityError::VaultError(format!("Could not read service account token: {}", e)))?;
+        Ok(Self::Kubernetes {
+            role: role.to_string(),
+            jwt: jwt.trim().to_string(),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Jwt(_) => "jwt",
+            Self::AppRole { .. } => "approle",
+            Self::Kubernetes { .. } => "kubernetes",
+        }
+    }
+}
+
 pub struct VaultClient {
     config: StrongholdConfiguration,
-    token: TokenWrap,
+    vault_config: VaultConfiguration,
+    auth: VaultAuth,
+    sub: Option<String>,
     vault_client: Client,
     exp: u64,
     password: Option<String>,
@@ -33,30 +68,48 @@ impl Debug for VaultClient {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("VaultClient")
             .field("config", &self.config)
+            .field("vault_config", &self.vault_config)
+            .field("auth", &self.auth.name())
+            .field("sub", &self.sub)
             .field("exp", &self.exp)
-            .field("password", &self.password)
             .finish()
     }
 }
 
 impl VaultClient {
-    pub async fn new(config: StrongholdConfiguration, token: TokenWrap) -> Result<VaultClient> {
-        let vault_config = vaultrs::client::VaultClientSettingsBuilder::default()
-            .address("http://35.230.109.16:8200")
-            .build()
-            .expect("Should be able to build the vault configuration");
+    /// Connects to the configured Vault with a user token
+    pub async fn new(config: &BaseConfiguration, token: TokenWrap) -> Result<VaultClient> {
+        Self::with_configuration(config.stronghold.clone(), config.vault.clone(), VaultAuth::Jwt(token)).await
+    }
 
-        let mut vault_client = Client::new(vault_config).expect("Should be able to use vault client");
+    /// Connects using the given Vault configuration and auth method. Clients created from a JWT resolve the
+    /// stronghold secret of the token subject, others need [`VaultClient::set_user`].
+    pub async fn with_configuration(
+        config: StrongholdConfiguration,
+        vault_config: VaultConfiguration,
+        auth: VaultAuth,
+    ) -> Result<VaultClient> {
+        let settings = VaultClientSettingsBuilder::default()
+            .address(&vault_config.address)
+            .build()
+            .map_err(|e| IdentityError::VaultError(e.to_string()))?;
 
-        let auth_info = Self::set_client_token(&mut vault_client, token.clone()).await?;
+        let mut vault_client = Client::new(settings)?;
+        let auth_info = Self::login(&vault_client, &vault_config, &auth).await?;
+        vault_client.set_token(&auth_info.client_token);
 
-        let exp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + auth_info.lease_duration;
+        let sub = match &auth {
+            VaultAuth::Jwt(token) => token.get_sub(),
+            _ => None,
+        };
 
         Ok(VaultClient {
             config,
-            token,
+            vault_config,
+            auth,
+            sub,
             vault_client,
-            exp,
+            exp: now() + auth_info.lease_duration,
             password: None,
         })
     }
@@ -65,26 +118,35 @@ impl VaultClient {
         &self.config
     }
 
-    async fn check_token(&mut self) -> Result<()> {
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let (mount, role) = match self.token.token_type() {
-            TokenType::VAULT => ("jwt", Some("default".to_string())),
-            _ => ("jwt2", None),
-        };
-        if self.exp <= now {
-            let auth_info = oidc::login(&self.vault_client, mount, self.token.raw(), role)
-                .await
-                .expect("Should be able to login");
+    pub fn vault_config(&self) -> &VaultConfiguration {
+        &self.vault_config
+    }
 
+    /// Sets the user whose stronghold secret is read and written
+    pub fn set_user(&mut self, sub: String) {
+        if self.sub.as_ref() != Some(&sub) {
+            self.password = None;
+        }
+        self.sub = Some(sub);
+    }
+
+    async fn check_token(&mut self) -> Result<()> {
+        if self.exp <= now() {
+            let auth_info = Self::login(&self.vault_client, &self.vault_config, &self.auth).await?;
             self.vault_client.set_token(&auth_info.client_token);
-            self.exp = self.token.get_expiration().unwrap_or(now);
+            self.exp = now() + auth_info.lease_duration;
         }
         Ok(())
     }
 
+    fn password_key(&self) -> Result<String> {
+        let sub = self.sub.as_ref().ok_or(IdentityError::NoVaultUser)?;
+        Ok(format!("users/{}/stronghold", sub))
+    }
+
     pub async fn store_password(&mut self, password: String) -> Result<()> {
         self.check_token().await?;
-        let key = format!("users/{}/stronghold", self.token.get_sub().unwrap());
+        let key = self.password_key()?;
 
         let data = serde_json::json!({
             "data": {
@@ -93,27 +155,30 @@ impl VaultClient {
         });
 
         debug!("Storing password in {}", key);
-        vaultrs::kv2::set(&self.vault_client, "stronghold", &key, &data)
-            .await
-            .expect("Should be able to store the secret");
+        vaultrs::kv2::set(&self.vault_client, &self.vault_config.kv_mount, &key, &data).await?;
         Ok(())
     }
 
     pub async fn retrieve_password(&mut self) -> Result<String> {
         self.check_token().await?;
-        let key = format!("users/{}/stronghold", self.token.get_sub().unwrap());
+        let key = self.password_key()?;
         match &self.password {
             None => {
                 debug!("No password in vault client");
-                if let Ok(secret) = vaultrs::kv2::read::<Value>(&self.vault_client, "stronghold", &key).await {
-                    if let Some(data) = secret.get("data") {
-                        if let Some(p) = data.get("password") {
-                            if let Some(password) = p.as_str() {
-                                self.password = Some(password.to_string());
-                                return Ok(password.to_string());
-                            }
+                match vaultrs::kv2::read::<Value>(&self.vault_client, &self.vault_config.kv_mount, &key).await {
+                    Ok(secret) => {
+                        if let Some(password) = secret
+                            .get("data")
+                            .and_then(|data| data.get("password"))
+                            .and_then(Value::as_str)
+                        {
+                            self.password = Some(password.to_string());
+                            return Ok(password.to_string());
                         }
                     }
+                    // Only a missing secret warrants a new key, anything else would orphan the existing snapshot
+                    Err(ClientError::APIError { code: 404, .. }) => {}
+                    Err(e) => return Err(e.into()),
                 }
 
                 info!("No stronghold key found, generating new one");
@@ -126,22 +191,401 @@ impl VaultClient {
         }
     }
 
+    /// Current kv2 version of the stronghold secret, 0 if it was never written
+    pub async fn password_version(&mut self) -> Result<u64> {
+        self.check_token().await?;
+        let key = self.password_key()?;
+        match vaultrs::kv2::read_metadata(&self.vault_client, &self.vault_config.kv_mount, &key).await {
+            Ok(metadata) => Ok(metadata.current_version),
+            Err(ClientError::APIError { code: 404, .. }) => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads a previous version of the stronghold secret, e.g. to open a snapshot that predates a rotation
+    pub async fn retrieve_password_version(&mut self, version: u64) -> Result<String> {
+        self.check_token().await?;
+        let key = self.password_key()?;
+        let secret =
+            vaultrs::kv2::read_version::<Value>(&self.vault_client, &self.vault_config.kv_mount, &key, version).await?;
+        secret
+            .get("data")
+            .and_then(|data| data.get("password"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or(IdentityError::NoStrongholdSecret)
+    }
+
+    /// Writes the password only if the secret is still at `version` (kv2 check-and-set). Returns the new version
+    async fn store_password_cas(&mut self, password: &str, version: u64) -> Result<u64> {
+        self.check_token().await?;
+        let key = self.password_key()?;
+        let cas = u32::try_from(version)
+            .map_err(|_| IdentityError::VaultError(format!("Version {} exceeds the check-and-set range", version)))?;
+        let data = serde_json::json!({
+            "data": {
+                "password": password
+            }
+        });
+
+        let metadata = vaultrs::kv2::set_with_options(
+            &self.vault_client,
+            &self.vault_config.kv_mount,
+            &key,
+            &data,
+            SetSecretRequestOptions { cas },
+        )
+        .await?;
+        Ok(metadata.version)
+    }
+
+    /// The password of the version before the current one, to open a snapshot whose rotation was interrupted after
+    /// the new key was stored. None if the secret has no earlier version
+    pub async fn previous_password(&mut self) -> Result<Option<String>> {
+        let version = self.password_version().await?;
+        if version < 2 {
+            return Ok(None);
+        }
+        self.retrieve_password_version(version - 1).await.map(Some)
+    }
+
+    /// Rotates the stronghold password.
+    ///
+    /// The new key is stored first, as a kv2 version on top of the one just read, then the snapshot is re-encrypted
+    /// and uploaded. The previous version stays readable, so a snapshot left under the old key by an interrupted
+    /// rotation is still opened through [`VaultClient::previous_password`]. If the snapshot can't be re-encrypted, the
+    /// old key is stored again.
+    pub async fn rotate_password<T: Storage + Debug>(
+        &mut self,
+        identity: &UserIdentity,
+        storage: &StorageClient<T>,
+    ) -> SdkResult<()> {
+        let old_password = self.retrieve_password().await?;
+        let version = self.password_version().await?;
+        let new_password = new_stronghold_key();
+
+        info!("Rotating stronghold password from version {}", version);
+        let new_version = self.store_password_cas(&new_password, version).await?;
+        self.password = Some(new_password.clone());
+
+        if let Err(e) = identity.change_stronghold_password(new_password).await {
+            warn!("Could not re-encrypt snapshot, restoring password: {}", e);
+            match self.store_password_cas(&old_password, new_version).await {
+                Ok(_) => self.password = Some(old_password),
+                Err(revert) => error!(
+                    "Failed to restore password in vault, version {} still opens the snapshot: {}",
+                    version, revert
+                ),
+            }
+            return Err(e.into());
+        }
+
+        // The local snapshot and Vault agree on the new key, the remote snapshot opens with the previous version
+        // until the next upload
+        let snapshot_path = self.config.path.clone();
+        storage
+            .upload(StorageDataType::StrongholdSnapshot(&snapshot_path), None)
+            .await?;
+
+        info!("Stronghold password rotated to version {}", new_version);
+        Ok(())
+    }
+
+    /// The underlying vault client, logged in again if the lease expired
+    pub(crate) async fn authenticated(&mut self) -> Result<&Client> {
+        self.check_token().await?;
+        Ok(&self.vault_client)
+    }
+
+    /// The underlying vault client while its lease is valid, for callers sharing the client behind a lock
+    pub(crate) fn client(&self) -> Option<&Client> {
+        (self.exp > now()).then_some(&self.vault_client)
+    }
+
     pub async fn update_client_token(&mut self, token: TokenWrap) -> Result<()> {
-        Self::set_client_token(&mut self.vault_client, token.clone()).await?;
-        self.token = token;
-        self.exp = self.token.get_expiration().unwrap();
+        let auth = VaultAuth::Jwt(token.clone());
+        let auth_info = Self::login(&self.vault_client, &self.vault_config, &auth).await?;
+        self.vault_client.set_token(&auth_info.client_token);
+        if let Some(sub) = token.get_sub() {
+            self.set_user(sub);
+        }
+        self.auth = auth;
+        self.exp = now() + auth_info.lease_duration;
         Ok(())
     }
 
-    async fn set_client_token(vault_client: &mut vaultrs::client::VaultClient, token: TokenWrap) -> Result<AuthInfo> {
-        let (mount, role) = match token.token_type() {
-            TokenType::VAULT => ("jwt", Some("default".to_string())),
-            _ => ("jwt2", None),
+    async fn login(vault_client: &Client, vault_config: &VaultConfiguration, auth: &VaultAuth) -> Result<AuthInfo> {
+        let auth_info = match auth {
+            VaultAuth::Jwt(token) => {
+                let (mount, role) = match token.token_type() {
+                    TokenType::VAULT => (&vault_config.jwt_mount, vault_config.jwt_role.clone()),
+                    _ => (&vault_config.external_jwt_mount, None),
+                };
+                oidc::login(vault_client, mount, token.raw(), role).await?
+            }
+            VaultAuth::AppRole { role_id, secret_id } => {
+                approle::login(vault_client, &vault_config.approle_mount, role_id, secret_id).await?
+            }
+            VaultAuth::Kubernetes { role, jwt } => {
+                kubernetes::login(vault_client, &vault_config.kubernetes_mount, role, jwt).await?
+            }
         };
-        let auth_info = oidc::login(vault_client, mount, token.raw(), role)
-            .await
-            .expect("Should be able to login");
-        vault_client.set_token(&auth_info.client_token);
+        debug!("Logged into vault using {} auth", auth.name());
         Ok(auth_info)
     }
 }
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Integration tests against a local dev server, run with
+/// `vault server -dev -dev-root-token-id=root` and `cargo test --features test-support -- --ignored`.
+/// `VAULT_ADDR` and `VAULT_TOKEN` override the address and root token.
+#[cfg(test)]
+mod tests {
+    use reqwest::Method;
+    use serde_json::json;
+
+    use super::*;
+
+    const POLICY: &str = "demia-test";
+
+    fn dev_config() -> VaultConfiguration {
+        VaultConfiguration {
+            address: std::env::var("VAULT_ADDR").unwrap_or("http://127.0.0.1:8200".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Calls the Vault API with the root token
+    async fn root(method: Method, path: &str, body: Value) -> Value {
+        let token = std::env::var("VAULT_TOKEN").unwrap_or("root".to_string());
+        let response = reqwest::Client::new()
+            .request(method, format!("{}/v1/{}", dev_config().address, path))
+            .header("X-Vault-Token", token)
+            .json(&body)
+            .send()
+            .await
+            .expect("vault dev server should be running");
+        response.json().await.unwrap_or_default()
+    }
+
+    /// Mounts the kv2 engine and the auth method and creates the policy the test roles log in with. Mounting twice is
+    /// rejected, which is fine for reruns
+    async fn setup(auth_mount: (&str, &str)) {
+        root(Method::POST, "sys/mounts/stronghold", json!({ "type": "kv-v2" })).await;
+        root(
+            Method::PUT,
+            &format!("sys/policies/acl/{}", POLICY),
+            json!({ "policy": r#"path "stronghold/*" { capabilities = ["create", "read", "update", "delete", "list"] }"# }),
+        )
+        .await;
+        let (path, auth_type) = auth_mount;
+        root(
+            Method::POST,
+            &format!("sys/auth/{}", path),
+            json!({ "type": auth_type }),
+        )
+        .await;
+    }
+
+    async fn approle(role: &str, policies: &[&str]) -> VaultAuth {
+        setup(("approle", "approle")).await;
+        root(
+            Method::POST,
+            &format!("auth/approle/role/{}", role),
+            json!({ "token_policies": policies }),
+        )
+        .await;
+        let role_id =
+            root(Method::GET, &format!("auth/approle/role/{}/role-id", role), Value::Null).await["data"]["role_id"]
+                .as_str()
+                .unwrap()
+                .to_string();
+        let secret_id = root(
+            Method::POST,
+            &format!("auth/approle/role/{}/secret-id", role),
+            json!({}),
+        )
+        .await["data"]["secret_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        VaultAuth::AppRole { role_id, secret_id }
+    }
+
+    async fn connect(auth: VaultAuth, user: &str) -> VaultClient {
+        let mut client = VaultClient::with_configuration(StrongholdConfiguration::default(), dev_config(), auth)
+            .await
+            .unwrap();
+        client.set_user(user.to_string());
+        client
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a vault dev server"]
+    async fn test_approle_login_generates_key_once() {
+        let auth = approle("demia-test", &[POLICY]).await;
+        let user = uuid::Uuid::new_v4().to_string();
+
+        let mut client = connect(auth.clone(), &user).await;
+        assert_eq!(client.password_version().await.unwrap(), 0);
+        let password = client.retrieve_password().await.unwrap();
+        assert_eq!(client.password_version().await.unwrap(), 1);
+
+        // A second login reads the stored key instead of generating another one
+        let mut other = connect(auth, &user).await;
+        assert_eq!(other.retrieve_password().await.unwrap(), password);
+        assert_eq!(other.password_version().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a vault dev server"]
+    async fn test_denied_read_does_not_generate_key() {
+        let user = uuid::Uuid::new_v4().to_string();
+        let mut allowed = connect(approle("demia-test", &[POLICY]).await, &user).await;
+        let password = allowed.retrieve_password().await.unwrap();
+
+        // A 403 must not be mistaken for a missing secret and overwrite the key
+        let mut denied = connect(approle("demia-test-denied", &["default"]).await, &user).await;
+        assert!(denied.retrieve_password().await.is_err());
+
+        let mut allowed = connect(approle("demia-test", &[POLICY]).await, &user).await;
+        assert_eq!(allowed.password_version().await.unwrap(), 1);
+        assert_eq!(allowed.retrieve_password().await.unwrap(), password);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a vault dev server"]
+    async fn test_previous_password() {
+        let user = uuid::Uuid::new_v4().to_string();
+        let mut client = connect(approle("demia-test", &[POLICY]).await, &user).await;
+        let old_password = client.retrieve_password().await.unwrap();
+        assert!(client.previous_password().await.unwrap().is_none());
+
+        // A rotation stopped after the new key was stored
+        client.store_password_cas("rotated", 1).await.unwrap();
+        assert!(client.store_password_cas("concurrent", 1).await.is_err());
+        assert_eq!(client.previous_password().await.unwrap(), Some(old_password));
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    #[ignore = "needs a vault dev server"]
+    async fn test_jwt_login() {
+        use crate::{
+            clients::SecretManager,
+            test_support::{MockOidcConfig, MockOidcIssuer, MockUser},
+            utils::VAULT_JWT_ROLE,
+        };
+
+        let user = MockUser::new("vault-user", "password");
+        let issuer = MockOidcIssuer::start(MockOidcConfig {
+            users: vec![user.clone()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        setup(("jwt", "jwt")).await;
+        root(
+            Method::POST,
+            "auth/jwt/config",
+            json!({ "jwks_url": issuer.jwks_url() }),
+        )
+        .await;
+        root(
+            Method::POST,
+            &format!("auth/jwt/role/{}", VAULT_JWT_ROLE),
+            json!({
+                "role_type": "jwt",
+                "user_claim": "sub",
+                "bound_audiences": [TokenType::VAULT.client_id()],
+                "token_policies": [POLICY],
+            }),
+        )
+        .await;
+
+        let token = issuer
+            .keycloak()
+            .get_token(&TokenType::VAULT, &user.username, &user.password)
+            .await
+            .unwrap();
+        let mut client =
+            VaultClient::with_configuration(StrongholdConfiguration::default(), dev_config(), VaultAuth::Jwt(token))
+                .await
+                .unwrap();
+
+        // The user comes from the token subject
+        let password = client.retrieve_password().await.unwrap();
+        assert_eq!(client.password_key().unwrap(), format!("users/{}/stronghold", user.sub));
+        assert_eq!(client.retrieve_password_version(1).await.unwrap(), password);
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    #[ignore = "needs a vault dev server"]
+    async fn test_kubernetes_login() {
+        use axum::{Json, Router, routing::post};
+
+        use crate::test_support::{MockClaims, MockOidcConfig, MockOidcIssuer};
+
+        // Vault asks the cluster to review the service account token, this API server accepts any
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        let router = Router::new().route(
+            "/apis/authentication.k8s.io/v1/tokenreviews",
+            post(|| async {
+                Json(json!({
+                    "apiVersion": "authentication.k8s.io/v1",
+                    "kind": "TokenReview",
+                    "status": {
+                        "authenticated": true,
+                        "user": { "username": "system:serviceaccount:demia:sdk", "uid": "sdk-uid" }
+                    }
+                }))
+            }),
+        );
+        let server = tokio::spawn(async move { axum::serve(listener, router).await });
+
+        setup(("kubernetes", "kubernetes")).await;
+        root(
+            Method::POST,
+            "auth/kubernetes/config",
+            json!({ "kubernetes_host": host, "disable_local_ca_jwt": true }),
+        )
+        .await;
+        root(
+            Method::POST,
+            "auth/kubernetes/role/demia-test",
+            json!({
+                "bound_service_account_names": ["sdk"],
+                "bound_service_account_namespaces": ["demia"],
+                "token_policies": [POLICY],
+            }),
+        )
+        .await;
+
+        let issuer = MockOidcIssuer::start(MockOidcConfig::default()).await.unwrap();
+        let mut extra = serde_json::Map::new();
+        extra.insert(
+            "kubernetes.io".to_string(),
+            json!({ "namespace": "demia", "serviceaccount": { "name": "sdk", "uid": "sdk-uid" } }),
+        );
+        let jwt = issuer.mint_token(&MockClaims {
+            sub: "system:serviceaccount:demia:sdk".to_string(),
+            extra,
+            ..Default::default()
+        });
+        let auth = VaultAuth::Kubernetes {
+            role: "demia-test".to_string(),
+            jwt,
+        };
+
+        let mut client = connect(auth, &uuid::Uuid::new_v4().to_string()).await;
+        client.retrieve_password().await.unwrap();
+        assert_eq!(client.password_version().await.unwrap(), 1);
+        server.abort();
+    }
+}
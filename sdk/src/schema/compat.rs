@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::SchemaBundle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    /// Existing data or code generated from the old schemas may no longer work
+    Breaking,
+    Compatible,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaChange {
+    pub kind: ChangeKind,
+    /// Definition and location of the change, e.g. `Site/properties/location`
+    pub path: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompatibilityReport {
+    pub from: String,
+    pub to: String,
+    pub changes: Vec<SchemaChange>,
+}
+
+impl CompatibilityReport {
+    pub fn is_compatible(&self) -> bool {
+        self.breaking().next().is_none()
+    }
+
+    pub fn breaking(&self) -> impl Iterator<Item = &SchemaChange> {
+        self.changes.iter().filter(|change| change.kind == ChangeKind::Breaking)
+    }
+
+    fn push(&mut self, kind: ChangeKind, path: &str, description: String) {
+        self.changes.push(SchemaChange {
+            kind,
+            path: path.to_string(),
+            description,
+        });
+    }
+}
+
+/// Lists the changes from `old` to `new`.
+///
+/// Removed definitions, properties and enum values, changed types or references and newly required properties are
+/// breaking. Additions and properties that are no longer required are compatible.
+pub fn check_compatibility(old: &SchemaBundle, new: &SchemaBundle) -> CompatibilityReport {
+    let mut report = CompatibilityReport {
+        from: old.version.clone(),
+        to: new.version.clone(),
+        changes: Vec::new(),
+    };
+
+    for (name, old_schema) in &old.definitions {
+        match new.definitions.get(name) {
+            Some(new_schema) => compare(name, old_schema, new_schema, &mut report),
+            None => report.push(ChangeKind::Breaking, name, "Definition removed".to_string()),
+        }
+    }
+    for name in new
+        .definitions
+        .keys()
+        .filter(|name| !old.definitions.contains_key(*name))
+    {
+        report.push(ChangeKind::Compatible, name, "Definition added".to_string());
+    }
+    report
+}
+
+fn compare(path: &str, old: &Value, new: &Value, report: &mut CompatibilityReport) {
+    let (old_ref, new_ref) = (old.get("$ref"), new.get("$ref"));
+    if old_ref != new_ref {
+        report.push(
+            ChangeKind::Breaking,
+            path,
+            format!("Reference changed from {} to {}", display(old_ref), display(new_ref)),
+        );
+        return;
+    }
+
+    let (old_types, new_types) = (types(old), types(new));
+    if old_types != new_types {
+        report.push(
+            ChangeKind::Breaking,
+            path,
+            format!("Type changed from {:?} to {:?}", old_types, new_types),
+        );
+    }
+
+    compare_enum(path, old, new, report);
+    compare_properties(path, old, new, report);
+
+    for key in ["items", "additionalProperties"] {
+        if let (Some(old), Some(new)) = (old.get(key), new.get(key)) {
+            if old.is_object() && new.is_object() {
+                compare(&format!("{}/{}", path, key), old, new, report);
+            }
+        }
+    }
+    for key in ["oneOf", "anyOf", "allOf"] {
+        compare_variants(&format!("{}/{}", path, key), old.get(key), new.get(key), report);
+    }
+}
+
+fn compare_enum(path: &str, old: &Value, new: &Value, report: &mut CompatibilityReport) {
+    let values = |schema: &Value| {
+        schema
+            .get("enum")
+            .and_then(Value::as_array)
+            .map(|values| values.iter().map(Value::to_string).collect::<BTreeSet<_>>())
+    };
+    let (Some(old_values), Some(new_values)) = (values(old), values(new)) else {
+        return;
+    };
+    for value in old_values.difference(&new_values) {
+        report.push(ChangeKind::Breaking, path, format!("Enum value {} removed", value));
+    }
+    for value in new_values.difference(&old_values) {
+        report.push(ChangeKind::Compatible, path, format!("Enum value {} added", value));
+    }
+}
+
+fn compare_properties(path: &str, old: &Value, new: &Value, report: &mut CompatibilityReport) {
+    let properties = |schema: &Value| schema.get("properties").and_then(Value::as_object).cloned();
+    let (old_properties, new_properties) = match (properties(old), properties(new)) {
+        (None, None) => return,
+        (old, new) => (old.unwrap_or_default(), new.unwrap_or_default()),
+    };
+    let (old_required, new_required) = (required(old), required(new));
+
+    for (name, old_property) in &old_properties {
+        let property_path = format!("{}/properties/{}", path, name);
+        match new_properties.get(name) {
+            Some(new_property) => compare(&property_path, old_property, new_property, report),
+            None => report.push(ChangeKind::Breaking, &property_path, "Property removed".to_string()),
+        }
+    }
+    for name in new_properties.keys().filter(|name| !old_properties.contains_key(*name)) {
+        let kind = match new_required.contains(name) {
+            true => ChangeKind::Breaking,
+            false => ChangeKind::Compatible,
+        };
+        report.push(
+            kind,
+            &format!("{}/properties/{}", path, name),
+            "Property added".to_string(),
+        );
+    }
+
+    for name in new_required.difference(&old_required) {
+        if old_properties.contains_key(name) {
+            report.push(
+                ChangeKind::Breaking,
+                &format!("{}/properties/{}", path, name),
+                "Property became required".to_string(),
+            );
+        }
+    }
+    for name in old_required.difference(&new_required) {
+        report.push(
+            ChangeKind::Compatible,
+            &format!("{}/properties/{}", path, name),
+            "Property is no longer required".to_string(),
+        );
+    }
+}
+
+/// Variants of data carrying enums are matched by their tag, so reordering them is not a change
+fn compare_variants(path: &str, old: Option<&Value>, new: Option<&Value>, report: &mut CompatibilityReport) {
+    let variants = |schemas: Option<&Value>| {
+        schemas
+            .and_then(Value::as_array)
+            .map(|schemas| {
+                schemas
+                    .iter()
+                    .map(|schema| (variant_key(schema), schema.clone()))
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .unwrap_or_default()
+    };
+    let (old_variants, new_variants) = (variants(old), variants(new));
+
+    for (key, old_variant) in &old_variants {
+        let variant_path = format!("{}/{}", path, key);
+        match new_variants.get(key) {
+            Some(new_variant) => compare(&variant_path, old_variant, new_variant, report),
+            None => report.push(ChangeKind::Breaking, &variant_path, "Variant removed".to_string()),
+        }
+    }
+    for key in new_variants.keys().filter(|key| !old_variants.contains_key(*key)) {
+        report.push(
+            ChangeKind::Compatible,
+            &format!("{}/{}", path, key),
+            "Variant added".to_string(),
+        );
+    }
+}
+
+/// The unit variant name, the single required property of externally tagged variants, or the schema itself
+fn variant_key(schema: &Value) -> String {
+    match schema.get("enum").and_then(Value::as_array).map(Vec::as_slice) {
+        Some([Value::String(name)]) => return name.clone(),
+        // Unit variants are grouped into one enum, compared value by value
+        Some(_) => return "enum".to_string(),
+        None => {}
+    }
+    if let Some([Value::String(name)]) = schema.get("required").and_then(Value::as_array).map(Vec::as_slice) {
+        return name.clone();
+    }
+    schema.to_string()
+}
+
+fn types(schema: &Value) -> BTreeSet<String> {
+    match schema.get("type") {
+        Some(Value::String(name)) => BTreeSet::from([name.clone()]),
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+        _ => BTreeSet::new(),
+    }
+}
+
+fn required(schema: &Value) -> BTreeSet<String> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|names| names.iter().filter_map(Value::as_str).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn display(value: Option<&Value>) -> String {
+    value.map(Value::to_string).unwrap_or_else(|| "none".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::schema::SCHEMA_DRAFT;
+
+    fn bundle(version: &str, definitions: Value) -> SchemaBundle {
+        SchemaBundle {
+            schema: SCHEMA_DRAFT.to_string(),
+            version: version.to_string(),
+            definitions: serde_json::from_value(definitions).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_breaking_changes() {
+        let old = bundle(
+            "0.1.0",
+            json!({
+                "Site": {
+                    "type": "object",
+                    "required": ["id"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "avg": { "type": "number" },
+                        "name": { "type": "string" }
+                    }
+                },
+                "Kind": { "type": "string", "enum": ["A", "B"] },
+                "Old": { "type": "object" }
+            }),
+        );
+        let new = bundle(
+            "0.2.0",
+            json!({
+                "Site": {
+                    "type": "object",
+                    "required": ["id", "name"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "avg": { "type": ["number", "null"] },
+                        "name": { "type": "string" },
+                        "note": { "type": "string" }
+                    }
+                },
+                "Kind": { "type": "string", "enum": ["A", "C"] }
+            }),
+        );
+
+        let report = check_compatibility(&old, &new);
+        assert!(!report.is_compatible());
+        let breaking = report.breaking().map(|change| change.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            breaking,
+            vec!["Kind", "Old", "Site/properties/avg", "Site/properties/name"]
+        );
+        assert!(report.changes.contains(&SchemaChange {
+            kind: ChangeKind::Compatible,
+            path: "Site/properties/note".to_string(),
+            description: "Property added".to_string(),
+        }));
+
+        assert!(check_compatibility(&new, &new).changes.is_empty());
+    }
+}
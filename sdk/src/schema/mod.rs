@@ -0,0 +1,185 @@
+//! JSON Schemas of the SDK models, for generating types in other languages.
+//!
+//! [`bundle`] gives a draft-07 bundle of every model under `definitions`, [`openapi`] the same models as the components
+//! of an OpenAPI document. [`check_compatibility`] compares two bundles, e.g. the published one against the current.
+//! [`Validator`] checks inbound JSON against the schema of a model before it is deserialized.
+
+mod compat;
+mod validation;
+
+use std::collections::BTreeMap;
+
+pub use compat::{ChangeKind, CompatibilityReport, SchemaChange, check_compatibility};
+use rocket_okapi::okapi::{
+    openapi3::{Components, Info, OpenApi},
+    schemars::{
+        JsonSchema,
+        gen::{SchemaGenerator, SchemaSettings},
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+pub use validation::{ValidationMode, Validator, from_slice, from_value, validate};
+
+use crate::{
+    clients::{FileInfo, FileMetadata},
+    errors::{AnalyticsError, ApiError, Error, IdentityError, NodeError, SecretError, StorageError, UserError},
+    models::*,
+};
+
+pub const SCHEMA_DRAFT: &str = "http://json-schema.org/draft-07/schema#";
+
+/// Schemas of all models, keyed by type name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchemaBundle {
+    #[serde(rename = "$schema")]
+    pub schema: String,
+    /// Version of the SDK the schemas were generated from
+    pub version: String,
+    pub definitions: BTreeMap<String, Value>,
+}
+
+impl SchemaBundle {
+    pub fn file_name(&self) -> String {
+        format!("demia-schemas-{}.json", self.version)
+    }
+}
+
+fn add<T: JsonSchema>(generator: &mut SchemaGenerator) {
+    generator.subschema_for::<T>();
+}
+
+/// Adds every exported model, and with them the types they reference
+fn add_models(generator: &mut SchemaGenerator) {
+    // Sites and readings
+    add::<Site>(generator);
+    add::<NewSite>(generator);
+    add::<SiteState>(generator);
+    add::<Sensors>(generator);
+    add::<Sensor>(generator);
+    add::<Equipment>(generator);
+    add::<Reading>(generator);
+    add::<ReadingWrap>(generator);
+    add::<SheetData>(generator);
+    add::<NestedReading>(generator);
+    add::<ValueSet>(generator);
+    add::<ValueSetsWrap>(generator);
+    add::<Record>(generator);
+    add::<Notification>(generator);
+    add::<AssetFileWrapper>(generator);
+    add::<Card>(generator);
+    add::<FileInfo>(generator);
+    add::<FileMetadata>(generator);
+
+    // Analytics
+    add::<AnalyticsProfile>(generator);
+    add::<Calculation>(generator);
+    add::<Parameter>(generator);
+
+    // Accounts and tokens
+    add::<TokenClaims>(generator);
+    add::<TokenResponse>(generator);
+    add::<TokenType>(generator);
+    add::<Auth0User>(generator);
+    add::<NewAuth0User>(generator);
+    add::<KeycloakUser>(generator);
+    add::<KeycloakRole>(generator);
+    add::<KeycloakGroup>(generator);
+
+    // Guardian
+    add::<GuardianReport>(generator);
+    add::<ReportParams>(generator);
+    add::<DataResponse>(generator);
+    add::<VerifiableCredential>(generator);
+    add::<GuardianPolicy>(generator);
+    add::<GuardianBlockData>(generator);
+    add::<GuardianBlockSubmission>(generator);
+    add::<GuardianTrustChain>(generator);
+    add::<CredentialVerification>(generator);
+
+    // Retriever
+    add::<RetrieverUser>(generator);
+    add::<RetrievedMessage>(generator);
+    add::<RetrieverSyncStatus>(generator);
+
+    // Errors
+    add::<Error>(generator);
+    add::<ApiError>(generator);
+    add::<IdentityError>(generator);
+    add::<StorageError>(generator);
+    add::<SecretError>(generator);
+    add::<AnalyticsError>(generator);
+    add::<NodeError>(generator);
+    add::<UserError>(generator);
+}
+
+fn definitions(settings: SchemaSettings) -> BTreeMap<String, Value> {
+    let mut generator = settings.into_generator();
+    add_models(&mut generator);
+    generator
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap_or_default()))
+        .collect()
+}
+
+/// Draft-07 schemas of all models
+pub fn bundle() -> SchemaBundle {
+    SchemaBundle {
+        schema: SCHEMA_DRAFT.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        definitions: definitions(SchemaSettings::draft07()),
+    }
+}
+
+/// An OpenAPI 3.0 document holding all models as component schemas, without any paths
+pub fn openapi() -> OpenApi {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    add_models(&mut generator);
+    let schemas = generator
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, schema.into_object()))
+        .collect();
+
+    OpenApi {
+        openapi: "3.0.0".to_string(),
+        info: Info {
+            title: "Demia SDK models".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            ..Default::default()
+        },
+        components: Some(Components {
+            schemas,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_contains_models() {
+        let bundle = bundle();
+        for name in [
+            "Site",
+            "ValueSet",
+            "Record",
+            "GuardianReport",
+            "AnalyticsProfile",
+            "Error",
+        ] {
+            assert!(bundle.definitions.contains_key(name), "{} is missing", name);
+        }
+        // References resolve within the bundle
+        let site = serde_json::to_string(&bundle.definitions["Site"]).unwrap();
+        assert!(site.contains("#/definitions/SiteLocation"));
+        assert!(bundle.definitions.contains_key("SiteLocation"));
+
+        let openapi = serde_json::to_value(openapi()).unwrap();
+        assert!(openapi.pointer("/components/schemas/Site").is_some());
+    }
+}
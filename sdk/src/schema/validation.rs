@@ -0,0 +1,802 @@
+use std::sync::Arc;
+
+use regex::Regex;
+use rocket_okapi::okapi::schemars::{JsonSchema, gen::SchemaSettings};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value, json};
+
+use crate::errors::{ValidationError, ValidationErrors, ValidationResult};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValidationMode {
+    /// Rejects unknown properties, ambiguous variants and reading values the deserializer would coerce. Schemas
+    /// using keywords or formats the validator does not check are rejected rather than passed
+    #[default]
+    Strict,
+    /// Accepts everything the models' deserializers accept, ignoring keywords the validator does not check
+    Lenient,
+}
+
+const REQUIRED: &str = "is required";
+
+/// Keywords the validator checks, or annotations that need no checking
+const KEYWORDS: &[&str] = &[
+    "$schema",
+    "$id",
+    "$ref",
+    "$comment",
+    "definitions",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+    "type",
+    "enum",
+    "const",
+    "allOf",
+    "anyOf",
+    "oneOf",
+    "not",
+    "if",
+    "then",
+    "else",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "format",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "items",
+    "minItems",
+    "maxItems",
+    "uniqueItems",
+    "properties",
+    "patternProperties",
+    "additionalProperties",
+    "required",
+];
+
+/// Formats the validator checks, or that need no checking beyond the type
+const FORMATS: &[&str] = &[
+    "date-time",
+    "date",
+    "uuid",
+    "int8",
+    "int16",
+    "int32",
+    "int64",
+    "int",
+    "uint8",
+    "uint16",
+    "uint32",
+    "uint64",
+    "uint",
+    "float",
+    "double",
+];
+
+/// Schemas replacing the generated ones, where a type deserializes from more than its schema describes
+fn overrides(mode: ValidationMode) -> Vec<(&'static str, Value)> {
+    let tagged = |tag: &str, schema: Value| match mode {
+        ValidationMode::Strict => json!({
+            "type": "object",
+            "required": [tag],
+            "properties": { tag: schema },
+            "additionalProperties": false
+        }),
+        // `{"Float": "abc"}` deserializes to `Float(0.0)`
+        ValidationMode::Lenient if tag == "Float" => json!({ "type": "object", "required": [tag] }),
+        ValidationMode::Lenient => json!({ "type": "object", "required": [tag], "properties": { tag: schema } }),
+    };
+
+    vec![(
+        "NestedReadingValue",
+        json!({
+            "anyOf": [
+                { "type": ["number", "string", "boolean", "null"] },
+                tagged("Float", json!({ "type": "number" })),
+                tagged("String", json!({ "type": "string" })),
+                tagged("Int", json!({ "type": "integer", "format": "int32" })),
+                tagged("Bool", json!({ "type": "boolean" })),
+            ]
+        }),
+    )]
+}
+
+/// Checks JSON against the generated schema of a model, before deserializing it.
+///
+/// Errors carry the JSON pointer of the offending value, e.g. `/reading/Sensor/value/Float`.
+#[derive(Debug, Clone)]
+pub struct Validator {
+    root: Arc<Value>,
+    mode: ValidationMode,
+    /// Keywords and formats of the schema the validator does not check, reported in strict mode
+    unsupported: Vec<ValidationError>,
+}
+
+impl Validator {
+    pub fn for_type<T: JsonSchema>(mode: ValidationMode) -> Self {
+        let root = SchemaSettings::draft07().into_generator().into_root_schema_for::<T>();
+        let mut root = serde_json::to_value(root).unwrap_or(Value::Bool(true));
+
+        for (name, schema) in overrides(mode) {
+            if root.get("title").and_then(Value::as_str) == Some(name) {
+                let definitions = root.get("definitions").cloned();
+                root = schema;
+                if let (Some(root), Some(definitions)) = (root.as_object_mut(), definitions) {
+                    root.insert("definitions".to_string(), definitions);
+                }
+            } else if let Some(definitions) = root.get_mut("definitions").and_then(Value::as_object_mut) {
+                if definitions.contains_key(name) {
+                    definitions.insert(name.to_string(), schema);
+                }
+            }
+        }
+        Self::new(root, mode)
+    }
+
+    fn new(root: Value, mode: ValidationMode) -> Self {
+        let mut unsupported = Vec::new();
+        if mode == ValidationMode::Strict {
+            find_unsupported(&root, "", &mut unsupported);
+        }
+        Self {
+            root: Arc::new(root),
+            mode,
+            unsupported,
+        }
+    }
+
+    pub fn mode(&self) -> ValidationMode {
+        self.mode
+    }
+
+    pub fn validate(&self, value: &Value) -> ValidationResult<()> {
+        let mut errors = self.unsupported.clone();
+        self.check(&self.root, value, "", &mut errors);
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(ValidationErrors(errors)),
+        }
+    }
+
+    /// Validates the value and deserializes it into `T`
+    pub fn deserialize<T: DeserializeOwned>(&self, value: Value) -> ValidationResult<T> {
+        self.validate(&value)?;
+        serde_json::from_value(value).map_err(|e| ValidationErrors(vec![ValidationError::new("", e.to_string())]))
+    }
+
+    fn resolve(&self, reference: &str) -> Option<&Value> {
+        match reference {
+            "#" => Some(self.root.as_ref()),
+            reference => self
+                .root
+                .get("definitions")?
+                .get(reference.strip_prefix("#/definitions/")?),
+        }
+    }
+
+    fn check(&self, schema: &Value, value: &Value, path: &str, errors: &mut Vec<ValidationError>) {
+        let schema = match schema {
+            Value::Object(schema) => schema,
+            Value::Bool(false) => return errors.push(ValidationError::new(path, "no value is allowed")),
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match self.resolve(reference) {
+                Some(target) => self.check(target, value, path, errors),
+                None => errors.push(ValidationError::new(path, format!("unknown reference {}", reference))),
+            }
+            return;
+        }
+
+        let types = types(schema);
+        if !types.is_empty() && !types.iter().any(|name| is_type(value, name)) {
+            return errors.push(ValidationError::new(
+                path,
+                format!("expected {}, found {}", types.join(" or "), type_name(value)),
+            ));
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            if !values.contains(value) {
+                let allowed = values.iter().map(Value::to_string).collect::<Vec<_>>();
+                return errors.push(ValidationError::new(
+                    path,
+                    format!("expected one of {}, found {}", allowed.join(", "), value),
+                ));
+            }
+        }
+        if let Some(constant) = schema.get("const") {
+            if constant != value {
+                return errors.push(ValidationError::new(
+                    path,
+                    format!("expected {}, found {}", constant, value),
+                ));
+            }
+        }
+
+        if let Some(schemas) = schema.get("allOf").and_then(Value::as_array) {
+            schemas
+                .iter()
+                .for_each(|schema| self.check(schema, value, path, errors));
+        }
+        if let Some(schemas) = schema.get("anyOf").and_then(Value::as_array) {
+            self.check_variants(schemas, value, path, false, errors);
+        }
+        if let Some(schemas) = schema.get("oneOf").and_then(Value::as_array) {
+            self.check_variants(schemas, value, path, true, errors);
+        }
+        if let Some(not) = schema.get("not") {
+            if self.matches(not, value, path) {
+                errors.push(ValidationError::new(path, "matches a schema it must not match"));
+            }
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = match self.matches(condition, value, path) {
+                true => schema.get("then"),
+                false => schema.get("else"),
+            };
+            if let Some(branch) = branch {
+                self.check(branch, value, path, errors);
+            }
+        }
+
+        match value {
+            Value::Number(_) => check_number(schema, value, path, errors),
+            Value::String(string) => check_string(schema, string, path, errors),
+            Value::Array(items) => self.check_array(schema, items, path, errors),
+            Value::Object(object) => self.check_object(schema, object, path, errors),
+            _ => {}
+        }
+    }
+
+    /// Whether the value matches a subschema of `not` or `if`. These describe part of the value only, so properties
+    /// they don't list are not errors here
+    fn matches(&self, schema: &Value, value: &Value, path: &str) -> bool {
+        let lenient = Self {
+            root: self.root.clone(),
+            mode: ValidationMode::Lenient,
+            unsupported: Vec::new(),
+        };
+        let mut errors = Vec::new();
+        lenient.check(schema, value, path, &mut errors);
+        errors.is_empty()
+    }
+
+    fn check_variants(
+        &self,
+        variants: &[Value],
+        value: &Value,
+        path: &str,
+        exactly_one: bool,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let results = variants
+            .iter()
+            .map(|variant| {
+                let mut variant_errors = Vec::new();
+                self.check(variant, value, path, &mut variant_errors);
+                variant_errors
+            })
+            .collect::<Vec<_>>();
+
+        match results.iter().filter(|result| result.is_empty()).count() {
+            0 => {}
+            1 => return,
+            matched if exactly_one && self.mode == ValidationMode::Strict => {
+                return errors.push(ValidationError::new(
+                    path,
+                    format!("matches {} variants, expected exactly one", matched),
+                ));
+            }
+            _ => return,
+        }
+
+        // Variants rejecting the value as a whole say little. Of the others, report the one that found the properties
+        // it requires, i.e. the variant of the tag that was given
+        let closest = results
+            .into_iter()
+            .filter(|result| result.iter().all(|error| error.path != path))
+            .min_by_key(|result| {
+                let missing = result.iter().filter(|error| error.message == REQUIRED).count();
+                (missing, result.len())
+            });
+        match closest {
+            Some(closest) => errors.extend(closest),
+            None => errors.push(ValidationError::new(
+                path,
+                format!("{} does not match any of the allowed variants", type_name(value)),
+            )),
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        object: &Map<String, Value>,
+        path: &str,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for name in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(name) {
+                errors.push(ValidationError::new(&pointer(path, name), REQUIRED));
+            }
+        }
+
+        let patterns = schema
+            .get("patternProperties")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+            .filter_map(|(pattern, schema)| match Regex::new(pattern) {
+                Ok(regex) => Some((regex, schema)),
+                Err(e) => {
+                    errors.push(ValidationError::new(
+                        path,
+                        format!("invalid pattern {}: {}", pattern, e),
+                    ));
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for (name, value) in object {
+            let property_path = pointer(path, name);
+            let mut matched = false;
+            if let Some(property) = properties.and_then(|properties| properties.get(name)) {
+                self.check(property, value, &property_path, errors);
+                matched = true;
+            }
+            for (_, schema) in patterns.iter().filter(|(regex, _)| regex.is_match(name)) {
+                self.check(schema, value, &property_path, errors);
+                matched = true;
+            }
+            if matched {
+                continue;
+            }
+            match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => errors.push(ValidationError::new(&property_path, "is not allowed")),
+                Some(additional) => self.check(additional, value, &property_path, errors),
+                // Serde ignores unknown fields unless told otherwise, which hides misspelled ones
+                None if (properties.is_some() || !patterns.is_empty()) && self.mode == ValidationMode::Strict => {
+                    errors.push(ValidationError::new(&property_path, "is not a known property"))
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn check_array(&self, schema: &Map<String, Value>, items: &[Value], path: &str, errors: &mut Vec<ValidationError>) {
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+            if (items.len() as u64) < min {
+                errors.push(ValidationError::new(
+                    path,
+                    format!("expected at least {} items, found {}", min, items.len()),
+                ));
+            }
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+            if items.len() as u64 > max {
+                errors.push(ValidationError::new(
+                    path,
+                    format!("expected at most {} items, found {}", max, items.len()),
+                ));
+            }
+        }
+
+        if schema.get("uniqueItems").and_then(Value::as_bool) == Some(true) {
+            for (index, item) in items.iter().enumerate() {
+                if items[..index].contains(item) {
+                    errors.push(ValidationError::new(
+                        &pointer(path, &index.to_string()),
+                        "is a duplicate item",
+                    ));
+                }
+            }
+        }
+
+        match schema.get("items") {
+            // Tuples list a schema per position
+            Some(Value::Array(schemas)) => {
+                for (index, (schema, item)) in schemas.iter().zip(items).enumerate() {
+                    self.check(schema, item, &pointer(path, &index.to_string()), errors);
+                }
+            }
+            Some(schema) => {
+                for (index, item) in items.iter().enumerate() {
+                    self.check(schema, item, &pointer(path, &index.to_string()), errors);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+fn check_number(schema: &Map<String, Value>, value: &Value, path: &str, errors: &mut Vec<ValidationError>) {
+    let number = value.as_f64().unwrap_or_default();
+    if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+        if number < min {
+            errors.push(ValidationError::new(path, format!("must be at least {}", min)));
+        }
+    }
+    if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+        if number > max {
+            errors.push(ValidationError::new(path, format!("must be at most {}", max)));
+        }
+    }
+    if let Some(min) = schema.get("exclusiveMinimum").and_then(Value::as_f64) {
+        if number <= min {
+            errors.push(ValidationError::new(path, format!("must be greater than {}", min)));
+        }
+    }
+    if let Some(max) = schema.get("exclusiveMaximum").and_then(Value::as_f64) {
+        if number >= max {
+            errors.push(ValidationError::new(path, format!("must be less than {}", max)));
+        }
+    }
+    if let Some(divisor) = schema.get("multipleOf").and_then(Value::as_f64) {
+        let quotient = number / divisor;
+        if divisor > 0.0 && (quotient - quotient.round()).abs() > f64::EPSILON * quotient.abs().max(1.0) {
+            errors.push(ValidationError::new(path, format!("must be a multiple of {}", divisor)));
+        }
+    }
+    if let Some(format) = schema.get("format").and_then(Value::as_str) {
+        if !in_range(value, format) {
+            errors.push(ValidationError::new(
+                path,
+                format!("{} is out of range for {}", value, format),
+            ));
+        }
+    }
+}
+
+fn check_string(schema: &Map<String, Value>, string: &str, path: &str, errors: &mut Vec<ValidationError>) {
+    let valid = match schema.get("format").and_then(Value::as_str) {
+        Some("date-time") => chrono::DateTime::parse_from_rfc3339(string).is_ok(),
+        Some("date") => chrono::NaiveDate::parse_from_str(string, "%Y-%m-%d").is_ok(),
+        Some("uuid") => uuid::Uuid::parse_str(string).is_ok(),
+        _ => true,
+    };
+    if !valid {
+        errors.push(ValidationError::new(
+            path,
+            format!(
+                "\"{}\" is not a valid {}",
+                string,
+                schema["format"].as_str().unwrap_or_default()
+            ),
+        ));
+    }
+
+    let length = string.chars().count() as u64;
+    if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+        if length < min {
+            errors.push(ValidationError::new(
+                path,
+                format!("expected at least {} characters, found {}", min, length),
+            ));
+        }
+    }
+    if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+        if length > max {
+            errors.push(ValidationError::new(
+                path,
+                format!("expected at most {} characters, found {}", max, length),
+            ));
+        }
+    }
+    if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+        match Regex::new(pattern) {
+            Ok(regex) if regex.is_match(string) => {}
+            Ok(_) => errors.push(ValidationError::new(
+                path,
+                format!("\"{}\" does not match {}", string, pattern),
+            )),
+            Err(e) => errors.push(ValidationError::new(
+                path,
+                format!("invalid pattern {}: {}", pattern, e),
+            )),
+        }
+    }
+}
+
+/// Reports the keywords and formats of the schema and its subschemas that the validator does not check, at the JSON
+/// pointer of the schema
+fn find_unsupported(schema: &Value, path: &str, errors: &mut Vec<ValidationError>) {
+    let Value::Object(schema) = schema else {
+        return;
+    };
+    for keyword in schema.keys().filter(|keyword| !KEYWORDS.contains(&keyword.as_str())) {
+        errors.push(ValidationError::new(
+            path,
+            format!("schema keyword {} is not supported", keyword),
+        ));
+    }
+    if let Some(format) = schema.get("format").and_then(Value::as_str) {
+        if !FORMATS.contains(&format) {
+            errors.push(ValidationError::new(
+                path,
+                format!("schema format {} is not supported", format),
+            ));
+        }
+    }
+
+    for (keyword, subschema) in schema {
+        let keyword_path = pointer(path, keyword);
+        match (keyword.as_str(), subschema) {
+            ("definitions" | "properties" | "patternProperties", Value::Object(schemas)) => {
+                for (name, schema) in schemas {
+                    find_unsupported(schema, &pointer(&keyword_path, name), errors);
+                }
+            }
+            ("allOf" | "anyOf" | "oneOf" | "items", Value::Array(schemas)) => {
+                for (index, schema) in schemas.iter().enumerate() {
+                    find_unsupported(schema, &pointer(&keyword_path, &index.to_string()), errors);
+                }
+            }
+            ("items" | "additionalProperties" | "not" | "if" | "then" | "else", schema) => {
+                find_unsupported(schema, &keyword_path, errors)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Whether the number fits the integer format. Other formats take any number
+fn in_range(value: &Value, format: &str) -> bool {
+    let range = match format {
+        "int8" => (i8::MIN as f64, i8::MAX as f64),
+        "int16" => (i16::MIN as f64, i16::MAX as f64),
+        "int32" => (i32::MIN as f64, i32::MAX as f64),
+        "uint8" => (0.0, u8::MAX as f64),
+        "uint16" => (0.0, u16::MAX as f64),
+        "uint32" => (0.0, u32::MAX as f64),
+        // Beyond the precision of f64, so checked on the integer itself
+        "int64" | "int" => return value.as_i64().is_some(),
+        "uint64" | "uint" => return value.as_u64().is_some(),
+        _ => return true,
+    };
+    let number = value.as_f64().unwrap_or_default();
+    number >= range.0 && number <= range.1
+}
+
+fn types(schema: &Map<String, Value>) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Appends a reference token, escaped as in RFC 6901
+fn pointer(path: &str, token: &str) -> String {
+    format!("{}/{}", path, token.replace('~', "~0").replace('/', "~1"))
+}
+
+/// Validates the value against the schema of `T`. Keep a [`Validator`] around when checking many payloads
+pub fn validate<T: JsonSchema>(value: &Value, mode: ValidationMode) -> ValidationResult<()> {
+    Validator::for_type::<T>(mode).validate(value)
+}
+
+/// Validates the value against the schema of `T` before deserializing it
+pub fn from_value<T: JsonSchema + DeserializeOwned>(value: Value, mode: ValidationMode) -> ValidationResult<T> {
+    Validator::for_type::<T>(mode).deserialize(value)
+}
+
+pub fn from_slice<T: JsonSchema + DeserializeOwned>(json: &[u8], mode: ValidationMode) -> ValidationResult<T> {
+    let value =
+        serde_json::from_slice(json).map_err(|e| ValidationErrors(vec![ValidationError::new("", e.to_string())]))?;
+    from_value(value, mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NewSite, ReadingWrap, WrappedReadingType};
+
+    fn reading(value: Value) -> Value {
+        json!({
+            "id": "reading-1",
+            "address": "address-1",
+            "reading": {
+                "Sensor": {
+                    "id": "sensor-1",
+                    "value": value,
+                    "timestamp": "2024-05-01T12:00:00Z"
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_reading_values() {
+        let errors = validate::<ReadingWrap>(&reading(json!({ "Float": "abc" })), ValidationMode::Strict).unwrap_err();
+        assert_eq!(
+            errors.0,
+            vec![ValidationError::new(
+                "/reading/Sensor/value/Float",
+                "expected number, found string"
+            )]
+        );
+
+        // The deserializer coerces it to 0.0
+        let wrap = from_value::<ReadingWrap>(reading(json!({ "Float": "abc" })), ValidationMode::Lenient).unwrap();
+        assert!(matches!(wrap.reading, WrappedReadingType::Sensor(sensor) if sensor.value.as_f64() == Some(0.0)));
+
+        for value in [json!(1.5), json!("on"), json!(null), json!({ "Int": 3 })] {
+            validate::<ReadingWrap>(&reading(value), ValidationMode::Strict).unwrap();
+        }
+        let errors =
+            validate::<ReadingWrap>(&reading(json!({ "Int": 3_000_000_000u64 })), ValidationMode::Lenient).unwrap_err();
+        assert_eq!(errors.0[0].path, "/reading/Sensor/value/Int");
+
+        let mut missing = reading(json!(1.5));
+        missing["reading"]["Sensor"]
+            .as_object_mut()
+            .unwrap()
+            .remove("timestamp");
+        let errors = validate::<ReadingWrap>(&missing, ValidationMode::Lenient).unwrap_err();
+        assert_eq!(
+            errors.to_string(),
+            "Invalid payload: /reading/Sensor/timestamp: is required"
+        );
+    }
+
+    #[test]
+    fn test_unknown_properties() {
+        let mut site = serde_json::to_value(NewSite::default()).unwrap();
+        site["colour"] = json!("green");
+
+        let errors = validate::<NewSite>(&site, ValidationMode::Strict).unwrap_err();
+        assert_eq!(
+            errors.0,
+            vec![ValidationError::new("/colour", "is not a known property")]
+        );
+        validate::<NewSite>(&site, ValidationMode::Lenient).unwrap();
+    }
+
+    fn validator(schema: Value, mode: ValidationMode) -> Validator {
+        Validator::new(schema, mode)
+    }
+
+    fn messages(validator: &Validator, value: Value) -> Vec<String> {
+        match validator.validate(&value) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.0.into_iter().map(|error| error.message).collect(),
+        }
+    }
+
+    #[test]
+    fn test_string_and_number_keywords() {
+        let validator = validator(
+            json!({
+                "type": "object",
+                "properties": {
+                    "code": { "type": "string", "pattern": "^[A-Z]{2}$", "minLength": 2, "maxLength": 2 },
+                    "score": { "type": "number", "exclusiveMinimum": 0, "exclusiveMaximum": 1 },
+                    "step": { "type": "number", "multipleOf": 0.5 },
+                    "count": { "type": "integer", "format": "uint64" },
+                    "offset": { "type": "integer", "format": "int64" }
+                }
+            }),
+            ValidationMode::Strict,
+        );
+        assert!(
+            messages(
+                &validator,
+                json!({ "code": "EU", "score": 0.5, "step": 2.5, "count": u64::MAX, "offset": i64::MIN })
+            )
+            .is_empty()
+        );
+
+        let mut errors = messages(
+            &validator,
+            json!({ "code": "eur", "score": 1, "step": 0.3, "count": -1, "offset": u64::MAX }),
+        );
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                "\"eur\" does not match ^[A-Z]{2}$",
+                "-1 is out of range for uint64",
+                "18446744073709551615 is out of range for int64",
+                "expected at most 2 characters, found 3",
+                "must be a multiple of 0.5",
+                "must be less than 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_applicators() {
+        let validator = validator(
+            json!({
+                "type": "object",
+                "patternProperties": { "^x-": { "type": "string" } },
+                "not": { "required": ["legacy"] },
+                "if": { "properties": { "kind": { "const": "sensor" } }, "required": ["kind"] },
+                "then": { "required": ["unit"] },
+                "else": { "required": ["label"] },
+                "additionalProperties": { "type": ["string", "number"] }
+            }),
+            ValidationMode::Strict,
+        );
+        assert!(
+            messages(
+                &validator,
+                json!({ "kind": "sensor", "unit": "kWh", "x-source": "meter" })
+            )
+            .is_empty()
+        );
+        assert!(messages(&validator, json!({ "kind": "meter", "label": "Main" })).is_empty());
+
+        let errors = validator
+            .validate(&json!({ "kind": "sensor", "legacy": 1, "x-source": 2 }))
+            .unwrap_err();
+        assert_eq!(
+            errors.0,
+            vec![
+                ValidationError::new("", "matches a schema it must not match"),
+                ValidationError::new("/unit", REQUIRED),
+                ValidationError::new("/x-source", "expected string, found integer"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_keywords() {
+        let schema = json!({
+            "type": "object",
+            "minProperties": 1,
+            "properties": { "id": { "type": "string", "format": "email" } }
+        });
+        let strict = validator(schema.clone(), ValidationMode::Strict);
+        assert_eq!(
+            messages(&strict, json!({ "id": "a" })),
+            vec![
+                "schema keyword minProperties is not supported",
+                "schema format email is not supported",
+            ]
+        );
+        assert!(messages(&validator(schema, ValidationMode::Lenient), json!({ "id": "a" })).is_empty());
+    }
+}
@@ -0,0 +1,470 @@
+//! A logged in user with all of the SDK's clients.
+//!
+//! [`DemiaSessionBuilder`] logs in through the [`TokenManager`], fetches the stronghold password from Vault,
+//! downloads the user's snapshot and opens their [`UserIdentity`]. Renewed tokens are handed to Vault and the storage
+//! provider, and [`DemiaSession::shutdown`] uploads the snapshots again.
+
+use std::{fmt::Debug, future::Future, path::Path};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+
+#[cfg(feature = "aws")]
+use crate::clients::AwsClient;
+use crate::{
+    clients::{ApiClient, SecretManager, Storage, StorageClient, StorageDataType, TokenManager},
+    configuration::BaseConfiguration,
+    errors::{SdkResult, StorageError, StorageResult},
+    iota_sdk::client::{Client as IdentityClient, secret::stronghold::StrongholdSecretManager},
+    models::{TokenType, TokenWrap, UserIdentity, VaultClient},
+};
+
+/// How the session logs in
+#[derive(Clone)]
+pub enum Credentials {
+    Password {
+        username: String,
+        password: String,
+    },
+    /// Client credentials grant, for services
+    ClientSecret(String),
+    /// A refresh token of an earlier session
+    RefreshToken(String),
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Password { username, .. } => f.debug_struct("Password").field("username", username).finish(),
+            Self::ClientSecret(_) => f.write_str("ClientSecret"),
+            Self::RefreshToken(_) => f.write_str("RefreshToken"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DemiaSessionBuilder {
+    config: BaseConfiguration,
+    credentials: Credentials,
+    token_manager: TokenManager,
+    token_type: TokenType,
+}
+
+impl DemiaSessionBuilder {
+    pub fn new(config: BaseConfiguration, credentials: Credentials) -> Self {
+        Self {
+            config,
+            credentials,
+            token_manager: TokenManager::default(),
+            token_type: TokenType::VAULT,
+        }
+    }
+
+    /// Logs in through this token manager instead of the default Keycloak one
+    pub fn token_manager(mut self, token_manager: TokenManager) -> Self {
+        self.token_manager = token_manager;
+        self
+    }
+
+    /// Type of the token obtained at login, `TokenType::VAULT` by default
+    pub fn token_type(mut self, token_type: TokenType) -> Self {
+        self.token_type = token_type;
+        self
+    }
+
+    /// Logs in and opens the identity. `storage` creates the storage provider from the login token
+    pub async fn connect<T, F, Fut>(mut self, storage: F) -> SdkResult<DemiaSession<T>>
+    where
+        T: Storage + Debug,
+        F: FnOnce(TokenWrap) -> Fut,
+        Fut: Future<Output = StorageResult<T>>,
+    {
+        let token = match &self.credentials {
+            Credentials::Password { username, password } => {
+                self.token_manager
+                    .get_token(&self.token_type, username, password)
+                    .await?
+            }
+            Credentials::ClientSecret(secret) => {
+                self.token_manager
+                    .get_token_with_secret(&self.token_type, secret)
+                    .await?
+            }
+            Credentials::RefreshToken(refresh_token) => self.token_manager.resume(refresh_token.clone()).await?,
+        };
+
+        let mut vault = VaultClient::new(&self.config, token.clone()).await?;
+        let password = vault.retrieve_password().await?;
+
+        let storage = StorageClient::new(
+            self.config.application.public_bucket_path.clone(),
+            self.config.application.protected_bucket_path.clone(),
+            token.clone(),
+            storage(token.clone()).await?,
+        )
+        .await?;
+        download_snapshot(&storage, &self.config.stronghold.path).await?;
+
+        let api = ApiClient::try_from(&self.config.application)?;
+        let client = IdentityClient::builder()
+            .with_node(&self.config.identity.client.url)?
+            .finish()
+            .await?;
+        let (adapter, interrupted) = open_stronghold(&mut vault, &self.config.stronghold.path, &password).await?;
+        let identity = UserIdentity::new(
+            &api,
+            &token,
+            &self.config.identity,
+            &self.config.stronghold,
+            client,
+            adapter,
+        )
+        .await?;
+        if interrupted {
+            info!("Finishing an interrupted stronghold password rotation");
+            identity.change_stronghold_password(password).await?;
+        }
+
+        info!("Session opened for {}", identity.doc_id());
+        Ok(DemiaSession {
+            config: self.config,
+            token_manager: self.token_manager,
+            token_type: self.token_type,
+            token,
+            vault,
+            storage,
+            identity,
+            api,
+        })
+    }
+
+    /// Connects with S3 as the storage provider
+    #[cfg(feature = "aws")]
+    pub async fn connect_aws(self) -> SdkResult<DemiaSession<AwsClient>> {
+        self.connect(AwsClient::new).await
+    }
+}
+
+/// Opens the snapshot with the current stronghold password. A snapshot that only opens with the previous version is
+/// left by an interrupted rotation, which is reported so the caller re-encrypts it under the current password
+async fn open_stronghold(
+    vault: &mut VaultClient,
+    path: &str,
+    password: &str,
+) -> SdkResult<(StrongholdSecretManager, bool)> {
+    let error = match StrongholdSecretManager::builder()
+        .password(password.to_string())
+        .build(path)
+    {
+        Ok(adapter) => return Ok((adapter, false)),
+        Err(e) => e,
+    };
+
+    match vault.previous_password().await? {
+        Some(previous) => match StrongholdSecretManager::builder().password(previous).build(path) {
+            Ok(adapter) => Ok((adapter, true)),
+            Err(_) => Err(error.into()),
+        },
+        None => Err(error.into()),
+    }
+}
+
+/// Downloads the remote snapshot, unless the local one is at least as recent. A user without a remote snapshot keeps the
+/// local one, or starts with a new stronghold. Any other failure is an error, so a snapshot that could not be read is
+/// never replaced by a new one
+async fn download_snapshot<T: Storage + Debug>(storage: &StorageClient<T>, path: &str) -> SdkResult<()> {
+    let local = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(DateTime::<Utc>::from);
+
+    match storage
+        .download_data(StorageDataType::StrongholdSnapshot(path), local)
+        .await
+    {
+        Ok(_) | Err(StorageError::NotModified) => Ok(()),
+        Err(StorageError::NotFound(_)) => {
+            match local {
+                Some(_) => warn!("No stronghold snapshot stored, keeping the local one"),
+                None => info!("No stronghold snapshot stored, starting a new one"),
+            }
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Uploads the stronghold snapshot, and the streams snapshot when there is one. Both are attempted, the first failure
+/// is returned
+async fn upload_snapshots<T: Storage + Debug>(
+    storage: &StorageClient<T>,
+    stronghold_path: &str,
+    streams_path: &str,
+) -> StorageResult<()> {
+    let stronghold = storage
+        .upload(StorageDataType::StrongholdSnapshot(stronghold_path), None)
+        .await;
+    if let Err(e) = &stronghold {
+        warn!("Uploading the stronghold snapshot failed: {}", e);
+    }
+
+    let streams = match Path::new(streams_path).exists() {
+        true => {
+            storage
+                .upload(StorageDataType::StreamsSnapshot(streams_path), None)
+                .await
+        }
+        false => Ok(()),
+    };
+    if let Err(e) = &streams {
+        warn!("Uploading the streams snapshot failed: {}", e);
+    }
+    stronghold.and(streams)
+}
+
+/// The clients of a logged in user
+pub struct DemiaSession<T: Storage> {
+    config: BaseConfiguration,
+    token_manager: TokenManager,
+    token_type: TokenType,
+    /// Token the vault and storage clients currently hold
+    token: TokenWrap,
+    vault: VaultClient,
+    storage: StorageClient<T>,
+    identity: UserIdentity,
+    api: ApiClient,
+}
+
+impl<T: Storage + Debug> Debug for DemiaSession<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DemiaSession")
+            .field("token_type", &self.token_type)
+            .field("vault", &self.vault)
+            .field("storage", &self.storage)
+            .field("identity", &self.identity)
+            .finish()
+    }
+}
+
+impl<T: Storage + Debug> DemiaSession<T> {
+    pub fn config(&self) -> &BaseConfiguration {
+        &self.config
+    }
+
+    pub fn token_manager(&self) -> &TokenManager {
+        &self.token_manager
+    }
+
+    pub fn token_manager_mut(&mut self) -> &mut TokenManager {
+        &mut self.token_manager
+    }
+
+    pub fn vault(&self) -> &VaultClient {
+        &self.vault
+    }
+
+    pub fn vault_mut(&mut self) -> &mut VaultClient {
+        &mut self.vault
+    }
+
+    pub fn storage(&self) -> &StorageClient<T> {
+        &self.storage
+    }
+
+    pub fn identity(&self) -> &UserIdentity {
+        &self.identity
+    }
+
+    pub fn identity_mut(&mut self) -> &mut UserIdentity {
+        &mut self.identity
+    }
+
+    pub fn api(&self) -> &ApiClient {
+        &self.api
+    }
+
+    /// An unexpired login token. When it had to be renewed, the vault and storage clients are given the new one
+    pub async fn token(&mut self) -> SdkResult<TokenWrap> {
+        let token = self.token_manager.exchange(&self.token_type).await?;
+        if token.raw() != self.token.raw() {
+            self.vault.update_client_token(token.clone()).await?;
+            self.storage.update_credentials(token.clone()).await?;
+            self.token = token.clone();
+        }
+        Ok(token)
+    }
+
+    /// Writes the stronghold snapshot and uploads it, along with the streams snapshot when there is one, then saves
+    /// the refresh token if session persistence is enabled.
+    ///
+    /// The local snapshot is written first, so it is current whatever fails after. When the token can't be renewed
+    /// the uploads are still attempted with the current one
+    pub async fn shutdown(mut self) -> SdkResult<()> {
+        self.identity.write_stronghold_snapshot().await?;
+
+        if let Err(e) = self.token().await {
+            warn!("Renewing the token failed, uploading with the current one: {}", e);
+        }
+        let uploaded = upload_snapshots(
+            &self.storage,
+            &self.config.stronghold.path,
+            &self.config.streams.backup_path,
+        )
+        .await;
+        let persisted = self.token_manager.persist_session().await;
+
+        uploaded?;
+        persisted?;
+        info!("Session closed for {}", self.identity.doc_id());
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::{
+        clients::{FileInfo, FileMetadata, StorageInfo},
+        test_support::{MockOidcConfig, MockOidcIssuer, MockUser},
+    };
+
+    /// Objects kept in memory, keyed by their storage path
+    #[derive(Debug, Clone, Default)]
+    struct MemoryStorage {
+        objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        /// Paths of every upload attempt
+        uploads: Arc<Mutex<Vec<String>>>,
+        /// Returned by every call when set
+        error: Option<StorageError>,
+    }
+
+    impl MemoryStorage {
+        fn failing(error: StorageError) -> Self {
+            Self {
+                error: Some(error),
+                ..Default::default()
+            }
+        }
+
+        fn fail(&self) -> StorageResult<()> {
+            self.error.clone().map_or(Ok(()), Err)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Storage for MemoryStorage {
+        async fn upload(&self, file: StorageInfo<'_>) -> StorageResult<()> {
+            self.uploads.lock().unwrap().push(file.url.clone());
+            self.fail()?;
+            self.objects
+                .lock()
+                .unwrap()
+                .insert(file.url, file.data.unwrap_or_default());
+            Ok(())
+        }
+
+        async fn download(&self, info: StorageInfo<'_>, _: Option<DateTime<Utc>>) -> StorageResult<Vec<u8>> {
+            self.fail()?;
+            let object = self.objects.lock().unwrap().get(&info.url).cloned();
+            object.ok_or(StorageError::NotFound(info.url))
+        }
+
+        async fn delete(&self, info: StorageInfo<'_>) -> StorageResult<()> {
+            self.fail()?;
+            self.objects.lock().unwrap().remove(&info.url);
+            Ok(())
+        }
+
+        async fn list_objects(&self, _: StorageInfo<'_>) -> StorageResult<Vec<FileInfo>> {
+            self.fail()?;
+            Ok(Vec::new())
+        }
+
+        async fn get_metadata(&self, file: StorageInfo<'_>) -> StorageResult<FileMetadata> {
+            self.fail()?;
+            Err(StorageError::NotFound(file.url))
+        }
+
+        async fn set_metadata(&self, _: StorageInfo<'_>, _: HashMap<String, String>) -> StorageResult<()> {
+            self.fail()
+        }
+
+        async fn update_credentials(&mut self, _: TokenWrap) -> StorageResult<()> {
+            self.fail()
+        }
+    }
+
+    async fn storage_client(storage: MemoryStorage) -> (MockOidcIssuer, StorageClient<MemoryStorage>) {
+        let user = MockUser::new("alice", "correct horse");
+        let issuer = MockOidcIssuer::start(MockOidcConfig {
+            users: vec![user.clone()],
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let token = issuer
+            .keycloak()
+            .get_token(&TokenType::VAULT, &user.username, &user.password)
+            .await
+            .unwrap();
+        let client = StorageClient::new("public".to_string(), "protected".to_string(), token, storage)
+            .await
+            .unwrap();
+        (issuer, client)
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("demia-session-{}-{}", uuid::Uuid::new_v4(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let (_issuer, storage) = storage_client(MemoryStorage::default()).await;
+        let (stronghold, streams) = (temp_path("stronghold"), temp_path("streams"));
+
+        // A new user has nothing stored yet
+        download_snapshot(&storage, &stronghold).await.unwrap();
+        assert!(!Path::new(&stronghold).exists());
+
+        std::fs::write(&stronghold, b"snapshot").unwrap();
+        upload_snapshots(&storage, &stronghold, &streams).await.unwrap();
+        std::fs::remove_file(&stronghold).unwrap();
+
+        download_snapshot(&storage, &stronghold).await.unwrap();
+        assert_eq!(std::fs::read(&stronghold).unwrap(), b"snapshot");
+        std::fs::remove_file(&stronghold).ok();
+    }
+
+    #[tokio::test]
+    async fn test_storage_errors_fail() {
+        let failing = MemoryStorage::failing(StorageError::Credentials);
+        let (_issuer, storage) = storage_client(failing.clone()).await;
+        let (stronghold, streams) = (temp_path("stronghold"), temp_path("streams"));
+
+        // Without a local snapshot, starting a new stronghold would lose the stored one
+        assert!(matches!(
+            download_snapshot(&storage, &stronghold).await,
+            Err(crate::errors::Error::Storage(StorageError::Credentials))
+        ));
+        assert!(!Path::new(&stronghold).exists());
+
+        // The streams snapshot is attempted even though the stronghold one failed
+        std::fs::write(&stronghold, b"snapshot").unwrap();
+        std::fs::write(&streams, b"streams").unwrap();
+        assert!(matches!(
+            upload_snapshots(&storage, &stronghold, &streams).await,
+            Err(StorageError::Credentials)
+        ));
+        assert_eq!(failing.uploads.lock().unwrap().len(), 2);
+        std::fs::remove_file(&stronghold).ok();
+        std::fs::remove_file(&streams).ok();
+    }
+}
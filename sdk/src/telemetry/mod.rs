@@ -0,0 +1,337 @@
+//! Spans and OpenTelemetry metrics of the SDK's outbound calls.
+//!
+//! Every call to a service goes through a [`Call`], which runs it in a `tracing` span and records
+//! - `demia.client.duration`, a latency histogram in seconds,
+//! - `demia.client.errors`, failed calls by error variant,
+//! - `demia.token.refreshes`, renewals of access tokens.
+//!
+//! Each call is recorded by one layer only: Guardian calls as `client=guardian` with the resource, the other HTTP
+//! clients as `client=http` with the host.
+//!
+//! The instruments are no-ops until a meter provider is installed, through [`init`] or [`set_meter_provider`].
+
+use std::{
+    fmt::{Debug, Display},
+    future::Future,
+    sync::{Arc, LazyLock, RwLock},
+    time::{Duration, Instant},
+};
+
+use opentelemetry::{
+    KeyValue, global,
+    metrics::{Counter, Histogram, Meter},
+};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use tracing::{Instrument, field};
+
+use crate::{configuration::TelemetryConfiguration, errors::TelemetryResult};
+
+pub const METER_NAME: &str = "demia_sdk";
+
+struct Instruments {
+    duration: Histogram<f64>,
+    errors: Counter<u64>,
+    token_refreshes: Counter<u64>,
+}
+
+impl Instruments {
+    fn new(meter: &Meter) -> Self {
+        Self {
+            duration: meter
+                .f64_histogram("demia.client.duration")
+                .with_unit("s")
+                .with_description("Latency of outbound calls")
+                .build(),
+            errors: meter
+                .u64_counter("demia.client.errors")
+                .with_description("Failed outbound calls by error variant")
+                .build(),
+            token_refreshes: meter
+                .u64_counter("demia.token.refreshes")
+                .with_description("Renewals of access tokens")
+                .build(),
+        }
+    }
+}
+
+// Swapped when a provider is installed, since instruments stay bound to the provider they were created from
+static INSTRUMENTS: LazyLock<RwLock<Arc<Instruments>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(Instruments::new(&global::meter(METER_NAME)))));
+
+fn instruments() -> Arc<Instruments> {
+    INSTRUMENTS.read().unwrap().clone()
+}
+
+/// Installs the provider globally and binds the SDK's instruments to it
+pub fn set_meter_provider(provider: SdkMeterProvider) {
+    global::set_meter_provider(provider);
+    *INSTRUMENTS.write().unwrap() = Arc::new(Instruments::new(&global::meter(METER_NAME)));
+}
+
+/// Name of the error's variant, e.g. `GuardianResponse`. Wrapping errors give their own variant, e.g. `Api`
+pub fn error_kind(error: &impl Debug) -> String {
+    format!("{:?}", error)
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect()
+}
+
+/// Counts a renewal of an access token of the client
+pub fn record_token_refresh(client: &'static str, success: bool) {
+    instruments().token_refreshes.add(
+        1,
+        &[
+            KeyValue::new("client", client),
+            KeyValue::new("outcome", if success { "ok" } else { "error" }),
+        ],
+    );
+}
+
+/// An outbound call of one of the SDK's clients
+#[derive(Debug, Clone)]
+pub struct Call {
+    client: &'static str,
+    operation: String,
+    attributes: Vec<KeyValue>,
+}
+
+impl Call {
+    pub fn new(client: &'static str, operation: impl Into<String>) -> Self {
+        Self {
+            client,
+            operation: operation.into(),
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Adds an attribute to the metrics of the call. Keep the values low in cardinality, e.g. no ids
+    pub fn with(mut self, key: &'static str, value: impl Into<String>) -> Self {
+        self.attributes.push(KeyValue::new(key, value.into()));
+        self
+    }
+
+    pub fn span(&self) -> tracing::Span {
+        tracing::info_span!(
+            "demia_call",
+            client = self.client,
+            operation = %self.operation,
+            attributes = ?self.attributes,
+            error = field::Empty,
+        )
+    }
+
+    /// Runs the call in its span, recording the latency and the error variant when it fails
+    pub async fn observe<T, E, F>(self, call: F) -> Result<T, E>
+    where
+        E: Debug + Display,
+        F: Future<Output = Result<T, E>>,
+    {
+        let span = self.span();
+        let start = Instant::now();
+        let result = call.instrument(span.clone()).await;
+        if let Err(e) = &result {
+            span.record("error", field::display(e));
+        }
+        self.record(start.elapsed(), result.as_ref().err().map(error_kind).as_deref());
+        result
+    }
+
+    /// Records a call that was timed by the caller
+    pub fn record(&self, elapsed: Duration, error: Option<&str>) {
+        let instruments = instruments();
+        let mut attributes = vec![
+            KeyValue::new("client", self.client),
+            KeyValue::new("operation", self.operation.clone()),
+            KeyValue::new("outcome", if error.is_some() { "error" } else { "ok" }),
+        ];
+        attributes.extend(self.attributes.iter().cloned());
+
+        instruments.duration.record(elapsed.as_secs_f64(), &attributes);
+        if let Some(error) = error {
+            attributes.push(KeyValue::new("error", error.to_string()));
+            instruments.errors.add(1, &attributes);
+        }
+    }
+}
+
+/// The installed exporters. Flushes and shuts them down when dropped
+#[derive(Debug, Default)]
+pub struct Telemetry {
+    meter_provider: Option<SdkMeterProvider>,
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(&mut self) {
+        if let Some(provider) = self.meter_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                log::warn!("Shutting down the meter provider failed: {}", e);
+            }
+        }
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.tracer_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                log::warn!("Shutting down the tracer provider failed: {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Exports spans and metrics to the configured OTLP collector. Without one, or without the `otlp` feature, nothing is
+/// installed and spans only reach the `log` output
+pub fn init(config: &TelemetryConfiguration) -> TelemetryResult<Telemetry> {
+    match &config.otlp_endpoint {
+        #[cfg(feature = "otlp")]
+        Some(endpoint) => otlp::init(config, endpoint.trim_end_matches('/')),
+        #[cfg(not(feature = "otlp"))]
+        Some(endpoint) => {
+            log::warn!("Not exporting to {}, the `otlp` feature is disabled", endpoint);
+            Ok(Telemetry::default())
+        }
+        None => Ok(Telemetry::default()),
+    }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::{KeyValue, trace::TracerProvider as _};
+    use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{
+        Resource,
+        metrics::{PeriodicReader, SdkMeterProvider},
+        runtime,
+        trace::TracerProvider,
+    };
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+    use super::{METER_NAME, Telemetry, set_meter_provider};
+    use crate::{
+        configuration::TelemetryConfiguration,
+        errors::{TelemetryError, TelemetryResult},
+    };
+
+    pub(super) fn init(config: &TelemetryConfiguration, endpoint: &str) -> TelemetryResult<Telemetry> {
+        let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+
+        let metrics = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/metrics", endpoint))
+            .build()
+            .map_err(|e| TelemetryError::Exporter("metric", e.to_string()))?;
+        let reader = PeriodicReader::builder(metrics, runtime::Tokio)
+            .with_interval(std::time::Duration::from_secs(config.export_interval_secs))
+            .build();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource.clone())
+            .build();
+        set_meter_provider(meter_provider.clone());
+
+        let spans = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint))
+            .build()
+            .map_err(|e| TelemetryError::Exporter("span", e.to_string()))?;
+        let tracer_provider = TracerProvider::builder()
+            .with_batch_exporter(spans, runtime::Tokio)
+            .with_resource(resource)
+            .build();
+        tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(METER_NAME)))
+            .try_init()
+            .map_err(|e| TelemetryError::Subscriber(e.to_string()))?;
+
+        log::info!("Exporting telemetry to {}", endpoint);
+        Ok(Telemetry {
+            meter_provider: Some(meter_provider),
+            tracer_provider: Some(tracer_provider),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{ApiError, Error};
+
+    #[test]
+    fn test_error_kind() {
+        let error = ApiError::GuardianResponse {
+            code: 401,
+            message: "Unauthorized".to_string(),
+            url: "http://guardian.test".to_string(),
+        };
+        assert_eq!(error_kind(&error), "GuardianResponse");
+        assert_eq!(error_kind(&ApiError::BadRequest), "BadRequest");
+        assert_eq!(error_kind(&Error::Api(ApiError::BadRequest)), "Api");
+    }
+
+    #[cfg(feature = "test-support")]
+    #[tokio::test]
+    async fn test_recorded_metrics() {
+        use std::sync::Arc;
+
+        use serde_json::json;
+
+        use crate::{
+            clients::{GuardianApiClient, MockTransport, RetrieverApi},
+            test_support::InMemoryTelemetry,
+        };
+
+        // The meter provider is global, so the hosts and the resource are unique to this test
+        let telemetry = InMemoryTelemetry::install();
+        let mut guardian = GuardianApiClient::new("http://metrics.guardian.test/api/v1").unwrap();
+        guardian.set_transport(Arc::new(MockTransport::new().on_json(
+            "GET",
+            "/metrics/ok",
+            200,
+            json!({}),
+        )));
+        guardian.get_raw("token", "/metrics/ok").await.unwrap();
+        assert!(guardian.get_raw("token", "/metrics/missing").await.is_err());
+
+        let calls = telemetry.find(
+            "demia.client.duration",
+            &[("client", "guardian"), ("operation", "GET"), ("resource", "metrics")],
+        );
+        assert_eq!(calls.iter().map(|metric| metric.count).sum::<u64>(), 2);
+        let errors = telemetry.find(
+            "demia.client.errors",
+            &[("client", "guardian"), ("resource", "metrics"), ("error", "NotFound")],
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].sum, 1.0);
+        // Not recorded a second time by the http client underneath
+        assert!(
+            telemetry
+                .find("demia.client.duration", &[("host", "metrics.guardian.test")])
+                .is_empty()
+        );
+
+        let mut retriever = RetrieverApi::new("http://metrics.retriever.test").unwrap();
+        retriever.set_transport(Arc::new(MockTransport::new().on_json(
+            "GET",
+            "/sync-status/user",
+            200,
+            json!({ "id": "user" }),
+        )));
+        retriever.sync_status("token", "user").await.unwrap();
+        let http = telemetry.find(
+            "demia.client.duration",
+            &[
+                ("client", "http"),
+                ("host", "metrics.retriever.test"),
+                ("outcome", "ok"),
+            ],
+        );
+        assert_eq!(http.len(), 1);
+        assert_eq!(http[0].count, 1);
+    }
+}
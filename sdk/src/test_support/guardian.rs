@@ -0,0 +1,634 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use iota_sdk::crypto::{
+    hashes::{Digest, sha::Sha256},
+    signatures::ed25519::SecretKey,
+};
+use serde_json::{Value, json};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{
+    clients::{GuardianApiClient, JcsCanonicalizer, ProofCanonicalizer},
+    identity_demia::core::BaseEncoding,
+};
+
+/// An account of the mock Guardian
+#[derive(Debug, Clone)]
+pub struct MockGuardianUser {
+    pub username: String,
+    pub password: String,
+    /// Guardian role, e.g. `STANDARD_REGISTRY` or `USER`
+    pub role: String,
+    pub did: String,
+    /// Served as the `document` of the profile's DID document, with the public key as `#did-root-key`
+    pub did_document: Value,
+    /// Ed25519 key of the DID
+    pub secret_key: [u8; SecretKey::LENGTH],
+}
+
+impl MockGuardianUser {
+    pub fn new(username: &str, password: &str, role: &str) -> Self {
+        let did = format!("did:hedera:testnet:{}_0.0.{}", username, rand::random::<u16>());
+        let key = SecretKey::generate().expect("Should generate a key");
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            role: role.to_string(),
+            did_document: json!({
+                "@context": "https://www.w3.org/ns/did/v1",
+                "id": did,
+                "verificationMethod": [{
+                    "id": format!("{}#did-root-key", did),
+                    "type": "Ed25519VerificationKey2018",
+                    "controller": did,
+                    "publicKeyMultibase": BaseEncoding::encode_multibase(key.public_key().as_slice(), None),
+                }],
+            }),
+            did,
+            secret_key: *key.to_bytes(),
+        }
+    }
+
+    /// Adds an Ed25519Signature2018 proof by the DID root key, canonicalized with JCS
+    pub fn sign(&self, mut credential: Value) -> Value {
+        let mut proof = json!({
+            "type": "Ed25519Signature2018",
+            "created": Utc::now(),
+            "verificationMethod": format!("{}#did-root-key", self.did),
+            "proofPurpose": "assertionMethod",
+            "@context": credential["@context"],
+        });
+        let canonical = |value: &Value| JcsCanonicalizer.canonicalize(value).expect("JCS never fails");
+        let mut data = Sha256::digest(canonical(&proof)).to_vec();
+        data.extend(Sha256::digest(canonical(&credential)));
+        let header = URL_SAFE_NO_PAD.encode(br#"{"alg":"EdDSA","b64":false,"crit":["b64"]}"#);
+        let mut signing_input = format!("{}.", header).into_bytes();
+        signing_input.extend(data);
+        let signature = SecretKey::from_bytes(&self.secret_key).sign(&signing_input);
+
+        proof.as_object_mut().unwrap().remove("@context");
+        proof["jws"] = json!(format!("{}..{}", header, URL_SAFE_NO_PAD.encode(signature.to_bytes())));
+        credential["proof"] = proof;
+        credential
+    }
+}
+
+/// A block of a mock policy. Documents posted to a block with `submits_to` are stored in the block with that tag
+#[derive(Debug, Clone, Default)]
+pub struct MockGuardianBlock {
+    pub id: String,
+    pub block_type: String,
+    pub tag: Option<String>,
+    /// Served as the block data
+    pub documents: Vec<Value>,
+    pub submits_to: Option<String>,
+    /// Trust chains of a `reportBlock` by hash, served after the hash is posted as `filterValue`
+    pub trust_chains: HashMap<String, Value>,
+    /// Hash posted last to a `reportBlock`
+    pub filter_value: Option<String>,
+    pub children: Vec<MockGuardianBlock>,
+}
+
+impl MockGuardianBlock {
+    pub fn new(block_type: &str, tag: &str) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            block_type: block_type.to_string(),
+            tag: Some(tag.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_document(mut self, document: Value) -> Self {
+        self.documents.push(document);
+        self
+    }
+
+    pub fn with_child(mut self, child: MockGuardianBlock) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn submits_to(mut self, tag: &str) -> Self {
+        self.submits_to = Some(tag.to_string());
+        self
+    }
+
+    pub fn with_trust_chain(mut self, hash: &str, chain: Value) -> Self {
+        self.trust_chains.insert(hash.to_string(), chain);
+        self
+    }
+
+    /// The block data, the selected trust chain for a `reportBlock`
+    fn data(&self) -> Value {
+        match self.block_type.as_str() {
+            "reportBlock" => self
+                .filter_value
+                .as_ref()
+                .and_then(|hash| self.trust_chains.get(hash))
+                .cloned()
+                .unwrap_or(Value::Null),
+            _ => json!(self.documents),
+        }
+    }
+
+    fn find(&self, predicate: &dyn Fn(&MockGuardianBlock) -> bool) -> Option<&MockGuardianBlock> {
+        if predicate(self) {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(predicate))
+    }
+
+    fn find_mut(&mut self, predicate: &dyn Fn(&MockGuardianBlock) -> bool) -> Option<&mut MockGuardianBlock> {
+        if predicate(self) {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|child| child.find_mut(predicate))
+    }
+
+    fn tree(&self) -> Value {
+        json!({
+            "id": self.id,
+            "blockType": self.block_type,
+            "tag": self.tag,
+            "children": self.children.iter().map(MockGuardianBlock::tree).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockGuardianPolicy {
+    pub id: String,
+    pub name: String,
+    pub root: MockGuardianBlock,
+}
+
+impl MockGuardianPolicy {
+    pub fn new(name: &str, root: MockGuardianBlock) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            root,
+        }
+    }
+
+    /// A policy for [`GuardianClient::send_report`](crate::clients::GuardianClient::send_report). The block tagged
+    /// `send` serves a `project_sr` document and stores the submitted reports in the block tagged `reports`. The
+    /// `reportBlock` tagged `trust_chain` serves the chains added with [`MockGuardian::add_trust_chain`]
+    pub fn monitoring_report() -> Self {
+        let send = MockGuardianBlock::new("interfaceDocumentsSourceBlock", "send")
+            .with_document(json!({ "id": "project", "type": "project_sr" }))
+            .submits_to("reports");
+        let reports = MockGuardianBlock::new("interfaceDocumentsSourceBlock", "reports");
+        let trust_chain = MockGuardianBlock::new("reportBlock", "trust_chain");
+        Self::new(
+            "monitoring_report",
+            MockGuardianBlock::new("interfaceContainerBlock", "root")
+                .with_child(send)
+                .with_child(reports)
+                .with_child(trust_chain),
+        )
+    }
+
+    fn json(&self, with_config: bool) -> Value {
+        let mut policy = json!({
+            "id": self.id,
+            "name": self.name,
+            "status": "PUBLISH",
+            "version": "1.0.0",
+            "createDate": Utc::now(),
+        });
+        if with_config {
+            policy["config"] = self.root.tree();
+        }
+        policy
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MockGuardianConfig {
+    pub users: Vec<MockGuardianUser>,
+    pub policies: Vec<MockGuardianPolicy>,
+}
+
+/// A body posted to a block
+#[derive(Debug, Clone)]
+pub struct MockGuardianPost {
+    pub policy_id: String,
+    pub block_id: String,
+    pub username: String,
+    pub body: Value,
+}
+
+#[derive(Default)]
+struct GuardianState {
+    users: Vec<MockGuardianUser>,
+    policies: Mutex<Vec<MockGuardianPolicy>>,
+    refresh_tokens: Mutex<HashMap<String, String>>,
+    access_tokens: Mutex<HashMap<String, String>>,
+    posts: Mutex<Vec<MockGuardianPost>>,
+    /// Status the next post is answered with, and whether it is stored anyway
+    post_failure: Mutex<Option<(StatusCode, bool)>>,
+}
+
+/// A Guardian API on localhost holding its policies in memory.
+///
+/// Serves login, access tokens, profiles, policies, tag lookups and block reads and posts below `/api/v1`, so a
+/// [`GuardianApiClient`] can be pointed at [`MockGuardian::api_url`]. Every post is recorded for assertions.
+/// The server stops when the mock is dropped.
+pub struct MockGuardian {
+    url: String,
+    state: Arc<GuardianState>,
+    server: JoinHandle<()>,
+}
+
+impl MockGuardian {
+    pub async fn start(config: MockGuardianConfig) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+
+        let state = Arc::new(GuardianState {
+            users: config.users,
+            policies: Mutex::new(config.policies),
+            ..Default::default()
+        });
+
+        let router = Router::new()
+            .route("/api/v1/accounts/login", post(login))
+            .route("/api/v1/accounts/access-token", post(access_token))
+            .route("/api/v1/profiles/:username", get(profile))
+            .route("/api/v1/policies", get(policies))
+            .route("/api/v1/policies/:policy", get(policy))
+            .route("/api/v1/policies/:policy/blocks", get(blocks))
+            .route("/api/v1/policies/:policy/tag/:tag", get(tag))
+            .route(
+                "/api/v1/policies/:policy/blocks/:block",
+                get(block_data).post(post_block),
+            )
+            .with_state(state.clone());
+
+        let server = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                log::warn!("Mock Guardian stopped: {}", e);
+            }
+        });
+
+        Ok(Self { url, state, server })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Url to build a [`GuardianApiClient`] from, usable as `ApplicationConfiguration::guardian_api`
+    pub fn api_url(&self) -> String {
+        format!("{}/api/v1", self.url)
+    }
+
+    pub fn api_client(&self) -> GuardianApiClient {
+        GuardianApiClient::new(self.api_url().as_str()).expect("Mock url is valid")
+    }
+
+    pub fn policy(&self, name: &str) -> Option<MockGuardianPolicy> {
+        self.state
+            .policies
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|policy| policy.name == name)
+            .cloned()
+    }
+
+    /// Adds a document to the data of the tagged block
+    pub fn add_document(&self, policy_id: &str, tag: &str, document: Value) -> bool {
+        let mut policies = self.state.policies.lock().unwrap();
+        let block = policies
+            .iter_mut()
+            .find(|policy| policy.id == policy_id)
+            .and_then(|policy| policy.root.find_mut(&|block| block.tag.as_deref() == Some(tag)));
+        match block {
+            Some(block) => {
+                block.documents.push(document);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds a trust chain to the tagged `reportBlock`, served once `hash` is posted as its filter
+    pub fn add_trust_chain(&self, policy_id: &str, tag: &str, hash: &str, chain: Value) -> bool {
+        let mut policies = self.state.policies.lock().unwrap();
+        let block = policies
+            .iter_mut()
+            .find(|policy| policy.id == policy_id)
+            .and_then(|policy| policy.root.find_mut(&|block| block.tag.as_deref() == Some(tag)));
+        match block {
+            Some(block) => {
+                block.trust_chains.insert(hash.to_string(), chain);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn user(&self, username: &str) -> Option<MockGuardianUser> {
+        self.state.users.iter().find(|user| user.username == username).cloned()
+    }
+
+    /// Every body posted to a block, in order
+    pub fn posts(&self) -> Vec<MockGuardianPost> {
+        self.state.posts.lock().unwrap().clone()
+    }
+
+    /// Stores the next post but answers it with `status`, like a gateway that lost Guardian's response
+    pub fn fail_next_post_after_accepting(&self, status: StatusCode) {
+        self.state.post_failure.lock().unwrap().replace((status, true));
+    }
+
+    /// Answers the next post with `status` without storing it
+    pub fn reject_next_post(&self, status: StatusCode) {
+        self.state.post_failure.lock().unwrap().replace((status, false));
+    }
+
+    /// Invalidates every access token, so clients have to renew them
+    pub fn expire_access_tokens(&self) {
+        self.state.access_tokens.lock().unwrap().clear();
+    }
+}
+
+impl Drop for MockGuardian {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (
+        status,
+        Json(json!({ "statusCode": status.as_u16(), "message": message })),
+    )
+        .into_response()
+}
+
+impl GuardianState {
+    /// The user of the bearer token
+    fn authorize(&self, headers: &HeaderMap) -> Result<MockGuardianUser, Response> {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "Unauthorized"))?;
+        let username = self
+            .access_tokens
+            .lock()
+            .unwrap()
+            .get(token)
+            .cloned()
+            .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "Unauthorized"))?;
+        self.users
+            .iter()
+            .find(|user| user.username == username)
+            .cloned()
+            .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "Unauthorized"))
+    }
+
+    fn with_policy<T>(&self, policy_id: &str, f: impl FnOnce(&mut MockGuardianPolicy) -> T) -> Result<T, Response> {
+        let mut policies = self.policies.lock().unwrap();
+        policies
+            .iter_mut()
+            .find(|policy| policy.id == policy_id)
+            .map(f)
+            .ok_or_else(|| error(StatusCode::NOT_FOUND, "Policy does not exist"))
+    }
+}
+
+async fn login(State(state): State<Arc<GuardianState>>, Json(body): Json<Value>) -> Response {
+    let field = |name: &str| body.get(name).and_then(Value::as_str).unwrap_or_default();
+    let Some(user) = state
+        .users
+        .iter()
+        .find(|user| user.username == field("username") && user.password == field("password"))
+    else {
+        return error(StatusCode::UNAUTHORIZED, "Unauthorized request");
+    };
+
+    let refresh_token = uuid::Uuid::new_v4().to_string();
+    state
+        .refresh_tokens
+        .lock()
+        .unwrap()
+        .insert(refresh_token.clone(), user.username.clone());
+    Json(json!({
+        "username": user.username,
+        "did": user.did,
+        "role": user.role,
+        "refreshToken": refresh_token,
+    }))
+    .into_response()
+}
+
+async fn access_token(State(state): State<Arc<GuardianState>>, Json(body): Json<Value>) -> Response {
+    let refresh_token = body.get("refreshToken").and_then(Value::as_str).unwrap_or_default();
+    let Some(username) = state.refresh_tokens.lock().unwrap().get(refresh_token).cloned() else {
+        return error(StatusCode::UNAUTHORIZED, "Unauthorized request");
+    };
+
+    let access_token = uuid::Uuid::new_v4().to_string();
+    state
+        .access_tokens
+        .lock()
+        .unwrap()
+        .insert(access_token.clone(), username);
+    Json(json!({ "accessToken": access_token })).into_response()
+}
+
+async fn profile(
+    State(state): State<Arc<GuardianState>>,
+    headers: HeaderMap,
+    Path(username): Path<String>,
+) -> Response {
+    if let Err(response) = state.authorize(&headers) {
+        return response;
+    }
+    let Some(user) = state.users.iter().find(|user| user.username == username) else {
+        return error(StatusCode::NOT_FOUND, "User does not exist");
+    };
+
+    let now = Utc::now();
+    Json(json!({
+        "username": user.username,
+        "role": user.role,
+        "did": user.did,
+        "parent": "",
+        "hederaAccountId": "0.0.1",
+        "confirmed": true,
+        "failed": false,
+        "hederaAccountKey": null,
+        "topicId": "0.0.2",
+        "parentTopicId": "0.0.2",
+        "didDocument": {
+            "createDate": now,
+            "did": user.did,
+            "document": user.did_document,
+            "id": user.did,
+            "messageId": "",
+            "status": "CREATE",
+            "topicId": "0.0.2",
+            "updateDate": now,
+            "verificationMethods": {},
+            "_id": user.did,
+        },
+        "vcDocument": null,
+    }))
+    .into_response()
+}
+
+async fn policies(State(state): State<Arc<GuardianState>>, headers: HeaderMap) -> Response {
+    if let Err(response) = state.authorize(&headers) {
+        return response;
+    }
+    let policies = state.policies.lock().unwrap();
+    Json(policies.iter().map(|policy| policy.json(false)).collect::<Vec<_>>()).into_response()
+}
+
+async fn policy(
+    State(state): State<Arc<GuardianState>>,
+    headers: HeaderMap,
+    Path(policy_id): Path<String>,
+) -> Response {
+    if let Err(response) = state.authorize(&headers) {
+        return response;
+    }
+    match state.with_policy(&policy_id, |policy| policy.json(true)) {
+        Ok(policy) => Json(policy).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn blocks(
+    State(state): State<Arc<GuardianState>>,
+    headers: HeaderMap,
+    Path(policy_id): Path<String>,
+) -> Response {
+    if let Err(response) = state.authorize(&headers) {
+        return response;
+    }
+    match state.with_policy(&policy_id, |policy| policy.root.tree()) {
+        Ok(tree) => Json(tree).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn tag(
+    State(state): State<Arc<GuardianState>>,
+    headers: HeaderMap,
+    Path((policy_id, tag)): Path<(String, String)>,
+) -> Response {
+    if let Err(response) = state.authorize(&headers) {
+        return response;
+    }
+    let id = state.with_policy(&policy_id, |policy| {
+        policy
+            .root
+            .find(&|block| block.tag.as_deref() == Some(tag.as_str()))
+            .map(|block| block.id.clone())
+    });
+    match id {
+        Ok(Some(id)) => Json(json!({ "id": id })).into_response(),
+        Ok(None) => error(StatusCode::NOT_FOUND, "Block does not exist"),
+        Err(response) => response,
+    }
+}
+
+async fn block_data(
+    State(state): State<Arc<GuardianState>>,
+    headers: HeaderMap,
+    Path((policy_id, block_id)): Path<(String, String)>,
+) -> Response {
+    if let Err(response) = state.authorize(&headers) {
+        return response;
+    }
+    let data = state.with_policy(&policy_id, |policy| {
+        policy.root.find(&|block| block.id == block_id).map(|block| {
+            json!({
+                "id": block.id,
+                "blockType": block.block_type,
+                "data": block.data(),
+            })
+        })
+    });
+    match data {
+        Ok(Some(data)) => Json(data).into_response(),
+        Ok(None) => error(StatusCode::NOT_FOUND, "Block does not exist"),
+        Err(response) => response,
+    }
+}
+
+async fn post_block(
+    State(state): State<Arc<GuardianState>>,
+    headers: HeaderMap,
+    Path((policy_id, block_id)): Path<(String, String)>,
+    Json(body): Json<Value>,
+) -> Response {
+    let user = match state.authorize(&headers) {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let failure = state.post_failure.lock().unwrap().take();
+    if let Some((status, false)) = failure {
+        return error(status, "Rejected");
+    }
+
+    let stored = state.with_policy(&policy_id, |policy| {
+        let block = policy.root.find_mut(&|block| block.id == block_id)?;
+        if let Some(hash) = body.get("filterValue").and_then(Value::as_str) {
+            block.filter_value = Some(hash.to_string());
+        }
+        let block = block.clone();
+        if let (Some(target), Some(document)) = (&block.submits_to, body.get("document")) {
+            let stored = json!({
+                "id": uuid::Uuid::new_v4().to_string(),
+                "type": block.tag,
+                "owner": user.did,
+                "policyId": policy.id,
+                "createDate": Utc::now(),
+                "document": { "issuer": user.did, "credentialSubject": [document] },
+            });
+            if let Some(target) = policy
+                .root
+                .find_mut(&|candidate| candidate.tag.as_deref() == Some(target.as_str()))
+            {
+                target.documents.push(stored);
+            }
+        }
+        Some(())
+    });
+    match stored {
+        Ok(Some(())) => {}
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Block does not exist"),
+        Err(response) => return response,
+    }
+
+    state.posts.lock().unwrap().push(MockGuardianPost {
+        policy_id,
+        block_id,
+        username: user.username,
+        body,
+    });
+    match failure {
+        Some((status, _)) => error(status, "Response lost"),
+        None => Json(json!(true)).into_response(),
+    }
+}
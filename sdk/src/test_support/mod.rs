@@ -0,0 +1,11 @@
+//! Local stand-ins for the hosted services the SDK clients talk to. Only compiled with the `test-support` feature.
+
+mod guardian;
+mod oidc;
+mod telemetry;
+
+pub use guardian::{
+    MockGuardian, MockGuardianBlock, MockGuardianConfig, MockGuardianPolicy, MockGuardianPost, MockGuardianUser,
+};
+pub use oidc::{MockClaims, MockKeyAlgorithm, MockOidcConfig, MockOidcIssuer, MockUser};
+pub use telemetry::{InMemoryTelemetry, RecordedMetric};
@@ -0,0 +1,529 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Form, Json, Router,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::{
+    RsaPrivateKey,
+    pkcs8::{EncodePrivateKey, LineEnding},
+    traits::PublicKeyParts,
+};
+use serde_json::{Map, Value, json};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{
+    clients::{Auth0Client, Keycloak},
+    configuration::ApplicationConfiguration,
+    models::{TokenResponse, TokenType},
+    utils::TOKEN_EXCHANGE_GRANT,
+};
+
+const KEY_ID: &str = "demia-mock-key";
+
+/// Signing algorithm of the issuer key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MockKeyAlgorithm {
+    #[default]
+    Rs256,
+    Es256,
+}
+
+impl MockKeyAlgorithm {
+    fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::Rs256 => Algorithm::RS256,
+            Self::Es256 => Algorithm::ES256,
+        }
+    }
+}
+
+/// A user that can log in against the mock issuer with the password grant
+#[derive(Debug, Clone)]
+pub struct MockUser {
+    pub username: String,
+    pub password: String,
+    pub sub: String,
+    pub email: String,
+    pub nickname: String,
+}
+
+impl MockUser {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            sub: uuid::Uuid::new_v4().to_string(),
+            email: format!("{}@demia.test", username),
+            nickname: username.to_string(),
+        }
+    }
+}
+
+/// Claims placed into a minted token
+#[derive(Debug, Clone, Default)]
+pub struct MockClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    pub nickname: Option<String>,
+    /// Expiry in seconds since the epoch, defaults to now + the issuer token lifetime
+    pub exp: Option<u64>,
+    /// Overrides the issuer audiences when not empty
+    pub audiences: Vec<String>,
+    /// Additional claims such as roles or namespaced claims
+    pub extra: Map<String, Value>,
+}
+
+impl From<&MockUser> for MockClaims {
+    fn from(user: &MockUser) -> Self {
+        Self {
+            sub: user.sub.clone(),
+            email: Some(user.email.clone()),
+            nickname: Some(user.nickname.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockOidcConfig {
+    pub users: Vec<MockUser>,
+    /// Audiences added to every token that doesn't override them
+    pub audiences: Vec<String>,
+    pub token_lifetime: Duration,
+    pub algorithm: MockKeyAlgorithm,
+    /// `kid` of the key in the JWKS and in token headers
+    pub key_id: String,
+    /// Size of RSA keys
+    pub key_bits: usize,
+}
+
+impl Default for MockOidcConfig {
+    fn default() -> Self {
+        Self {
+            users: Vec::new(),
+            audiences: [
+                TokenType::AWS,
+                TokenType::AUTH0,
+                TokenType::Auth0Admin,
+                TokenType::VAULT,
+            ]
+            .iter()
+            .map(|t| t.client_id().to_string())
+            .collect(),
+            token_lifetime: Duration::from_secs(300),
+            algorithm: MockKeyAlgorithm::default(),
+            key_id: KEY_ID.to_string(),
+            key_bits: 2048,
+        }
+    }
+}
+
+struct IssuerState {
+    issuer: String,
+    config: MockOidcConfig,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    jwks: Value,
+    refresh_tokens: Mutex<HashMap<String, MockClaims>>,
+    /// Grant type of every token request
+    grants: Mutex<Vec<String>>,
+}
+
+/// An OIDC issuer serving JWKS and token endpoints on localhost.
+///
+/// Both the Keycloak (`/protocol/openid-connect/*`) and Auth0 (`/oauth/token`, `/.well-known/jwks.json`) routes are
+/// served, so [`Keycloak`], [`Auth0Client`] and a `TokenManager` wrapping either can be pointed at
+/// [`MockOidcIssuer::url`]. A Vault dev server can use [`MockOidcIssuer::jwks_url`] for its jwt auth mounts.
+/// The server stops when the issuer is dropped.
+pub struct MockOidcIssuer {
+    url: String,
+    state: Arc<IssuerState>,
+    server: JoinHandle<()>,
+}
+
+impl MockOidcIssuer {
+    /// Generates a new keypair and self-signed certificate and starts serving on a random local port
+    pub async fn start(config: MockOidcConfig) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+
+        let (encoding_key, decoding_key, jwks) = match config.algorithm {
+            MockKeyAlgorithm::Rs256 => rsa_key(&config.key_id, config.key_bits)?,
+            MockKeyAlgorithm::Es256 => ec_key(&config.key_id)?,
+        };
+
+        let state = Arc::new(IssuerState {
+            issuer: url.clone(),
+            config,
+            encoding_key,
+            decoding_key,
+            jwks,
+            refresh_tokens: Mutex::new(HashMap::new()),
+            grants: Mutex::new(Vec::new()),
+        });
+
+        let router = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/.well-known/jwks.json", get(jwks))
+            .route("/protocol/openid-connect/certs", get(jwks))
+            .route("/protocol/openid-connect/token", post(token))
+            .route("/oauth/token", post(token))
+            .with_state(state.clone());
+
+        let server = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                log::warn!("Mock OIDC issuer stopped: {}", e);
+            }
+        });
+
+        Ok(Self { url, state, server })
+    }
+
+    /// Base url of the issuer, usable as `ApplicationConfiguration::secrets_api`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn jwks_url(&self) -> String {
+        format!("{}/protocol/openid-connect/certs", self.url)
+    }
+
+    pub fn jwks(&self) -> &Value {
+        &self.state.jwks
+    }
+
+    pub fn application_config(&self) -> ApplicationConfiguration {
+        ApplicationConfiguration {
+            secrets_api: self.url.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn keycloak(&self) -> Keycloak {
+        Keycloak::new(&self.application_config())
+    }
+
+    pub fn auth0(&self) -> Auth0Client {
+        Auth0Client::new(&self.application_config())
+    }
+
+    /// Signs a token with the issuer key, for paths that start from a raw bearer string
+    pub fn mint_token(&self, claims: &MockClaims) -> String {
+        self.state.mint(claims)
+    }
+
+    /// How many token requests used the grant type, e.g. `refresh_token`
+    pub fn grant_count(&self, grant_type: &str) -> usize {
+        self.state
+            .grants
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|grant| grant.as_str() == grant_type)
+            .count()
+    }
+
+    /// Mints an access/id token pair plus a refresh token the token endpoint will accept
+    pub fn token_response(&self, claims: &MockClaims) -> TokenResponse {
+        self.state.token_response(claims)
+    }
+}
+
+/// Keys and JWKS of a new RSA keypair. The JWKS carries the modulus and exponent as well as an x5c certificate
+fn rsa_key(key_id: &str, bits: usize) -> std::io::Result<(EncodingKey, DecodingKey, Value)> {
+    let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), bits).map_err(io_error)?;
+    let private_pem = private_key.to_pkcs8_pem(LineEnding::LF).map_err(io_error)?;
+    let encoding_key = EncodingKey::from_rsa_pem(private_pem.as_bytes()).map_err(io_error)?;
+    let key_pair = rcgen::KeyPair::from_pem(&private_pem).map_err(io_error)?;
+
+    let url_safe = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let modulus = url_safe.encode(private_key.n().to_bytes_be());
+    let exponent = url_safe.encode(private_key.e().to_bytes_be());
+    let decoding_key = DecodingKey::from_rsa_components(&modulus, &exponent).map_err(io_error)?;
+    let jwks = json!({
+        "keys": [{
+            "kid": key_id,
+            "kty": "RSA",
+            "alg": "RS256",
+            "use": "sig",
+            "n": modulus,
+            "e": exponent,
+            "x5c": [certificate(&key_pair)?],
+        }]
+    });
+    Ok((encoding_key, decoding_key, jwks))
+}
+
+/// Keys and JWKS of a new P-256 keypair
+fn ec_key(key_id: &str) -> std::io::Result<(EncodingKey, DecodingKey, Value)> {
+    let key_pair = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).map_err(io_error)?;
+    let encoding_key = EncodingKey::from_ec_pem(key_pair.serialize_pem().as_bytes()).map_err(io_error)?;
+
+    // Uncompressed point, 0x04 followed by the x and y coordinates
+    let point = key_pair.public_key_raw();
+    let url_safe = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let x = url_safe.encode(&point[1..33]);
+    let y = url_safe.encode(&point[33..65]);
+    let decoding_key = DecodingKey::from_ec_components(&x, &y).map_err(io_error)?;
+    let jwks = json!({
+        "keys": [{
+            "kid": key_id,
+            "kty": "EC",
+            "alg": "ES256",
+            "use": "sig",
+            "crv": "P-256",
+            "x": x,
+            "y": y,
+            "x5c": [certificate(&key_pair)?],
+        }]
+    });
+    Ok((encoding_key, decoding_key, jwks))
+}
+
+/// A base64 self-signed certificate of the key, for clients reading the key from x5c
+fn certificate(key_pair: &rcgen::KeyPair) -> std::io::Result<String> {
+    let certificate = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+        .and_then(|params| params.self_signed(key_pair))
+        .map_err(io_error)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(&**certificate.der()))
+}
+
+impl Drop for MockOidcIssuer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl IssuerState {
+    fn mint(&self, claims: &MockClaims) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let audiences = match claims.audiences.is_empty() {
+            true => &self.config.audiences,
+            false => &claims.audiences,
+        };
+
+        let mut body = claims.extra.clone();
+        body.insert("iss".to_string(), json!(self.issuer));
+        body.insert("sub".to_string(), json!(claims.sub));
+        body.insert("aud".to_string(), json!(audiences));
+        body.insert("iat".to_string(), json!(now));
+        body.insert(
+            "exp".to_string(),
+            json!(claims.exp.unwrap_or(now + self.config.token_lifetime.as_secs())),
+        );
+        if let Some(email) = &claims.email {
+            body.insert("email".to_string(), json!(email));
+        }
+        if let Some(nickname) = &claims.nickname {
+            body.insert("nickname".to_string(), json!(nickname));
+            body.insert("preferred_username".to_string(), json!(nickname));
+        }
+
+        let mut header = Header::new(self.config.algorithm.algorithm());
+        header.kid = Some(self.config.key_id.clone());
+        jsonwebtoken::encode(&header, &Value::Object(body), &self.encoding_key)
+            .expect("Mock claims are plain json and should always encode")
+    }
+
+    /// Re-issues the claims of a token minted by this issuer for another audience
+    fn exchange(&self, subject_token: &str, audience: &str) -> Option<MockClaims> {
+        let mut validation = Validation::new(self.config.algorithm.algorithm());
+        validation.validate_aud = false;
+        let subject = jsonwebtoken::decode::<Value>(subject_token, &self.decoding_key, &validation).ok()?;
+
+        let claim = |name: &str| subject.claims.get(name).and_then(Value::as_str).map(str::to_string);
+        Some(MockClaims {
+            sub: claim("sub")?,
+            email: claim("email"),
+            nickname: claim("nickname"),
+            audiences: vec![audience.to_string()],
+            ..Default::default()
+        })
+    }
+
+    fn token_response(&self, claims: &MockClaims) -> TokenResponse {
+        let token = self.mint(claims);
+        let refresh_token = uuid::Uuid::new_v4().to_string();
+        self.refresh_tokens
+            .lock()
+            .unwrap()
+            .insert(refresh_token.clone(), claims.clone());
+
+        TokenResponse {
+            access_token: token.clone(),
+            id_token: token,
+            refresh_token,
+        }
+    }
+}
+
+async fn discovery(State(state): State<Arc<IssuerState>>) -> Json<Value> {
+    Json(json!({
+        "issuer": state.issuer,
+        "jwks_uri": format!("{}/protocol/openid-connect/certs", state.issuer),
+        "token_endpoint": format!("{}/protocol/openid-connect/token", state.issuer),
+        "id_token_signing_alg_values_supported": [format!("{:?}", state.config.algorithm.algorithm())],
+        "grant_types_supported": ["password", "client_credentials", "refresh_token", TOKEN_EXCHANGE_GRANT],
+    }))
+}
+
+async fn jwks(State(state): State<Arc<IssuerState>>) -> Json<Value> {
+    Json(state.jwks.clone())
+}
+
+async fn token(State(state): State<Arc<IssuerState>>, Form(params): Form<HashMap<String, String>>) -> Response {
+    let param = |name: &str| params.get(name).map(String::as_str).unwrap_or_default();
+    state.grants.lock().unwrap().push(param("grant_type").to_string());
+
+    let claims = match param("grant_type") {
+        "password" => state
+            .config
+            .users
+            .iter()
+            .find(|user| user.username == param("username") && user.password == param("password"))
+            .map(MockClaims::from),
+        "client_credentials" if !param("client_id").is_empty() => Some(MockClaims {
+            sub: format!("service-account-{}", param("client_id")),
+            audiences: params.get("audience").into_iter().cloned().collect(),
+            ..Default::default()
+        }),
+        "refresh_token" => state.refresh_tokens.lock().unwrap().remove(param("refresh_token")),
+        TOKEN_EXCHANGE_GRANT => state.exchange(param("subject_token"), param("audience")),
+        grant => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "unsupported_grant_type", "error_description": grant })),
+            )
+                .into_response();
+        }
+    };
+
+    match claims {
+        Some(claims) => Json(state.token_response(&claims)).into_response(),
+        None => (StatusCode::UNAUTHORIZED, Json(json!({ "error": "invalid_grant" }))).into_response(),
+    }
+}
+
+fn io_error<E: std::fmt::Display>(error: E) -> std::io::Error {
+    std::io::Error::other(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::jwk::JwkSet;
+
+    use super::*;
+    use crate::clients::SecretManager;
+
+    async fn issuer(algorithm: MockKeyAlgorithm) -> (MockOidcIssuer, MockUser) {
+        let user = MockUser::new("alice", "correct horse");
+        let issuer = MockOidcIssuer::start(MockOidcConfig {
+            users: vec![user.clone()],
+            algorithm,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        (issuer, user)
+    }
+
+    #[tokio::test]
+    async fn test_keycloak_login() {
+        let (issuer, user) = issuer(MockKeyAlgorithm::Rs256).await;
+        let mut keycloak = issuer.keycloak();
+
+        let token = keycloak
+            .get_token(&TokenType::VAULT, &user.username, &user.password)
+            .await
+            .unwrap();
+        assert_eq!(token.get_sub(), Some(user.sub.clone()));
+        assert!(!token.is_expired());
+
+        let refreshed = keycloak.refresh_token().await.unwrap();
+        assert_eq!(refreshed.get_sub(), Some(user.sub));
+    }
+
+    #[tokio::test]
+    async fn test_auth0_login() {
+        let (issuer, user) = issuer(MockKeyAlgorithm::Rs256).await;
+        let mut auth0 = issuer.auth0();
+
+        let token = auth0
+            .get_token(&TokenType::AUTH0, &user.username, &user.password)
+            .await
+            .unwrap();
+        assert_eq!(token.get_sub(), Some(user.sub.clone()));
+        assert_eq!(token.token_type(), &TokenType::AUTH0);
+
+        assert!(
+            auth0
+                .get_token(&TokenType::AUTH0, &user.username, "wrong")
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keycloak_exchange() {
+        let (issuer, user) = issuer(MockKeyAlgorithm::Rs256).await;
+        let mut keycloak = issuer.keycloak();
+        let subject = keycloak
+            .get_token(&TokenType::VAULT, &user.username, &user.password)
+            .await
+            .unwrap();
+        let refresh = keycloak.session_refresh();
+
+        let exchanged = keycloak.exchange_token(&subject, &TokenType::AWS).await.unwrap();
+        assert_eq!(exchanged.token_type(), &TokenType::AWS);
+        assert_eq!(exchanged.get_sub(), Some(user.sub));
+        assert_eq!(
+            exchanged.token_data().claims["aud"],
+            json!([TokenType::AWS.client_id()])
+        );
+        // The session stays on the refresh token of the login
+        assert_eq!(keycloak.session_refresh(), refresh);
+    }
+
+    #[tokio::test]
+    async fn test_auth0_exchange() {
+        let (issuer, user) = issuer(MockKeyAlgorithm::Rs256).await;
+        let mut auth0 = issuer.auth0();
+        let subject = auth0
+            .get_token(&TokenType::AUTH0, &user.username, &user.password)
+            .await
+            .unwrap();
+
+        // The exchanged token is only issued for the requested audience, not the login client
+        let exchanged = auth0.exchange_token(&subject, &TokenType::Auth0Admin).await.unwrap();
+        assert_eq!(exchanged.token_type(), &TokenType::Auth0Admin);
+        assert_eq!(exchanged.get_sub(), Some(user.sub));
+        assert_eq!(
+            exchanged.token_data().claims["aud"],
+            json!([TokenType::Auth0Admin.client_id()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ec_jwks() {
+        let (issuer, user) = issuer(MockKeyAlgorithm::Es256).await;
+        let token = issuer.mint_token(&MockClaims::from(&user));
+
+        let jwks: JwkSet = reqwest::get(issuer.jwks_url()).await.unwrap().json().await.unwrap();
+        let key = DecodingKey::from_jwk(&jwks.keys[0]).unwrap();
+        let mut validation = Validation::new(Algorithm::ES256);
+        validation.validate_aud = false;
+        let data = jsonwebtoken::decode::<Value>(&token, &key, &validation).unwrap();
+        assert_eq!(data.header.kid.as_deref(), Some(KEY_ID));
+        assert_eq!(data.claims["sub"], json!(user.sub));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use opentelemetry_sdk::{
+    metrics::{
+        PeriodicReader, SdkMeterProvider,
+        data::{Histogram, Sum},
+    },
+    runtime,
+    testing::metrics::InMemoryMetricsExporter,
+};
+
+use crate::telemetry;
+
+/// A data point of a metric. Histograms are reduced to their count and sum, counters have a count of 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMetric {
+    pub name: String,
+    pub attributes: BTreeMap<String, String>,
+    pub count: u64,
+    pub sum: f64,
+}
+
+/// Collects the SDK's metrics in process.
+///
+/// Installing it replaces the global meter provider, so tests asserting on metrics should not run concurrently.
+pub struct InMemoryTelemetry {
+    exporter: InMemoryMetricsExporter,
+    provider: SdkMeterProvider,
+}
+
+impl InMemoryTelemetry {
+    pub fn install() -> Self {
+        let exporter = InMemoryMetricsExporter::default();
+        // Exports from its own thread, so flushing works from a current thread runtime as well
+        let reader = PeriodicReader::builder(exporter.clone(), runtime::TokioCurrentThread).build();
+        let provider = SdkMeterProvider::builder().with_reader(reader).build();
+        telemetry::set_meter_provider(provider.clone());
+        Self { exporter, provider }
+    }
+
+    /// Every data point recorded so far
+    pub fn metrics(&self) -> Vec<RecordedMetric> {
+        if let Err(e) = self.provider.force_flush() {
+            log::warn!("Flushing metrics failed: {}", e);
+        }
+        // Temporality is cumulative, the last export holds the totals
+        let exported = self.exporter.get_finished_metrics().unwrap_or_default();
+        let Some(resource_metrics) = exported.last() else {
+            return Vec::new();
+        };
+
+        let mut recorded = Vec::new();
+        for metric in resource_metrics.scope_metrics.iter().flat_map(|scope| &scope.metrics) {
+            let data = metric.data.as_any();
+            let name = metric.name.to_string();
+            if let Some(histogram) = data.downcast_ref::<Histogram<f64>>() {
+                recorded.extend(histogram.data_points.iter().map(|point| RecordedMetric {
+                    name: name.clone(),
+                    attributes: attributes(&point.attributes),
+                    count: point.count,
+                    sum: point.sum,
+                }));
+            } else if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+                recorded.extend(sum.data_points.iter().map(|point| RecordedMetric {
+                    name: name.clone(),
+                    attributes: attributes(&point.attributes),
+                    count: 1,
+                    sum: point.value as f64,
+                }));
+            }
+        }
+        recorded
+    }
+
+    /// Data points of the metric having all the given attributes
+    pub fn find(&self, name: &str, attributes: &[(&str, &str)]) -> Vec<RecordedMetric> {
+        self.metrics()
+            .into_iter()
+            .filter(|metric| metric.name == name)
+            .filter(|metric| {
+                attributes
+                    .iter()
+                    .all(|(key, value)| metric.attributes.get(*key).map(String::as_str) == Some(*value))
+            })
+            .collect()
+    }
+}
+
+fn attributes(attributes: &[opentelemetry::KeyValue]) -> BTreeMap<String, String> {
+    attributes
+        .iter()
+        .map(|kv| (kv.key.to_string(), kv.value.to_string()))
+        .collect()
+}
@@ -6,6 +6,7 @@ pub const STRONGHOLD_KEY_HEDERA_USERNAME: &str = "HEDERA_USERNAME";
 pub const STRONGHOLD_DOC_KEYS: &str = "streams_doc_keys";
 pub const STRONGHOLD_SIG_KEYS: &str = "streams_sig_keys";
 pub const STRONGHOLD_KE_KEYS: &str = "streams_ke_keys";
+pub const STRONGHOLD_KEY_TOKEN_SESSION: &str = "token_session";
 
 // Identity fragments
 pub const DID_FRAGMENT_HEDERA_DID: &str = "hedera-did";
@@ -15,9 +16,41 @@ pub const LOCAL_API: &str = "http://localhost:1111";
 pub const RETRIEVER_API: &str = "http://localhost:9000";
 pub const GUARDIAN_API: &str = "http://guardian.demia-nodes.net/api/v1";
 pub const SECRETS_API: &str = "https://auth.demia-testing-domain.com/realms/DemiaTest";
+pub const VAULT_API: &str = "http://35.230.109.16:8200";
+
+// Vault mounts
+pub const VAULT_KV_MOUNT: &str = "stronghold";
+pub const VAULT_JWT_MOUNT: &str = "jwt";
+pub const VAULT_JWT_ROLE: &str = "default";
+pub const VAULT_EXTERNAL_JWT_MOUNT: &str = "jwt2";
+pub const VAULT_APPROLE_MOUNT: &str = "approle";
+pub const VAULT_KUBERNETES_MOUNT: &str = "kubernetes";
+pub const VAULT_TRANSIT_MOUNT: &str = "transit";
+pub const KUBERNETES_SERVICE_ACCOUNT_TOKEN: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+// Keycloak client whose client roles grant permissions in the SDK
+pub const API_CLIENT_ID: &str = "demia-api";
+
+// OAuth token exchange (RFC 8693)
+pub const TOKEN_EXCHANGE_GRANT: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+pub const ID_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:id_token";
+
+// Guardian
+pub const GUARDIAN_APPROVE_BUTTON: &str = "Option_0";
+pub const GUARDIAN_REJECT_BUTTON: &str = "Option_1";
+pub const GUARDIAN_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Offline issuer defaults, lifetimes in seconds
+pub const STATIC_ISSUER: &str = "demia-static";
+pub const STATIC_TOKEN_LIFETIME: u64 = 300;
+pub const STATIC_REFRESH_LIFETIME: u64 = 30 * 24 * 60 * 60;
 
 // Timeouts
 pub const API_TIMEOUT: Duration = Duration::from_secs(10);
+pub const JWKS_CACHE_TTL: Duration = Duration::from_secs(600);
+pub const JWKS_MIN_REFETCH: Duration = Duration::from_secs(60);
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
 
 // Buckets
 pub const PROTECTED_BUCKET_PATH: &str = "stronghold-snapshots";
@@ -30,3 +63,7 @@ pub const fn bucket_path(production: bool) -> &'static str {
         false => PROTECTED_BUCKET_PATH_TEST,
     }
 }
+
+// Telemetry
+pub const TELEMETRY_SERVICE_NAME: &str = "demia_sdk";
+pub const TELEMETRY_EXPORT_INTERVAL: Duration = Duration::from_secs(60);
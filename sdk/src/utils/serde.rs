@@ -9,6 +9,25 @@ where
     Ok(opt.unwrap_or_default())
 }
 
+/// Accepts either a single string or a list of strings, as used by the JWT `aud` claim
+pub fn deserialize_string_or_vec<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrVec {
+        String(String),
+        Vec(Vec<String>),
+    }
+
+    Ok(match Option::<StringOrVec>::deserialize(deserializer)? {
+        Some(StringOrVec::String(s)) => vec![s],
+        Some(StringOrVec::Vec(v)) => v,
+        None => vec![],
+    })
+}
+
 pub mod map_serialize {
     use std::{collections::HashMap, fmt};
 